@@ -8,9 +8,8 @@ use std::{
 };
 
 use c2pdf::{
-  ParsedFont, PdfDocument, PdfSaveOptions,
+  ParsedFont, PdfDocument, PdfSaveOptions, RunOptions,
   code_to_pdf::CodeToPdf,
-  dimensions::Dimensions,
   font_loader,
   logging::{Logger, LoggerMessage},
 };
@@ -120,17 +119,20 @@ fn app_view() -> impl IntoView {
           let mut doc = PdfDocument::new("doc");
           let font = ParsedFont::from_bytes(&bytes, 0, &mut vec![]).unwrap();
           let font_id = doc.add_font(&font);
-          let (doc_subset, number_files_processed) = CodeToPdf::run_parallel(
+          let result = CodeToPdf::run_parallel(
             font_id,
             &bytes,
             path_for_thread.clone(),
-            vec![],
-            Dimensions::default(),
-            12.0,
-            None,
-            true,
-            None,
+            RunOptions::default(),
           );
+          let (doc_subset, number_files_processed) = match result {
+            Ok(result) => result,
+            Err(err) => {
+              logger_for_thread.log_message(format!("ERROR: {err}"));
+              logger_for_thread.send_raw_message(LoggerMessage::Complete);
+              return 0;
+            }
+          };
           doc_subset.lock().unwrap().to_document(&mut doc);
           let f = File::create(path_for_thread.join("output.pdf")).unwrap();
           let mut f = std::io::BufWriter::new(f);
@@ -1,30 +1,110 @@
 use argh::FromArgs;
-use c2pdf::code_to_pdf::CodeToPdf;
-use c2pdf::dimensions::Dimensions;
-use c2pdf::font_loader::load_font;
+use c2pdf::batch::{RootSelection, find_roots, output_names};
+use c2pdf::code_to_pdf::{CodeToPdf, RectoStart};
+use c2pdf::cover::Cover;
+use c2pdf::diff::{Diff, DiffSource, DiffStyle};
+use c2pdf::dimensions::{Dimensions, GutterSide, PaperSize};
+use c2pdf::font_loader::{load_font, load_monospace_font};
+use c2pdf::git;
+use c2pdf::header_footer::{HeaderFooter, Slots};
+use c2pdf::imposition::{Imposition, PagesPerSheet};
 use c2pdf::logging::Logger;
+use c2pdf::sort::SortOrder;
+use c2pdf::source::MemorySource;
+use c2pdf::split::{Split, parse_size, part_path};
+use c2pdf::text_manipulation::GridMode;
+use c2pdf::{Resources, RunOptions};
 use core::f32;
-use log::{error, info};
+use log::{error, info, warn};
 use printpdf::*;
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
 use std::fs::File;
-use std::num::NonZeroU8;
-use std::path::PathBuf;
+use std::io::Read;
+use std::num::{NonZeroU8, NonZeroUsize};
+use std::path::{Path, PathBuf};
 use std::time::Instant;
 // This makes `FromArgs` happy
 type StringVec = Vec<String>;
+fn parse_split_by(s: &str) -> Result<Split, String> {
+  Split::parse_by(s)
+}
 fn vec_from_string(s: &str) -> Result<StringVec, String> {
   Ok(s.split(",").map(str::to_string).collect())
 }
 #[derive(FromArgs)]
 /// Generates a PDF from your source code
 struct Arguments {
-  /// the path to walk for files to highlight
+  /// the path to walk for files to highlight, a zip or tar archive to read them from,
+  /// or `-` to read a single file from stdin
   #[argh(positional)]
   walk_path: String,
 
+  /// git revision (such as a tag, branch, or commit) to read files from, instead of the working tree.
+  /// Files are read directly from the repository, without checking the revision out
+  #[argh(option)]
+  rev: Option<String>,
+
+  /// only show the changes to each file since an older version of the project:
+  /// either a git revision, or a directory containing the old version
+  /// (which can be made explicit with a `rev:` or `dir:` prefix).
+  /// The new version is the working tree, or `--rev` if given
+  #[argh(option)]
+  diff_from: Option<DiffSource>,
+
+  /// how changes are shown with `--diff-from`: `unified` (default) or `side-by-side`
+  #[argh(option, default = "DiffStyle::Unified")]
+  diff_style: DiffStyle,
+
+  /// only include files tracked by git (in the index of the repository containing the walk path),
+  /// leaving out untracked files such as scratch files and local `.env` files
+  #[argh(switch)]
+  git_tracked_only: bool,
+
+  /// language to highlight every file as (a syntax name or extension, such as `rust` or `rs`),
+  /// instead of detecting it from each file
+  #[argh(option)]
+  language: Option<String>,
+
+  /// name of the file read from stdin, which is shown in the header
+  /// and used to detect its language if `--language` isn't given (`stdin` by default)
+  #[argh(option, default = "String::from(\"stdin\")")]
+  title: String,
+
   /// path to output PDF to
   #[argh(option, default = "String::from(\"output.pdf\")")]
   out: String,
+
+  /// split the output into PDFs of at most this many pages, named `output-001.pdf` and so on
+  #[argh(option)]
+  split_pages: Option<NonZeroUsize>,
+
+  /// split the output into PDFs of at most roughly this size (such as `50MB`), named `output-001.pdf` and so on
+  #[argh(option, from_str_fn(parse_size))]
+  split_size: Option<u64>,
+
+  /// split the output into a PDF for each `top-level-dir`, named `output-001.pdf` and so on
+  #[argh(option, from_str_fn(parse_split_by))]
+  split_by: Option<Split>,
+
+  /// write pages to the output as soon as they're generated, so memory use stays roughly constant
+  /// for very large repositories. The cover, tree, compact mode, `{pages}`, and imposition aren't supported
+  #[argh(switch)]
+  stream: bool,
+
+  /// batch mode: generate a separate PDF for every directory and archive directly inside the walk path
+  /// (such as one per student submission). `c2pdf batch <path>` is shorthand for this
+  #[argh(switch)]
+  per_dir: bool,
+
+  /// batch mode: generate a separate PDF for every directory (relative to the walk path) matching the glob,
+  /// such as `*/src`
+  #[argh(option)]
+  roots: Option<String>,
+
+  /// directory to write the PDFs to in batch mode, each named after its folder (relative to the walk path) (`.` by default).
+  /// `--report` is also treated as a directory in batch mode
+  #[argh(option, default = "PathBuf::from(\".\")")]
+  out_dir: PathBuf,
   /// comma separated string of globs to exclude.
   /// Default exclusions are `pnpm-lock.yaml` and `Cargo.lock`
   #[argh(
@@ -34,6 +114,29 @@ struct Arguments {
   )]
   exclude: StringVec,
 
+  /// comma separated string of globs to include. If given, only files matching at least one of them are included
+  /// (exclusions still take precedence)
+  #[argh(option, from_str_fn(vec_from_string), default = "vec![]")]
+  include: StringVec,
+
+  /// path of a file listing the files to include (one per line, such as the output of `git diff --name-only`),
+  /// or `-` to read the list from stdin. Files are printed in the order they're listed, instead of walking the path.
+  /// Paths are relative to the path, and are filtered in the same way as when walking it. Can't be used with `--rev`
+  #[argh(option)]
+  files_from: Option<PathBuf>,
+
+  /// order of the files in each directory: `name`, `natural` (numbers in names are compared by value),
+  /// `extension`, `size` (smallest first), `mtime` (least recently modified first), or `manual`.
+  /// Files are always printed before directories. By default, files are left in the order they're read.
+  /// Also applies to `--rev` and `--diff-from`, but can't be used with `--files-from`
+  #[argh(option)]
+  sort: Option<SortOrder>,
+
+  /// path of a file listing the paths to print first (relative to the walk path, one per line), such as
+  /// the README and entry points. Everything else follows by name. Implies `--sort manual`
+  #[argh(option)]
+  sort_file: Option<PathBuf>,
+
   /// whether to include the path at the top of each page
   ///
   /// (defaults to true)
@@ -44,6 +147,39 @@ struct Arguments {
   #[argh(option, default = "String::from(\"Project Code\")")]
   name: String,
 
+  /// add a cover page, showing the name of the PDF, the date, the git commit, and totals for the files
+  #[argh(switch)]
+  cover: bool,
+
+  /// description to show on the cover page (implies `--cover`)
+  #[argh(option)]
+  description: Option<String>,
+
+  /// path of a logo image to show on the cover page (implies `--cover`)
+  #[argh(option)]
+  logo: Option<PathBuf>,
+
+  /// add an overview of every file (like `tree`), with their sizes and the pages they start on
+  #[argh(switch)]
+  tree: bool,
+
+  /// show the language, size, line count, modification time, and last git commit of each file above its contents
+  #[argh(switch)]
+  file_header: bool,
+
+  /// annotate each line with the short hash of the commit which last changed it, and the initials of its author,
+  /// in a gutter on the `left` or `right` of each column
+  #[argh(option)]
+  blame: Option<GutterSide>,
+
+  /// add an appendix with the number of files, and lines of code, comments, and blank lines, for each language
+  #[argh(switch)]
+  stats: bool,
+
+  /// path to write a JSON report of the lines in every file, and the totals for each language, to
+  #[argh(option)]
+  report: Option<PathBuf>,
+
   /// name (will load from system fonts) or path of font to use
   ///
   /// code-to-pdf will use the bundled `Helvetica` font by default, or if the font provided cannot be loaded
@@ -54,7 +190,24 @@ struct Arguments {
   #[argh(option, default = "12.0")]
   font_size: f32,
 
-  /// size of the top margin (20.0 by default)
+  /// shrink the font of files with lines too long to fit on the page, so they don't wrap
+  #[argh(switch)]
+  auto_fit: bool,
+
+  /// smallest font size that `--auto-fit` can shrink files to (6.0 by default)
+  #[argh(option, default = "6.0")]
+  min_font_size: f32,
+
+  /// paper size: a4, a3, a5, letter, legal, tabloid, or WxH with an optional unit (mm, in, or pt).
+  /// Defaults to a4
+  #[argh(option, default = "PaperSize::A4")]
+  paper: PaperSize,
+
+  /// rotate pages into landscape orientation
+  #[argh(switch)]
+  landscape: bool,
+
+  /// size of the top margin, from the top of the page to the body, which the header is in (20.0 by default)
   #[argh(option, default = "20.0")]
   margin_top: f32,
 
@@ -70,10 +223,89 @@ struct Arguments {
   #[argh(option, default = "10.0")]
   margin_right: f32,
 
-  /// text to add to (the top of) every page
+  /// swap the left and right margins on even pages, for duplex printing.
+  /// `--margin-left` becomes the inner (binding) margin, and `--margin-right` the outer margin
+  #[argh(switch)]
+  mirror_margins: bool,
+
+  /// number of columns to split the body of each page into (1 by default)
+  #[argh(option, default = "1")]
+  columns: u32,
+
+  /// space between columns (5.0 by default)
+  #[argh(option, default = "5.0")]
+  column_gap: f32,
+
+  /// start every file on an odd (recto) page, inserting blank pages where needed
+  #[argh(switch)]
+  recto_start: bool,
+
+  /// text to write on blank pages inserted by `--recto-start` (implies `--recto-start`),
+  /// such as "This page intentionally left blank"
+  #[argh(option)]
+  blank_page_text: Option<String>,
+
+  /// text to add to (the top of) every page.
+  /// Shorthand for `--header-right`
   #[argh(option)]
   page_text: Option<String>,
 
+  /// text for the left of the header (`{path}` by default, if `--include-path` is true).
+  /// Supports the template variables `{path}`, `{file_name}`, `{page}`, `{pages}`, `{date}`, `{project}`, `{git_commit}`, and `{lines}`
+  #[argh(option)]
+  header_left: Option<String>,
+
+  /// text for the centre of the header
+  #[argh(option)]
+  header_centre: Option<String>,
+
+  /// text for the right of the header
+  #[argh(option)]
+  header_right: Option<String>,
+
+  /// text for the left of the footer
+  #[argh(option)]
+  footer_left: Option<String>,
+
+  /// text for the centre of the footer
+  #[argh(option)]
+  footer_centre: Option<String>,
+
+  /// text for the right of the footer
+  #[argh(option)]
+  footer_right: Option<String>,
+
+  /// height of the header, which is the part of the top margin directly above the body (15.0 by default).
+  /// Headers which wrap onto more lines than fit push the body down
+  #[argh(option, default = "15.0")]
+  header_height: f32,
+
+  /// height of the footer, between the body and the bottom margin
+  /// (10.0 by default if the footer has any text, otherwise 0.0)
+  #[argh(option)]
+  footer_height: Option<f32>,
+
+  /// draw a rule between the header and the body
+  #[argh(switch)]
+  header_separator: bool,
+
+  /// draw a rule between the body and the footer
+  #[argh(switch)]
+  footer_separator: bool,
+
+  /// put files on the same page as the previous file if they fit in the space left,
+  /// after a separator and their path
+  #[argh(switch)]
+  compact: bool,
+
+  /// number of pages to put on each side of a sheet: 1 (default), 2, or 4
+  #[argh(option, default = "PagesPerSheet::One")]
+  n_up: PagesPerSheet,
+
+  /// order pages for a saddle-stitched booklet, with two pages on each side of a sheet (can't be used with `--n-up`)
+  #[argh(switch)]
+  booklet: bool,
+
   /// number of threads to use for processing
   #[argh(option)]
   threads: Option<NonZeroU8>,
@@ -83,14 +315,107 @@ struct Arguments {
   no_log: bool,
 
   /// image quality: value between 0.0 and 1.0 (defaults to 0.85)
-  #[argh(option, default = "0.85")]
-  image_quality: f32,
+  #[argh(option)]
+  image_quality: Option<f32>,
+
+  /// position every character on a monospace grid, keeping columns aligned.
+  /// Uses the bundled `DejaVu Sans Mono` font unless `--font` is given
+  #[argh(switch)]
+  monospace: bool,
+
+  /// width of each grid cell in point (implies `--monospace`).
+  /// Detected from the font by default
+  #[argh(option)]
+  cell_width: Option<f32>,
+}
+/// Parses the arguments in the same way as [`argh::from_env`].
+///
+/// `argh` treats `-` as an unknown option, so if it's given as the walk path (to read from stdin),
+/// it's moved after `--` to be parsed as a positional argument
+fn parse_args() -> Arguments {
+  let mut args = std::env::args_os()
+    .map(|arg| arg.to_string_lossy().into_owned())
+    .collect::<Vec<_>>();
+  // `c2pdf batch <path>` is shorthand for `c2pdf --per-dir <path>`
+  if args.len() > 2 && args[1] == "batch" {
+    args[1] = "--per-dir".to_string();
+  }
+  let command = Path::new(&args[0])
+    .file_name()
+    .map_or(args[0].clone(), |name| name.to_string_lossy().into_owned());
+  move_stdin_path(&command, &mut args);
+  let strs = args.iter().map(String::as_str).collect::<Vec<_>>();
+  Arguments::from_args(&[&command], &strs[1..]).unwrap_or_else(|early_exit| {
+    std::process::exit(match early_exit.status {
+      Ok(()) => {
+        println!("{}", early_exit.output);
+        0
+      }
+      Err(()) => {
+        eprintln!(
+          "{}\nRun {command} --help for more information.",
+          early_exit.output
+        );
+        1
+      }
+    })
+  })
+}
+/// Moves a `-` given as the walk path after `--`, skipping any `-` which is the value of an option (such as `--files-from -`)
+fn move_stdin_path(command: &str, args: &mut Vec<String>) {
+  let mut i = 1;
+  while i < args.len() && args[i] != "--" {
+    if args[i] == "-" {
+      args.remove(i);
+      args.extend(["--".to_string(), "-".to_string()]);
+      return;
+    }
+    // The value of an option is skipped, whatever it is
+    i += if takes_value(command, &args[i]) { 2 } else { 1 };
+  }
 }
+/// Whether `arg` is an option which takes a value, found by checking whether `argh` complains that it's missing one
+fn takes_value(command: &str, arg: &str) -> bool {
+  arg.starts_with("--")
+    && Arguments::from_args(&[command], &[arg])
+      .is_err_and(|early_exit| early_exit.output.starts_with("No value provided"))
+}
+/// Reads a list of paths (one per line, ignoring blank lines) from the file at `path`, or stdin if it's `-`
+fn read_file_list(path: &Path) -> std::io::Result<Vec<PathBuf>> {
+  let list = if path == Path::new("-") {
+    std::io::read_to_string(std::io::stdin())?
+  } else {
+    std::fs::read_to_string(path)?
+  };
+  Ok(
+    list
+      .lines()
+      .map(str::trim)
+      .filter(|line| !line.is_empty())
+      .map(PathBuf::from)
+      .collect(),
+  )
+}
+thread_local! {
+  /// The font parsed for PDFs, which can't be shared between threads, so is parsed once on each thread instead
+  static PARSED_FONT: std::cell::OnceCell<ParsedFont> = const { std::cell::OnceCell::new() };
+}
+
+/// Loaded once, and shared between every PDF generated in batch mode
+struct Shared {
+  font_bytes: std::sync::Arc<Vec<u8>>,
+  resources: Resources,
+  grid: GridMode,
+  files: Option<Vec<PathBuf>>,
+  sort: Option<SortOrder>,
+  split: Option<Split>,
+}
+
 fn main() {
   // Parse args
-  let args: Arguments = argh::from_env();
+  let args = parse_args();
   // Set up logger
-  let logger = Box::leak(Box::new(Logger::new(crossbeam_channel::unbounded())));
+  let logger: &Logger = Box::leak(Box::new(Logger::new(crossbeam_channel::unbounded())));
   log::set_logger(logger)
     .map(|()| {
       if !args.no_log {
@@ -98,45 +423,378 @@ fn main() {
       }
     })
     .expect("should be able to set logger");
-  let path = args.walk_path;
-  let page_dimensions = Dimensions::new(
-    Mm(210.0),
-    Mm(297.0),
-    Mm(args.margin_top),
-    Mm(args.margin_bottom),
-    Mm(args.margin_left),
-    Mm(args.margin_right),
-  );
-  let mut doc = PdfDocument::new(&args.name);
-  let (font_bytes, font_loaded) = load_font(args.font);
+  if let Some(threads) = args.threads {
+    // Build the global threadpool with the correct number of threads, before anything runs on it
+    rayon::ThreadPoolBuilder::new()
+      .num_threads(u8::from(threads) as usize)
+      .build_global()
+      .unwrap();
+  }
+  let path = PathBuf::from(&args.walk_path);
+  let files = args.files_from.as_ref().map(|list| {
+    read_file_list(list).unwrap_or_else(|err| {
+      error!(
+        "Unable to read the list of files from {}: {err}",
+        list.display()
+      );
+      fail(logger);
+    })
+  });
+  let sort = match (args.sort.clone(), &args.sort_file) {
+    (None | Some(SortOrder::Manual(_)), Some(order)) => match read_file_list(order) {
+      Ok(paths) => Some(SortOrder::Manual(paths)),
+      Err(err) => {
+        error!("Unable to read the order from {}: {err}", order.display());
+        fail(logger);
+      }
+    },
+    (Some(_), Some(_)) => {
+      error!("`--sort-file` can only be used with `--sort manual`");
+      fail(logger);
+    }
+    (sort, None) => sort,
+  };
+  let grid = match (args.monospace, args.cell_width) {
+    (_, Some(width)) => GridMode::Fixed(width),
+    (true, None) => GridMode::Detect,
+    (false, None) => GridMode::Proportional,
+  };
+  let (font_bytes, font_loaded) = if grid == GridMode::Proportional {
+    load_font(args.font.clone())
+  } else {
+    load_monospace_font(args.font.clone())
+  };
   if let c2pdf::font_loader::FontLoaded::FailProvided = font_loaded {
     error!("Unable to load provided font")
   }
-  let font_bytes = &*font_bytes;
-  let font = ParsedFont::from_bytes(font_bytes, 0, &mut vec![]).unwrap();
-  let font_id = doc.add_font(&font);
-  let start = Instant::now();
-  let (doc_subset, processed_file_count) = CodeToPdf::run_parallel(
-    font_id,
+  let split = match (args.split_pages, args.split_size, &args.split_by) {
+    (None, None, None) => None,
+    (Some(pages), None, None) => Some(Split::Pages(pages)),
+    (None, Some(size), None) => Some(Split::Size(size)),
+    (None, None, Some(split)) => Some(split.clone()),
+    _ => {
+      error!("Only one of `--split-pages`, `--split-size`, and `--split-by` can be used");
+      fail(logger);
+    }
+  };
+  if args.stream && split.is_some() {
+    error!("`--stream` can't be used with `--split-pages`, `--split-size`, or `--split-by`");
+    fail(logger);
+  }
+  if args.stream && args.image_quality.is_some() {
+    error!("`--image-quality` can't be used with `--stream`, as streamed images aren't re-encoded");
+    fail(logger);
+  }
+  if args.booklet && args.n_up != PagesPerSheet::One {
+    error!(
+      "`--booklet` always puts two pages on each side of a sheet, so it can't be used with `--n-up`"
+    );
+    fail(logger);
+  }
+  if args.stream && (args.n_up != PagesPerSheet::One || args.booklet) {
+    warn!("`--n-up` and `--booklet` aren't supported when streaming, so pages aren't imposed");
+  }
+  let resources = Resources::load(&font_bytes, args.font_size, grid);
+  let shared = Shared {
     font_bytes,
-    PathBuf::from(path),
-    args.exclude,
-    page_dimensions,
-    args.font_size,
-    args.page_text,
-    args.include_path,
-    args.threads,
+    resources,
+    grid,
+    files,
+    sort,
+    split,
+  };
+  let selection = match (&args.roots, args.per_dir) {
+    (Some(glob), _) => Some(RootSelection::Glob(glob.clone())),
+    (None, true) => Some(RootSelection::PerDir),
+    (None, false) => None,
+  };
+  let Some(selection) = selection else {
+    if !generate(
+      &args,
+      path,
+      Path::new(&args.out),
+      args.report.clone(),
+      &shared,
+    ) {
+      fail(logger);
+    }
+    return;
+  };
+  let start = Instant::now();
+  let roots = find_roots(&path, &selection).unwrap_or_else(|err| {
+    error!("Unable to read {}: {err}", path.display());
+    fail(logger);
+  });
+  let names = output_names(&path, &roots).unwrap_or_else(|err| {
+    error!("{err}");
+    fail(logger);
+  });
+  for dir in std::iter::once(&args.out_dir).chain(&args.report) {
+    if let Err(err) = std::fs::create_dir_all(dir) {
+      error!("Unable to create {}: {err}", dir.display());
+    }
+  }
+  let failed = roots
+    .par_iter()
+    .zip(names.par_iter())
+    .filter(|(root, name)| {
+      let out = args.out_dir.join(name);
+      let report = args
+        .report
+        .as_ref()
+        .map(|dir| dir.join(name).with_extension("json"));
+      !generate(&args, root.to_path_buf(), &out, report, &shared)
+    })
+    .count();
+  info!(
+    "Generated {} PDFs in {} seconds",
+    roots.len() - failed,
+    start.elapsed().as_secs_f32()
   );
-  doc_subset.lock().unwrap().to_document(&mut doc);
-  let num_pages = doc.pages.len();
+  if failed > 0 {
+    error!("Unable to generate {failed} of {} PDFs", roots.len());
+    fail(logger);
+  }
+}
+
+/// Exits with a failure status, once everything already logged has been printed
+fn fail(logger: &Logger) -> ! {
+  _ = logger.clone().finish();
+  std::process::exit(1)
+}
+
+/// Generates the PDF of the files in `path`, and writes it to `out`.
+///
+/// Returns whether the PDF was generated
+fn generate(
+  args: &Arguments,
+  root: PathBuf,
+  out: &Path,
+  report: Option<PathBuf>,
+  shared: &Shared,
+) -> bool {
+  let batch = args.per_dir || args.roots.is_some();
+  // Each PDF in batch mode is named after its folder
+  let name = match batch {
+    true => out.file_stem().map_or(args.name.clone(), |stem| {
+      stem.to_string_lossy().into_owned()
+    }),
+    false => args.name.clone(),
+  };
+  let revision_commit = match &args.rev {
+    Some(rev) => {
+      let Some(commit) = git::revision_commit(&root, rev) else {
+        error!("Unable to find revision `{rev}` in {}", root.display());
+        return false;
+      };
+      Some(commit)
+    }
+    None => None,
+  };
+  let header_footer = HeaderFooter {
+    header: Slots {
+      left: args
+        .header_left
+        .clone()
+        .or_else(|| args.include_path.then(|| "{path}".to_string())),
+      centre: args.header_centre.clone(),
+      right: args.header_right.clone().or(args.page_text.clone()),
+    },
+    footer: Slots {
+      left: args.footer_left.clone(),
+      centre: args.footer_centre.clone(),
+      // Show which commit the files were read from
+      right: args.footer_right.clone().or_else(|| {
+        args
+          .rev
+          .is_some()
+          .then(|| "Commit {git_commit}".to_string())
+      }),
+    },
+    header_separator: args.header_separator,
+    footer_separator: args.footer_separator,
+    ..HeaderFooter::new(name.clone(), &root)
+  };
+  let header_footer = match &revision_commit {
+    Some(commit) => HeaderFooter {
+      git_commit: Some(commit.clone()),
+      ..header_footer
+    },
+    None => header_footer,
+  };
+  let footer_height = args
+    .footer_height
+    .unwrap_or(if header_footer.footer.is_empty() {
+      0.0
+    } else {
+      10.0
+    });
+  let mut page_dimensions = Dimensions::from_paper_size(args.paper);
+  if args.landscape {
+    page_dimensions = page_dimensions.landscape();
+  }
+  let page_dimensions = Dimensions {
+    header_height: Mm(args.header_height),
+    footer_height: Mm(footer_height),
+    mirror_margins: args.mirror_margins,
+    columns: args.columns.max(1),
+    column_gap: Mm(args.column_gap),
+    ..Dimensions::new(
+      page_dimensions.width,
+      page_dimensions.height,
+      Mm(args.margin_top),
+      Mm(args.margin_bottom),
+      Mm(args.margin_left),
+      Mm(args.margin_right),
+    )
+  };
+  let recto_start = match (args.recto_start, &args.blank_page_text) {
+    (_, Some(text)) => RectoStart::Notice(text.clone()),
+    (true, None) => RectoStart::Blank,
+    (false, None) => RectoStart::Disabled,
+  };
+  let cover = (args.cover || args.description.is_some() || args.logo.is_some()).then(|| {
+    let mut cover = Cover {
+      description: args.description.clone(),
+      ..Cover::new(name.clone(), &root)
+    };
+    if let Some(commit) = &revision_commit {
+      cover.git_commit = Some(commit.clone());
+      cover.git_branch = args.rev.clone();
+    }
+    match &args.logo {
+      Some(logo) => cover.clone().with_logo(logo).unwrap_or_else(|err| {
+        error!("Unable to load logo: {err}");
+        cover
+      }),
+      None => cover,
+    }
+  });
+  let mut doc = PdfDocument::new(&name);
+  let font_bytes = &*shared.font_bytes;
+  let font_id = PARSED_FONT.with(|font| {
+    doc.add_font(font.get_or_init(|| ParsedFont::from_bytes(font_bytes, 0, &mut vec![]).unwrap()))
+  });
+  let start = Instant::now();
+  let options = RunOptions {
+    exclusions: args.exclude.clone(),
+    page_dimensions,
+    font_size: args.font_size,
+    header_footer,
+    threads: args.threads,
+    grid: shared.grid,
+    recto_start,
+    compact: args.compact,
+    auto_fit: args.auto_fit.then_some(args.min_font_size),
+    cover,
+    tree: args.tree,
+    stats: args.stats,
+    file_header: args.file_header,
+    revision: args.rev.clone(),
+    diff: args.diff_from.clone().map(|from| Diff {
+      from,
+      style: args.diff_style,
+    }),
+    inclusions: args.include.clone(),
+    files: shared.files.clone(),
+    sort: shared.sort.clone(),
+    git_tracked_only: args.git_tracked_only,
+    blame: args.blame,
+    language: args.language.clone(),
+    resources: Some(shared.resources.clone()),
+    stream: args.stream.then(|| out.to_path_buf()),
+  };
+  let result = if root == Path::new("-") {
+    let mut contents = vec![];
+    if let Err(err) = std::io::stdin().read_to_end(&mut contents) {
+      error!("Unable to read from stdin: {err}");
+    }
+    let source = MemorySource::new(vec![(PathBuf::from(&args.title), contents)]);
+    CodeToPdf::run_source(font_id, font_bytes, source, options)
+  } else {
+    CodeToPdf::run_parallel(font_id, font_bytes, root.clone(), options)
+  };
+  let (doc_subset, processed_file_count) = match result {
+    Ok(result) => result,
+    Err(err) => {
+      error!("ERROR: {err}");
+      return false;
+    }
+  };
+  if let Some(report) = &report
+    && let Err(err) = std::fs::write(report, doc_subset.lock().unwrap().report())
+  {
+    error!("Unable to write report: {err}");
+  }
+  if args.stream {
+    // The pages have already been written
+    info!("Wrote {}", out.display());
+    info!(
+      "Processed {} files in {} seconds",
+      processed_file_count,
+      start.elapsed().as_secs_f32()
+    );
+    return true;
+  }
+  let mut docs = match &shared.split {
+    Some(split) => doc_subset
+      .lock()
+      .unwrap()
+      .to_documents(&mut doc, split, &root),
+    None => {
+      doc_subset.lock().unwrap().to_document(&mut doc);
+      vec![doc]
+    }
+  };
+  let num_pages = docs.iter().map(|doc| doc.pages.len()).sum::<usize>();
+  let imposition = Imposition {
+    pages_per_sheet: args.n_up,
+    booklet: args.booklet,
+  };
   // let before_write = Instant::now();
-  let f = File::create(args.out).unwrap();
+  for (i, doc) in docs.iter_mut().enumerate() {
+    imposition.impose_document(doc);
+    let out = match shared.split {
+      Some(_) => &part_path(out, i + 1),
+      None => out,
+    };
+    if !save(doc, out, args.image_quality.unwrap_or(0.85)) {
+      return false;
+    }
+    if shared.split.is_some() {
+      info!("Wrote {}", out.display());
+    }
+  }
+  // println!("Written in {}", before_write.elapsed().as_micros());
+  if batch {
+    info!("Wrote {}", out.display());
+  } else {
+    info!("Done!");
+  }
+  info!(
+    "Processed {} files and generated {} pages in {} seconds",
+    processed_file_count,
+    num_pages,
+    start.elapsed().as_secs_f32()
+  );
+  true
+}
+
+/// Saves `doc` to `out`, returning whether it could be created
+fn save(doc: &PdfDocument, out: &Path, image_quality: f32) -> bool {
+  let f = match File::create(out) {
+    Ok(f) => f,
+    Err(err) => {
+      error!("Unable to create {}: {err}", out.display());
+      return false;
+    }
+  };
   let mut f = std::io::BufWriter::new(f);
   doc.save_writer(
     &mut f,
     &PdfSaveOptions {
       image_optimization: Some(ImageOptimizationOptions {
-        quality: Some(args.image_quality),
+        quality: Some(image_quality),
         max_image_size: None,
         ..Default::default()
       }),
@@ -144,12 +802,34 @@ fn main() {
     },
     &mut vec![],
   );
-  // println!("Written in {}", before_write.elapsed().as_micros());
-  info!("Done!");
-  info!(
-    "Processed {} files and generated {} pages in {} seconds",
-    processed_file_count,
-    num_pages,
-    start.elapsed().as_secs_f32()
-  )
+  true
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn moving_stdin_path() {
+    let moved = |args: &str| {
+      let mut args = args.split(' ').map(String::from).collect::<Vec<_>>();
+      move_stdin_path("c2pdf", &mut args);
+      args.join(" ")
+    };
+    assert_eq!(moved("c2pdf - --out x.pdf"), "c2pdf --out x.pdf -- -");
+    assert_eq!(
+      moved("c2pdf --title a.rs - --git-tracked-only"),
+      "c2pdf --title a.rs --git-tracked-only -- -"
+    );
+    // `-` as the value of an option is left alone
+    assert_eq!(
+      moved("c2pdf src --sort-file - --out x.pdf"),
+      "c2pdf src --sort-file - --out x.pdf"
+    );
+    assert_eq!(
+      moved("c2pdf --header-centre - -"),
+      "c2pdf --header-centre - -- -"
+    );
+    assert_eq!(moved("c2pdf -- -"), "c2pdf -- -");
+  }
 }
@@ -86,20 +86,25 @@ impl PageData {
           contents,
         })
       }
-      // Custom text
-      3 => {
+      // Custom text in the other header and footer slots, between the path and the contents
+      n if n > 2 => {
         let path = Self::parse_path(&sections[0]);
-        let contents = Self::parse_contents(&sections[2]);
+        let custom_text = sections[1..n - 1]
+          .iter()
+          .map(|section| Self::parse_contents(section))
+          .collect::<Vec<_>>()
+          .join("\n");
+        let contents = Self::parse_contents(&sections[n - 1]);
         Ok(Self {
           path,
-          custom_text: None,
+          custom_text: Some(custom_text),
           contents,
         })
       }
       _ => {
         dbg!(&sections);
         Err(format!(
-          "Malformed Page. Sections length should be 2 or 3, but it was {}",
+          "Malformed Page. Sections length should be at least 2, but it was {}",
           sections.len()
         ))
       }
@@ -156,7 +161,10 @@ fn main() {
   let mut pages_iterator = doc
     .pages
     .iter()
-    .map(|p| PageData::parse_from_sections(parse_sections(p)))
+    .map(parse_sections)
+    // Pages without both a path and contents (such as blank pages) don't belong to a file
+    .filter(|sections| sections.len() >= 2)
+    .map(PageData::parse_from_sections)
     .peekable();
   while let Some(file_data_result) = next_file_data(&mut pages_iterator) {
     let file_data = match file_data_result {
@@ -0,0 +1,530 @@
+//! Reads the files in zip and tar archives, so they can be printed without being extracted first
+//!
+//! Reading archives always fails if the `archive` feature is disabled
+
+use std::{
+  cmp::Ordering,
+  collections::HashMap,
+  io,
+  path::{Component, Path, PathBuf},
+};
+#[cfg(feature = "archive")]
+use std::{
+  fs::File,
+  io::{Read, Seek, SeekFrom},
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use ignore::{
+  Match,
+  gitignore::{Gitignore, GitignoreBuilder},
+  overrides::Override,
+};
+
+use crate::{
+  helpers::format_size,
+  sort::{EntryMetadata, SortOrder},
+  source::{Source, SourceFile},
+};
+
+/// Format of an archive
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveKind {
+  /// `.zip`
+  Zip,
+  /// `.tar`
+  Tar,
+  /// `.tar.gz` or `.tgz`
+  TarGz,
+}
+impl ArchiveKind {
+  /// Detects the format of the archive at `path` from its extension, or returns `None` if it isn't an archive
+  pub fn from_path(path: &Path) -> Option<Self> {
+    let name = path.file_name()?.to_string_lossy().to_ascii_lowercase();
+    if name.ends_with(".zip") {
+      Some(Self::Zip)
+    } else if name.ends_with(".tar") {
+      Some(Self::Tar)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+      Some(Self::TarGz)
+    } else {
+      None
+    }
+  }
+}
+
+/// Maximum total size of the files in an archive once they're decompressed (1 GiB), so that archives which
+/// expand to far more than their own size (such as zip bombs) are rejected instead of filling up memory or disk space
+pub const MAX_SIZE: u64 = 1 << 30;
+
+/// A file in an archive, which is read when it's needed
+struct Entry {
+  /// Path of the file inside the archive
+  name: PathBuf,
+  /// Size and modification time, from the header of the entry
+  metadata: EntryMetadata,
+  /// Index of the entry in a zip archive, or the position of its contents in a tar archive
+  #[cfg(feature = "archive")]
+  position: u64,
+}
+
+/// An open archive, which the contents of each entry are read from
+enum Reader {
+  #[cfg(feature = "archive")]
+  Zip(zip::ZipArchive<File>),
+  /// A tar archive, which has been decompressed into a temporary file if needed
+  #[cfg(feature = "archive")]
+  Tar {
+    file: File,
+    /// Kept so that the temporary file is deleted along with the reader
+    _temp: Option<TempFile>,
+  },
+}
+impl Reader {
+  /// Reads the contents of `entry`, failing if there are more of them than its header says
+  fn read(&mut self, entry: &Entry) -> io::Result<Vec<u8>> {
+    #[cfg(not(feature = "archive"))]
+    {
+      _ = entry;
+      match *self {}
+    }
+    #[cfg(feature = "archive")]
+    {
+      let size = entry.metadata.size;
+      let mut contents = vec![];
+      match self {
+        Self::Zip(archive) => {
+          let file = archive
+            .by_index(entry.position as usize)
+            .map_err(io::Error::other)?;
+          // One more byte than expected is read, to check that the header is right
+          file.take(size + 1).read_to_end(&mut contents)?;
+        }
+        Self::Tar { file, .. } => {
+          file.seek(SeekFrom::Start(entry.position))?;
+          file.take(size).read_to_end(&mut contents)?;
+        }
+      }
+      if contents.len() as u64 != size {
+        return Err(io::Error::other(format!(
+          "The size of {} doesn't match its header",
+          entry.name.display()
+        )));
+      }
+      Ok(contents)
+    }
+  }
+}
+
+/// A temporary file, which is deleted when this is dropped
+#[cfg(feature = "archive")]
+struct TempFile(PathBuf);
+#[cfg(feature = "archive")]
+impl Drop for TempFile {
+  fn drop(&mut self) {
+    _ = std::fs::remove_file(&self.0);
+  }
+}
+
+/// The files in a zip or tar archive, in the same order as the walker would give the extracted files.
+///
+/// Paths are joined onto the path of the archive (such as `submission.zip/src/main.rs`).
+/// Only the list of files (and any ignore files) is read up front, and the contents of each file are read when it's needed.
+/// `.tar.gz` archives can't be read out of order, so they're decompressed into a temporary file first
+pub struct ArchiveSource {
+  path: PathBuf,
+  reader: Reader,
+  entries: std::vec::IntoIter<Entry>,
+}
+impl ArchiveSource {
+  /// Lists the files in the archive at `path`.
+  ///
+  /// Paths ignored by `overrides` or by `.gitignore` and `.ignore` files in the archive are skipped,
+  /// as are hidden files, symbolic links, and paths outside the archive (such as `../file`).
+  /// Entries in each directory are ordered by `sort` (by name if not given), using the sizes and modification times
+  /// in the archive. Fails if the files add up to more than [`MAX_SIZE`]
+  pub fn new(
+    path: &Path,
+    kind: ArchiveKind,
+    overrides: &Override,
+    sort: Option<&SortOrder>,
+  ) -> Result<Self, String> {
+    let (mut reader, entries) = list_entries(path, kind).map_err(|err| err.to_string())?;
+    let total_size = entries
+      .iter()
+      .map(|entry| entry.metadata.size)
+      .fold(0, u64::saturating_add);
+    if total_size > MAX_SIZE {
+      return Err(format!(
+        "The files in the archive add up to {}, which is more than the limit of {}",
+        format_size(total_size),
+        format_size(MAX_SIZE)
+      ));
+    }
+    let mut ignores = vec![];
+    for entry in &entries {
+      if entry.name.ends_with(".gitignore") || entry.name.ends_with(".ignore") {
+        let contents = reader.read(entry).map_err(|err| err.to_string())?;
+        ignores.push(ignore_file(path, &entry.name, &contents));
+      }
+    }
+    let mut files = entries
+      .into_iter()
+      .filter(|entry| {
+        let hidden = entry
+          .name
+          .components()
+          .any(|component| component.as_os_str().to_string_lossy().starts_with('.'));
+        !hidden && !is_ignored(path, &path.join(&entry.name), overrides, &ignores)
+      })
+      .collect::<Vec<_>>();
+    let metadata = files
+      .iter()
+      .map(|entry| (path.join(&entry.name), entry.metadata))
+      .collect::<HashMap<_, _>>();
+    let sort = sort.cloned().unwrap_or(SortOrder::Name).with_root(path);
+    files.sort_by(|a, b| {
+      walk_order(&path.join(&a.name), &path.join(&b.name), |a, b| {
+        // Directories don't have any metadata in the archive
+        sort.compare_with(a, b, |path| metadata.get(path).copied().unwrap_or_default())
+      })
+    });
+    Ok(Self {
+      path: path.to_path_buf(),
+      reader,
+      entries: files.into_iter(),
+    })
+  }
+}
+impl Source for ArchiveSource {
+  fn next_file(&mut self) -> Option<io::Result<SourceFile>> {
+    let entry = self.entries.next()?;
+    let contents = self.reader.read(&entry);
+    Some(contents.map(|contents| SourceFile::from_bytes(self.path.join(&entry.name), contents)))
+  }
+}
+
+/// Opens the archive at `path`, and lists the regular files in it from their headers
+fn list_entries(path: &Path, kind: ArchiveKind) -> io::Result<(Reader, Vec<Entry>)> {
+  #[cfg(not(feature = "archive"))]
+  {
+    _ = (path, kind);
+    Err(io::Error::other(
+      "Reading archives requires the `archive` feature",
+    ))
+  }
+  #[cfg(feature = "archive")]
+  {
+    let file = File::open(path)?;
+    let mut entries = vec![];
+    match kind {
+      ArchiveKind::Zip => {
+        let mut archive = zip::ZipArchive::new(file).map_err(io::Error::other)?;
+        for i in 0..archive.len() {
+          // Only the header is read, without decompressing the file
+          let entry = archive.by_index_raw(i).map_err(io::Error::other)?;
+          // Entries with paths outside the archive (such as `../file`) are skipped
+          let Some(name) = entry.enclosed_name() else {
+            continue;
+          };
+          if !entry.is_file() || entry.is_symlink() {
+            continue;
+          }
+          entries.push(Entry {
+            name,
+            metadata: EntryMetadata {
+              size: entry.size(),
+              modified: entry.last_modified().and_then(zip_time),
+            },
+            position: i as u64,
+          });
+        }
+        Ok((Reader::Zip(archive), entries))
+      }
+      ArchiveKind::Tar | ArchiveKind::TarGz => {
+        let (mut file, temp) = match kind {
+          ArchiveKind::TarGz => decompress(file)?,
+          _ => (file, None),
+        };
+        let mut archive = tar::Archive::new(&mut file);
+        for entry in archive.entries_with_seek()? {
+          let entry = entry?;
+          if !entry.header().entry_type().is_file() {
+            continue;
+          }
+          let Some(name) = enclosed(&entry.path()?) else {
+            continue;
+          };
+          let modified = entry.header().mtime().ok();
+          entries.push(Entry {
+            name,
+            metadata: EntryMetadata {
+              size: entry.size(),
+              modified: modified.map(|seconds| UNIX_EPOCH + Duration::from_secs(seconds)),
+            },
+            position: entry.raw_file_position(),
+          });
+        }
+        Ok((Reader::Tar { file, _temp: temp }, entries))
+      }
+    }
+  }
+}
+
+/// Decompresses a gzipped file into a temporary file, failing if it's larger than [`MAX_SIZE`]
+#[cfg(feature = "archive")]
+fn decompress(file: File) -> io::Result<(File, Option<TempFile>)> {
+  static COUNT: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+  let count = COUNT.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+  let temp = TempFile(
+    std::env::temp_dir().join(format!("c2pdf-archive-{}-{count}.tar", std::process::id())),
+  );
+  let mut decompressed = std::fs::OpenOptions::new()
+    .read(true)
+    .write(true)
+    .create_new(true)
+    .open(&temp.0)?;
+  let decoder = flate2::read::GzDecoder::new(io::BufReader::new(file));
+  let size = io::copy(&mut decoder.take(MAX_SIZE + 1), &mut decompressed)?;
+  if size > MAX_SIZE {
+    return Err(io::Error::other(format!(
+      "The archive is larger than the limit of {} once it's decompressed",
+      format_size(MAX_SIZE)
+    )));
+  }
+  decompressed.seek(SeekFrom::Start(0))?;
+  Ok((decompressed, Some(temp)))
+}
+
+/// Converts the modification time of a zip entry (which doesn't have a time zone, so is assumed to be UTC)
+#[cfg(feature = "archive")]
+fn zip_time(time: zip::DateTime) -> Option<SystemTime> {
+  // Days since 1970-01-01, from https://howardhinnant.github.io/date_algorithms.html#days_from_civil
+  let (month, day) = (i64::from(time.month()), i64::from(time.day()));
+  let year = i64::from(time.year()) - i64::from(month <= 2);
+  let era = year.div_euclid(400);
+  let year_of_era = year - era * 400;
+  let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+  let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+  let days = era * 146_097 + day_of_era - 719_468;
+  let seconds = days * 86_400
+    + i64::from(time.hour()) * 3_600
+    + i64::from(time.minute()) * 60
+    + i64::from(time.second());
+  u64::try_from(seconds)
+    .ok()
+    .map(|seconds| UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+/// Removes `.` components from `path`, or returns `None` if it could refer to something outside the archive
+#[cfg(feature = "archive")]
+fn enclosed(path: &Path) -> Option<PathBuf> {
+  let mut enclosed = PathBuf::new();
+  for component in path.components() {
+    match component {
+      Component::Normal(part) => enclosed.push(part),
+      Component::CurDir => {}
+      _ => return None,
+    }
+  }
+  (!enclosed.as_os_str().is_empty()).then_some(enclosed)
+}
+
+/// Parses an ignore file at `entry` in the archive at `archive`
+fn ignore_file(archive: &Path, entry: &Path, contents: &[u8]) -> Gitignore {
+  let dir = archive.join(entry.parent().unwrap_or(Path::new("")));
+  let mut builder = GitignoreBuilder::new(dir);
+  for line in String::from_utf8_lossy(contents).lines() {
+    // Invalid globs are skipped, in the same way as the walker
+    _ = builder.add_line(None, line);
+  }
+  builder.build().unwrap_or_else(|_| Gitignore::empty())
+}
+
+/// Returns whether `file` (or a directory containing it) in the archive at `archive` is ignored
+fn is_ignored(archive: &Path, file: &Path, overrides: &Override, ignores: &[Gitignore]) -> bool {
+  let mut directories = file
+    .ancestors()
+    .skip(1)
+    .take_while(|dir| dir.starts_with(archive) && *dir != archive);
+  let overridden = overrides.matched(file, false).is_ignore()
+    || directories.any(|dir| overrides.matched(dir, true).is_ignore());
+  if overridden {
+    return true;
+  }
+  // The ignore file closest to the file takes precedence
+  let mut ignores = ignores
+    .iter()
+    .filter(|ignore| file.starts_with(ignore.path()))
+    .collect::<Vec<_>>();
+  ignores.sort_by_key(|ignore| std::cmp::Reverse(ignore.path().components().count()));
+  for ignore in ignores {
+    match ignore.matched_path_or_any_parents(file, false) {
+      Match::Ignore(_) => return true,
+      Match::Whitelist(_) => return false,
+      Match::None => {}
+    }
+  }
+  false
+}
+
+/// Orders paths in the same way as the walker: depth-first, with the files in each directory before its subdirectories,
+/// which are ordered by `compare`
+fn walk_order(a: &Path, b: &Path, compare: impl Fn(&Path, &Path) -> Ordering) -> Ordering {
+  let a_parts = a.components().collect::<Vec<_>>();
+  let b_parts = b.components().collect::<Vec<_>>();
+  let common = a_parts
+    .iter()
+    .zip(&b_parts)
+    .take_while(|(a, b)| a == b)
+    .count();
+  // The entries containing each path, in the first directory which they don't have in common
+  let entry = |parts: &[Component]| {
+    parts[..=common.min(parts.len() - 1)]
+      .iter()
+      .collect::<PathBuf>()
+  };
+  let (a_entry, b_entry) = (entry(&a_parts), entry(&b_parts));
+  let a_is_dir = a_parts.len() > common + 1;
+  let b_is_dir = b_parts.len() > common + 1;
+  a_is_dir
+    .cmp(&b_is_dir)
+    .then_with(|| compare(&a_entry, &b_entry))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn detecting_archives() {
+    assert_eq!(
+      ArchiveKind::from_path(Path::new("a/submission.ZIP")),
+      Some(ArchiveKind::Zip)
+    );
+    assert_eq!(
+      ArchiveKind::from_path(Path::new("project.tar.gz")),
+      Some(ArchiveKind::TarGz)
+    );
+    assert_eq!(ArchiveKind::from_path(Path::new("src")), None);
+  }
+  #[test]
+  fn ordering_entries() {
+    let mut paths = [
+      "a.zip/src/main.rs",
+      "a.zip/README.md",
+      "a.zip/src/a/b.rs",
+      "a.zip/Cargo.toml",
+    ]
+    .map(PathBuf::from);
+    paths.sort_by(|a, b| walk_order(a, b, |a, b| SortOrder::Name.compare(a, b)));
+    assert_eq!(
+      paths,
+      [
+        "a.zip/Cargo.toml",
+        "a.zip/README.md",
+        "a.zip/src/main.rs",
+        "a.zip/src/a/b.rs"
+      ]
+      .map(PathBuf::from)
+    );
+  }
+  /// Files in the test archives, including ones which should be skipped
+  #[cfg(feature = "archive")]
+  const FILES: [(&str, &str); 7] = [
+    ("src/main.rs", "fn main() {}"),
+    ("src/lib.rs", "// the library"),
+    ("notes.txt", "notes"),
+    (".hidden.rs", "hidden"),
+    ("target/out.rs", "built"),
+    (".gitignore", "target/"),
+    ("../outside.rs", "outside"),
+  ];
+  /// Lists the files read from the archive at `path` which match `inclusions` but not `exclusions`, in `sort` order
+  #[cfg(feature = "archive")]
+  fn archive_files(
+    path: &Path,
+    inclusions: &[&str],
+    exclusions: &[&str],
+    sort: Option<&SortOrder>,
+  ) -> Vec<String> {
+    let to_strings = |globs: &[&str]| {
+      globs
+        .iter()
+        .map(|glob| glob.to_string())
+        .collect::<Vec<_>>()
+    };
+    let overrides =
+      crate::build_overrides(path, &to_strings(exclusions), &to_strings(inclusions)).unwrap();
+    let kind = ArchiveKind::from_path(path).unwrap();
+    let source = ArchiveSource::new(path, kind, &overrides, sort).unwrap();
+    crate::source::read_all(source)
+      .unwrap()
+      .into_iter()
+      .map(|(file, _)| {
+        let name = file.strip_prefix(path).unwrap();
+        name.to_string_lossy().into_owned()
+      })
+      .collect()
+  }
+  #[cfg(feature = "archive")]
+  fn check_archive(path: &Path) {
+    assert_eq!(
+      archive_files(path, &[], &[], None),
+      ["notes.txt", "src/lib.rs", "src/main.rs"]
+    );
+    assert_eq!(
+      archive_files(path, &["*.rs"], &[], None),
+      ["src/lib.rs", "src/main.rs"]
+    );
+    assert_eq!(
+      archive_files(path, &[], &["main.rs"], None),
+      ["notes.txt", "src/lib.rs"]
+    );
+    // Sizes come from the headers in the archive
+    assert_eq!(
+      archive_files(path, &[], &[], Some(&SortOrder::Size)),
+      ["notes.txt", "src/main.rs", "src/lib.rs"]
+    );
+  }
+  #[test]
+  #[cfg(feature = "archive")]
+  fn reading_zip_archives() {
+    use std::io::Write;
+    let dir = std::env::temp_dir().join(format!("c2pdf-zip-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("submission.zip");
+    let mut writer = zip::ZipWriter::new(File::create(&path).unwrap());
+    for (name, contents) in FILES {
+      writer
+        .start_file(name, zip::write::SimpleFileOptions::default())
+        .unwrap();
+      writer.write_all(contents.as_bytes()).unwrap();
+    }
+    writer.finish().unwrap();
+    check_archive(&path);
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+  #[test]
+  #[cfg(feature = "archive")]
+  fn reading_tar_archives() {
+    let dir = std::env::temp_dir().join(format!("c2pdf-tar-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("submission.tar.gz");
+    let encoder =
+      flate2::write::GzEncoder::new(File::create(&path).unwrap(), flate2::Compression::default());
+    let mut builder = tar::Builder::new(encoder);
+    for (name, contents) in FILES {
+      let mut header = tar::Header::new_gnu();
+      // The name is set directly, as `set_path` refuses paths containing `..`
+      header.as_gnu_mut().unwrap().name[..name.len()].copy_from_slice(name.as_bytes());
+      header.set_size(contents.len() as u64);
+      header.set_mode(0o644);
+      header.set_cksum();
+      builder.append(&header, contents.as_bytes()).unwrap();
+    }
+    builder.into_inner().unwrap().finish().unwrap();
+    check_archive(&path);
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+}
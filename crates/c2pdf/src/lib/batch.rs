@@ -0,0 +1,175 @@
+//! Finds the roots to generate separate PDFs for in batch mode (such as one per student submission)
+
+use std::{
+  collections::HashMap,
+  fs, io,
+  path::{Path, PathBuf},
+};
+
+use ignore::WalkBuilder;
+
+use crate::{archive::ArchiveKind, sort::natural_cmp};
+
+/// How the roots are found below the batch directory
+#[derive(Debug, Clone, PartialEq)]
+pub enum RootSelection {
+  /// Every directory and archive directly inside the batch directory
+  PerDir,
+  /// Every directory (relative to the batch directory) matching the glob.
+  /// Directories inside a matching directory aren't matched
+  Glob(String),
+}
+
+/// Returns the roots below `dir`, ordered naturally by path. Hidden entries are skipped
+pub fn find_roots(dir: &Path, selection: &RootSelection) -> io::Result<Vec<PathBuf>> {
+  let mut roots = match selection {
+    RootSelection::PerDir => fs::read_dir(dir)?
+      .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+      .filter(|path| !is_hidden(path))
+      .filter(|path| path.is_dir() || ArchiveKind::from_path(path).is_some())
+      .collect::<Vec<_>>(),
+    RootSelection::Glob(glob) => {
+      let mut roots: Vec<PathBuf> = vec![];
+      let walker = WalkBuilder::new(dir)
+        .standard_filters(false)
+        .hidden(true)
+        .sort_by_file_path(|a, b| a.cmp(b))
+        .build();
+      for entry in walker.flatten() {
+        let path = entry.path();
+        if !entry.file_type().is_some_and(|f| f.is_dir())
+          || roots.iter().any(|root| path.starts_with(root))
+        {
+          continue;
+        }
+        let Ok(relative) = path.strip_prefix(dir) else {
+          continue;
+        };
+        if !relative.as_os_str().is_empty()
+          && fast_glob::glob_match(glob, relative.to_string_lossy().replace('\\', "/"))
+        {
+          roots.push(path.to_path_buf());
+        }
+      }
+      roots
+    }
+  };
+  roots.sort_by(|a, b| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()));
+  Ok(roots)
+}
+
+/// Returns whether the name of `path` starts with a `.`
+fn is_hidden(path: &Path) -> bool {
+  path
+    .file_name()
+    .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+}
+
+/// Name of the PDF generated for `root` in the batch directory `dir`: its path relative to `dir`
+/// with each separator replaced by `-` (such as `alice-src.pdf`), without the extension if it's an archive
+pub fn output_name(dir: &Path, root: &Path) -> String {
+  let relative = root.strip_prefix(dir).unwrap_or(root);
+  let name = relative
+    .components()
+    .map(|component| component.as_os_str().to_string_lossy())
+    .collect::<Vec<_>>()
+    .join("-");
+  let lower = name.to_ascii_lowercase();
+  let stem = match ArchiveKind::from_path(root) {
+    Some(_) => [".tar.gz", ".tgz", ".tar", ".zip"]
+      .iter()
+      .find(|ext| lower.ends_with(*ext))
+      .map_or(name.as_str(), |ext| &name[..name.len() - ext.len()]),
+    None => &name,
+  };
+  match stem {
+    "" => "output.pdf".to_string(),
+    stem => format!("{stem}.pdf"),
+  }
+}
+
+/// Names of the PDFs generated for each of the `roots` in `dir` (see [`output_name`]).
+///
+/// Fails if any roots would share a name (such as `alice/` and `alice.zip`, or `a-b/` and `a/b`), so that one PDF
+/// doesn't overwrite another. Names are compared ignoring case, as they are on some file systems
+pub fn output_names(dir: &Path, roots: &[PathBuf]) -> Result<Vec<String>, String> {
+  let names = roots
+    .iter()
+    .map(|root| output_name(dir, root))
+    .collect::<Vec<_>>();
+  let mut roots_by_name: HashMap<String, Vec<&Path>> = HashMap::new();
+  for (name, root) in names.iter().zip(roots) {
+    roots_by_name
+      .entry(name.to_lowercase())
+      .or_default()
+      .push(root);
+  }
+  let mut clashes = names
+    .iter()
+    .filter_map(|name| {
+      let roots = roots_by_name.remove(&name.to_lowercase())?;
+      (roots.len() > 1).then(|| {
+        let roots = roots
+          .iter()
+          .map(|root| format!("`{}`", root.display()))
+          .collect::<Vec<_>>();
+        format!(
+          "{} would all be written to the same PDF, {name}",
+          roots.join(", ")
+        )
+      })
+    })
+    .peekable();
+  match clashes.peek() {
+    Some(_) => Err(clashes.collect::<Vec<_>>().join("\n")),
+    None => Ok(names),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn naming_outputs() {
+    let dir = Path::new("submissions");
+    assert_eq!(output_name(dir, &dir.join("alice")), "alice.pdf");
+    assert_eq!(output_name(dir, &dir.join("bob.tar.gz")), "bob.pdf");
+    assert_eq!(output_name(dir, &dir.join("Carol.ZIP")), "Carol.pdf");
+    assert_eq!(output_name(dir, &dir.join("dave/src")), "dave-src.pdf");
+  }
+  #[test]
+  fn detecting_clashing_names() {
+    let dir = Path::new("submissions");
+    let roots = ["alice", "bob", "Alice.zip"].map(|root| dir.join(root));
+    assert_eq!(
+      output_names(dir, &roots),
+      Err(format!(
+        "`{}`, `{}` would all be written to the same PDF, alice.pdf",
+        roots[0].display(),
+        roots[2].display()
+      ))
+    );
+    let roots = ["a-b", "a/c"].map(|root| dir.join(root));
+    assert_eq!(
+      output_names(dir, &roots),
+      Ok(vec!["a-b.pdf".to_string(), "a-c.pdf".to_string()])
+    );
+  }
+  #[test]
+  fn finding_roots() {
+    let dir = std::env::temp_dir().join(format!("c2pdf-batch-{}", std::process::id()));
+    for sub in ["student10/src", "student2/src", ".hidden", "student2/tests"] {
+      fs::create_dir_all(dir.join(sub)).unwrap();
+    }
+    fs::write(dir.join("notes.txt"), "").unwrap();
+    let per_dir = find_roots(&dir, &RootSelection::PerDir).unwrap();
+    assert_eq!(per_dir, vec![dir.join("student2"), dir.join("student10")]);
+    let glob = find_roots(&dir, &RootSelection::Glob("*/src".to_string())).unwrap();
+    assert_eq!(
+      glob,
+      vec![dir.join("student2/src"), dir.join("student10/src")]
+    );
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -1,32 +1,43 @@
 //! Contains [`HighlighterConfig`] and [`CodeToPdf`] structs
 
 use std::{
+  borrow::Cow,
   cmp::Ordering,
-  collections::BTreeMap,
+  collections::{BTreeMap, HashMap, HashSet},
   ffi::OsStr,
-  fs,
-  io::BufRead,
+  fs::File,
+  io::{BufRead, BufReader},
   mem,
-  path::Path,
+  path::{Path, PathBuf},
   sync::{Arc, Mutex},
 };
 
 use ignore::Walk;
-use log::error;
+use log::{error, warn};
 use printpdf::{
-  FontId, Op, PdfDocument, PdfPage, Pt, Px, RawImage, TextItem, XObject, XObjectId,
-  XObjectRotation, XObjectTransform, color,
+  CurTransMat, FontId, Mm, Op, PdfDocument, PdfPage, Point, Pt, Px, RawImage, Rect, TextItem,
+  TextMatrix, XObject, XObjectId, XObjectRotation, XObjectTransform, color,
 };
 use syntect::{
-  easy::HighlightFile,
-  highlighting::{Color, Style, Theme},
-  parsing::SyntaxSet,
+  highlighting::{Color, HighlightIterator, HighlightState, Highlighter, Style, Theme},
+  parsing::{ParseState, ScopeStack, SyntaxSet},
 };
 
 use crate::{
-  dimensions::Dimensions,
-  helpers::{ProcessedText, init_page},
-  text_manipulation::TextWrapper,
+  cover::{Cover, CoverTotals},
+  diff::{DiffLine, DiffRow, DiffStyle, FileDiff, diff_rows, is_binary},
+  dimensions::{Dimensions, GutterSide},
+  file_header::FileHeader,
+  git::{self, LastCommits},
+  header_footer::{
+    HeaderFooter, TemplateVariables, render_notice, render_separator, separator_line_count,
+  },
+  helpers::{format_size, init_page},
+  source::{self, Source, WalkSource},
+  split::Split,
+  stats::{self, LineClassifier, LineCounts, LineKind},
+  text_manipulation::{TextWrapper, WRAP_SLACK},
+  tree::Tree,
 };
 
 /// Configuration struct for the highlighter ([`syntect`])
@@ -47,14 +58,249 @@ impl HighlighterConfig {
     }
   }
 }
+/// Information about a file that has been processed
+#[derive(Debug, Clone)]
+pub struct FileInfo {
+  /// Path of the file
+  pub path: PathBuf,
+  /// Number of lines in the file
+  pub lines: usize,
+  /// Size of the file in bytes
+  pub size: u64,
+  /// Name of the syntax used to highlight the file, or `None` if it isn't text (such as an image)
+  pub language: Option<String>,
+  /// Number of lines of code, comments, and blank lines
+  pub line_counts: LineCounts,
+}
+/// Reads a file line by line, keeping track of the state needed to highlight and classify each line
+struct FileHighlighter<'a> {
+  reader: &'a mut dyn BufRead,
+  /// Number of bytes read so far
+  size: u64,
+  parse_state: ParseState,
+  highlighter: Highlighter<'a>,
+  highlight_state: HighlightState,
+  classifier: LineClassifier,
+  /// Name of the syntax selected for the file
+  language: String,
+}
+impl<'a> FileHighlighter<'a> {
+  /// Initialises a new [`FileHighlighter`] for the file at `path`, which is read from `reader`.
+  ///
+  /// The syntax is selected by `language` (a name or extension, such as `rust` or `rs`) if given.
+  /// Otherwise it's selected in the same way as [`SyntaxSet::find_syntax_for_file`],
+  /// but the first line is taken from what's been buffered by `reader`, as the file might not be on disk
+  fn new(
+    path: &Path,
+    reader: &'a mut dyn BufRead,
+    language: Option<&str>,
+    syntax_set: &SyntaxSet,
+    theme: &'a Theme,
+  ) -> Self {
+    let file_name = path.file_name().and_then(OsStr::to_str).unwrap_or("");
+    let extension = path.extension().and_then(OsStr::to_str).unwrap_or("");
+    let language = language.and_then(|language| {
+      let syntax = syntax_set.find_syntax_by_token(language);
+      if syntax.is_none() {
+        warn!("Unknown language `{language}`, so detecting it from the file instead");
+      }
+      syntax
+    });
+    let syntax = language
+      .or_else(|| syntax_set.find_syntax_by_extension(file_name))
+      .or_else(|| syntax_set.find_syntax_by_extension(extension))
+      .or_else(|| {
+        let buffered = reader.fill_buf().unwrap_or_default();
+        let first_line = buffered
+          .split(|&byte| byte == b'\n')
+          .next()
+          .unwrap_or_default();
+        syntax_set.find_syntax_by_first_line(&String::from_utf8_lossy(first_line))
+      })
+      .unwrap_or_else(|| syntax_set.find_syntax_plain_text());
+    let highlighter = Highlighter::new(theme);
+    let highlight_state = HighlightState::new(&highlighter, ScopeStack::new());
+    Self {
+      reader,
+      size: 0,
+      parse_state: ParseState::new(syntax),
+      highlighter,
+      highlight_state,
+      classifier: LineClassifier::default(),
+      language: syntax.name.clone(),
+    }
+  }
+  /// Reads the next line (including its line ending) into `line`, returning `false` at the end of the file.
+  /// Reading stops at anything which isn't UTF-8
+  fn read_line(&mut self, line: &mut String) -> bool {
+    let length = self.reader.read_line(line).unwrap_or(0);
+    self.size += length as u64;
+    length > 0
+  }
+  /// Highlights every line of the file up front, so that lines can be shown out of order (in diff mode).
+  ///
+  /// Returns the colour and text of each region of each line, without line endings
+  fn highlight_all_lines(
+    &mut self,
+    highlighter_config: &HighlighterConfig,
+  ) -> (Vec<HighlightedLine>, LineCounts) {
+    let mut lines = vec![];
+    let mut line_counts = LineCounts::default();
+    let mut line = String::new();
+    while self.read_line(&mut line) {
+      let ops = (line.len() < highlighter_config.max_line_len_to_highlight)
+        .then(|| {
+          self
+            .parse_state
+            .parse_line(&line, &highlighter_config.syntax_set)
+            .ok()
+        })
+        .flatten();
+      line_counts.add(match &ops {
+        Some(ops) => self.classifier.classify(&line, ops),
+        // Lines which aren't parsed are assumed to be code
+        None if line.trim().is_empty() => LineKind::Blank,
+        None => LineKind::Code,
+      });
+      let mut regions = match ops {
+        Some(ops) => {
+          HighlightIterator::new(&mut self.highlight_state, &ops, &line, &self.highlighter)
+            .map(|(style, text)| (style.foreground, text.to_string()))
+            .collect()
+        }
+        None => vec![(Color::BLACK, line.clone())],
+      };
+      for (_, text) in &mut regions {
+        text.truncate(text.trim_end_matches(['\n', '\r']).len());
+      }
+      lines.push(regions);
+      line.clear();
+    }
+    (lines, line_counts)
+  }
+}
+/// Colour and text of each region of a highlighted line
+type HighlightedLine = Vec<(Color, String)>;
+/// Part of a row in diff mode, drawn in its own area of the page
+struct DiffCell<'a> {
+  /// Left edge of the area
+  left: Pt,
+  /// Width of the area
+  width: Pt,
+  /// Colour to fill the area with
+  background: Option<Color>,
+  /// Text (such as line numbers) in front of the line, right-aligned at the given positions
+  gutter: Vec<(Pt, String)>,
+  /// Left edge of the text of the line
+  text_left: Pt,
+  /// Text of the line
+  line: &'a [(Color, String)],
+}
+/// Whether every file starts on an odd (recto) page, for duplex printing
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum RectoStart {
+  /// Files start on the next page
+  #[default]
+  Disabled,
+  /// Blank pages are inserted after files which end on an odd page
+  Blank,
+  /// Like [`RectoStart::Blank`], but the inserted pages contain the given text
+  Notice(String),
+}
+/// A page generated for a file
+struct GeneratedPage {
+  page: PdfPage,
+  /// Index of the file from the walker
+  index: usize,
+  /// Number of lines of the body which have been used,
+  /// or `None` if the page can't be shared with other files (such as pages containing images)
+  used_lines: Option<u32>,
+  /// Indices of other files which have been moved onto this page (in compact mode)
+  other_files: Vec<usize>,
+}
+/// Pages generated for a single file, taken out of a [`DocumentSubset`] so they can be written as soon as they're ready
+pub(crate) struct FilePages {
+  index: usize,
+  pages: Vec<GeneratedPage>,
+  /// Images used on the pages
+  pub(crate) xobjects: BTreeMap<XObjectId, XObject>,
+}
+/// Everything needed to add headers and footers to pages
+struct Decorations {
+  header_footer: HeaderFooter,
+  page_dimensions: Dimensions,
+  font_id: FontId,
+  text_wrapper: TextWrapper,
+  /// Height of the header for each file, as it's the same on every page of the file
+  header_heights: HashMap<PathBuf, Mm>,
+}
+/// Stands in for numbers which aren't known until the pages are laid out (such as `{page}`) when measuring headers.
+/// This is the widest value expected, so a header is never measured as shorter than it ends up
+const WIDEST_NUMBER: usize = 9_999_999;
 /// Subset of `PdfDocument`. Created as some types within `PdfDocument` weren't sync so it couldn't be used with `rayon`
 #[derive(Default)]
 pub struct DocumentSubset {
   x_object_map: BTreeMap<XObjectId, XObject>,
   // font_map: Arc<Mutex<BTreeMap<FontId, ParsedFont>>>,
-  pages: Vec<(PdfPage, usize)>,
+  pages: Vec<GeneratedPage>,
+  // Processed files, keyed by their index from the walker
+  files: BTreeMap<usize, FileInfo>,
+  decorations: Option<Decorations>,
+  recto_start: RectoStart,
+  compact: bool,
+  cover: Option<Cover>,
+  tree: bool,
+  stats: bool,
 }
 impl DocumentSubset {
+  /// Initialises a new [`DocumentSubset`], which adds the given header and footer to every page
+  pub fn new(
+    header_footer: HeaderFooter,
+    page_dimensions: Dimensions,
+    font_id: FontId,
+    text_wrapper: TextWrapper,
+  ) -> Self {
+    Self {
+      decorations: Some(Decorations {
+        header_footer,
+        page_dimensions,
+        font_id,
+        text_wrapper,
+        header_heights: HashMap::new(),
+      }),
+      ..Default::default()
+    }
+  }
+  /// Sets whether blank pages are inserted so that every file starts on an odd page
+  pub fn with_recto_start(mut self, recto_start: RectoStart) -> Self {
+    self.recto_start = recto_start;
+    self
+  }
+  /// Sets whether files which fit in the space left on the previous page are put on that page,
+  /// after a separator and their path
+  pub fn with_compact(mut self, compact: bool) -> Self {
+    self.compact = compact;
+    self
+  }
+  /// Sets the cover page, which is added before all the files
+  pub fn with_cover(mut self, cover: Option<Cover>) -> Self {
+    self.cover = cover;
+    self
+  }
+  /// Sets whether an overview of every file is added before the files (after the cover)
+  pub fn with_tree(mut self, tree: bool) -> Self {
+    self.tree = tree;
+    self
+  }
+  /// Sets whether an appendix with statistics for each language is added after the files
+  pub fn with_stats(mut self, stats: bool) -> Self {
+    self.stats = stats;
+    self
+  }
+  /// Returns a report of the lines in every file, and the totals for each language, as JSON
+  pub fn report(&self) -> String {
+    stats::report(&self.files).to_string()
+  }
   /// Add an image
   pub fn add_image(&mut self, image: &RawImage) -> XObjectId {
     let id = XObjectId::new();
@@ -63,16 +309,386 @@ impl DocumentSubset {
       .insert(id.clone(), XObject::Image(image.clone()));
     id
   }
-  /// Append everything from the `DocumentSubset` into the actual PdfDocument
+  /// Records information about the file at the given walker `index`
+  pub fn add_file(&mut self, index: usize, info: FileInfo) {
+    self.files.insert(index, info);
+  }
+  /// Takes the pages generated for the file at walker `index`, along with the images on them
+  pub(crate) fn take_file(&mut self, index: usize) -> FilePages {
+    let (pages, others): (Vec<_>, Vec<_>) = mem::take(&mut self.pages)
+      .into_iter()
+      .partition(|generated| generated.index == index);
+    self.pages = others;
+    let xobjects = pages
+      .iter()
+      .flat_map(|generated| generated.page.get_xobject_ids())
+      .filter_map(|id| Some((id.clone(), self.x_object_map.remove(&id)?)))
+      .collect();
+    FilePages {
+      index,
+      pages,
+      xobjects,
+    }
+  }
+  /// Lays out the pages of `file` on their own, starting at `first_page` (starting at 1), for streaming.
+  /// The total number of pages isn't known, so `{pages}` is left empty.
+  ///
+  /// Returns the pages (after a blank page, if needed for the file to start on an odd page),
+  /// and the path and page number for the bookmark of the file, if it has any pages
+  pub(crate) fn lay_out_file(
+    &mut self,
+    file: &mut FilePages,
+    first_page: usize,
+  ) -> (Vec<PdfPage>, Option<(String, usize)>) {
+    if file.pages.is_empty() {
+      return (vec![], None);
+    }
+    let mut pages = vec![];
+    if self.recto_start != RectoStart::Disabled && first_page.is_multiple_of(2) {
+      pages.push(self.blank_page(first_page));
+    }
+    let start = first_page + pages.len();
+    for generated in mem::take(&mut file.pages) {
+      let page_number = first_page + pages.len();
+      pages.push(self.decorate(generated.page, page_number, None, file.index));
+    }
+    let bookmark = self
+      .files
+      .get(&file.index)
+      .map(|info| (info.path.display().to_string(), start));
+    (pages, bookmark)
+  }
+  /// Moves files which only take up a single page onto the previous page if they fit there (in compact mode).
+  ///
+  /// Pages are laid out independently (and in parallel), so each moved file is shifted down the page,
+  /// below a separator and its path
+  fn compact_pages(&mut self, pages: Vec<GeneratedPage>) -> Vec<GeneratedPage> {
+    if !self.compact {
+      return pages;
+    }
+    let Some(decorations) = &self.decorations else {
+      return pages;
+    };
+    let font_size = decorations.text_wrapper.font_size();
+    let line_height = Pt(font_size * 1.2);
+    let mut compacted: Vec<GeneratedPage> = Vec::with_capacity(pages.len());
+    let mut pages = pages.into_iter().peekable();
+    while let Some(mut generated) = pages.next() {
+      let single_page = compacted
+        .last()
+        .is_none_or(|previous| previous.index != generated.index)
+        && pages
+          .peek()
+          .is_none_or(|next| next.index != generated.index);
+      // The body of each file starts below its own header, so can start at different heights
+      let previous_dimensions = compacted
+        .last()
+        .and_then(|previous| self.file_dimensions(&self.file_path(previous.index)));
+      let path = self.file_path(generated.index);
+      let dimensions = self.file_dimensions(&path);
+      if single_page
+        && let Some(previous) = compacted.last_mut()
+        && let (Some(previous_used_lines), Some(used_lines)) =
+          (previous.used_lines, generated.used_lines)
+        && let (Some(previous_dimensions), Some(dimensions)) = (previous_dimensions, dimensions)
+        && let Some(decorations) = &mut self.decorations
+        // The separator takes a line, followed by the path (which may wrap)
+        && let separator_lines = separator_line_count(
+          &previous_dimensions,
+          &mut decorations.text_wrapper,
+          &path.display().to_string(),
+        )
+        && previous_used_lines + separator_lines + used_lines
+          <= previous_dimensions.max_line_count(font_size)
+      {
+        let first_line = previous_used_lines + separator_lines;
+        let body_top = previous_dimensions.body_top().into_pt();
+        render_separator(
+          &mut previous.page.ops,
+          &previous_dimensions,
+          &decorations.font_id,
+          &mut decorations.text_wrapper,
+          &path.display().to_string(),
+          body_top - line_height * previous_used_lines as f32,
+        );
+        let shift = body_top - line_height * first_line as f32 - dimensions.body_top().into_pt();
+        previous.page.ops.extend_from_slice(&[
+          Op::SaveGraphicsState,
+          Op::SetTransformationMatrix {
+            matrix: CurTransMat::Translate(Pt(0.0), shift),
+          },
+        ]);
+        previous.page.ops.append(&mut generated.page.ops);
+        previous.page.ops.push(Op::RestoreGraphicsState);
+        previous.used_lines = Some(first_line + used_lines);
+        previous.other_files.push(generated.index);
+        continue;
+      }
+      compacted.push(generated);
+    }
+    compacted
+  }
+  /// Generates the cover page, adding the logo (if any) to `doc`
+  fn cover_page(&self, cover: &Cover, doc: &mut PdfDocument) -> PdfPage {
+    let page_dimensions = self.page_dimensions();
+    let mut ops = vec![];
+    if let Some(decorations) = &self.decorations {
+      let logo_id = cover.logo.as_ref().map(|logo| {
+        let id = XObjectId::new();
+        doc
+          .resources
+          .xobjects
+          .map
+          .insert(id.clone(), XObject::Image(logo.clone()));
+        id
+      });
+      let totals = CoverTotals {
+        files: self.files.len(),
+        lines: self.files.values().map(|file| file.lines).sum(),
+      };
+      cover.render(
+        &mut ops,
+        &page_dimensions,
+        &decorations.font_id,
+        &decorations.text_wrapper,
+        logo_id,
+        &totals,
+      );
+    }
+    PdfPage::new(page_dimensions.width, page_dimensions.height, ops)
+  }
+  /// Generates a blank page, to be inserted as the given page number (starting at 1)
+  fn blank_page(&mut self, page_number: usize) -> PdfPage {
+    let page_dimensions = self.page_dimensions().for_page(page_number);
+    let mut ops = vec![];
+    if let (RectoStart::Notice(text), Some(decorations)) =
+      (&self.recto_start, &mut self.decorations)
+    {
+      render_notice(
+        &mut ops,
+        &page_dimensions,
+        &decorations.font_id,
+        &mut decorations.text_wrapper,
+        text,
+      );
+    }
+    PdfPage::new(page_dimensions.width, page_dimensions.height, ops)
+  }
+  /// Returns the path of the file at walker `index`, or an empty path if it hasn't been recorded
+  fn file_path(&self, index: usize) -> PathBuf {
+    self
+      .files
+      .get(&index)
+      .map(|file| file.path.clone())
+      .unwrap_or_default()
+  }
+  /// Returns the dimensions of the pages of the file at `path`, leaving enough room above the body for its header.
+  /// Values which aren't known until the pages are laid out (such as `{page}`) are assumed to be wide
+  pub(crate) fn file_dimensions(&mut self, path: &Path) -> Option<Dimensions> {
+    let decorations = self.decorations.as_mut()?;
+    let height = match decorations.header_heights.get(path) {
+      Some(height) => *height,
+      None => {
+        let variables = TemplateVariables {
+          path,
+          page: WIDEST_NUMBER,
+          pages: Some(WIDEST_NUMBER),
+          lines: WIDEST_NUMBER,
+        };
+        let height = decorations.header_footer.header_height(
+          &decorations.page_dimensions,
+          &mut decorations.text_wrapper,
+          &variables,
+        );
+        decorations
+          .header_heights
+          .insert(path.to_path_buf(), height);
+        height
+      }
+    };
+    Some(
+      decorations
+        .page_dimensions
+        .clone()
+        .with_header_space(height),
+    )
+  }
+  /// Returns the dimensions of pages
+  fn page_dimensions(&self) -> Dimensions {
+    self
+      .decorations
+      .as_ref()
+      .map(|decorations| decorations.page_dimensions.clone())
+      .unwrap_or_default()
+  }
+  /// Append everything from the `DocumentSubset` into the actual PdfDocument, with a bookmark for each file
   pub fn to_document(&mut self, doc: &mut PdfDocument) {
+    let (pages, file_pages) = self.lay_out(doc);
+    self.fill_part(doc, pages, &file_pages, 1);
+  }
+  /// Splits everything from the `DocumentSubset` into several documents, each based on `doc`
+  /// (so using the same fonts), with a bookmark for each file in it. `root` is the path that was walked.
+  ///
+  /// Pages are numbered across all the documents
+  pub fn to_documents(
+    &mut self,
+    doc: &mut PdfDocument,
+    split: &Split,
+    root: &Path,
+  ) -> Vec<PdfDocument> {
+    let (pages, file_pages) = self.lay_out(doc);
+    let xobjects = mem::take(&mut doc.resources.xobjects.map);
+    let part_overhead = estimate_part_overhead(doc, &pages);
+    let page_sizes = pages
+      .iter()
+      .enumerate()
+      .map(|(i, (page, index))| {
+        // The first page of each file has a bookmark
+        let bookmark = match index {
+          Some(_) if i == 0 || pages[i - 1].1 != *index => BOOKMARK_SIZE,
+          _ => 0,
+        };
+        estimate_size(page, &xobjects) + bookmark
+      })
+      .collect::<Vec<_>>();
+    let page_dirs = pages
+      .iter()
+      .map(|(_, index)| {
+        let file = self.files.get(index.as_ref()?)?;
+        let relative = file.path.strip_prefix(root).unwrap_or(&file.path);
+        // Files directly inside the root are grouped under an empty path
+        let mut components = relative.components();
+        let first = components.next();
+        Some(match (first, components.next()) {
+          (Some(first), Some(_)) => PathBuf::from(first.as_os_str()),
+          _ => PathBuf::new(),
+        })
+      })
+      .collect::<Vec<_>>();
+    let mut pages = pages.into_iter();
+    let mut first_page = 1;
+    split
+      .part_lengths(&page_sizes, &page_dirs, part_overhead)
+      .into_iter()
+      .map(|length| {
+        let mut part = doc.clone();
+        let part_pages = pages.by_ref().take(length).collect::<Vec<_>>();
+        // Only the images used in this part are included
+        for (page, _) in &part_pages {
+          for id in page.get_xobject_ids() {
+            if let Some(xobject) = xobjects.get(&id) {
+              part.resources.xobjects.map.insert(id, xobject.clone());
+            }
+          }
+        }
+        self.fill_part(&mut part, part_pages, &file_pages, first_page);
+        first_page += length;
+        part
+      })
+      .collect()
+  }
+  /// Sets the pages of `doc` to `pages`, which start at `first_page` (starting at 1) of the whole output,
+  /// and adds a bookmark for each file which starts on (or continues onto) them
+  fn fill_part(
+    &self,
+    doc: &mut PdfDocument,
+    pages: IndexedPages,
+    file_pages: &HashMap<usize, usize>,
+    first_page: usize,
+  ) {
+    let page_range = first_page..first_page + pages.len();
+    let mut bookmarks = file_pages
+      .iter()
+      .filter(|(_, page)| page_range.contains(page))
+      .map(|(&index, &page)| (page - first_page + 1, index))
+      .collect::<Vec<_>>();
+    // A file which started in an earlier part is bookmarked on the first page it's on
+    if let Some((_, Some(index))) = pages.first()
+      && file_pages.get(index).is_some_and(|&page| page < first_page)
+    {
+      bookmarks.push((1, *index));
+    }
+    bookmarks.sort();
+    for (page, index) in bookmarks {
+      if let Some(file) = self.files.get(&index) {
+        doc.add_bookmark(&file.path.display().to_string(), page);
+      }
+    }
+    doc.pages = pages.into_iter().map(|(page, _)| page).collect();
+  }
+  /// Generates the appendix with statistics for each language (if enabled), starting at `first_page` (starting at 1)
+  pub(crate) fn stats_pages(&mut self, first_page: usize) -> Vec<PdfPage> {
+    let (true, Some(decorations)) = (self.stats, &mut self.decorations) else {
+      return vec![];
+    };
+    let stats_pages = stats::render(
+      &decorations.page_dimensions,
+      &decorations.font_id,
+      &mut decorations.text_wrapper,
+      &self.files,
+    );
+    let page_dimensions = &decorations.page_dimensions;
+    stats_pages
+      .into_iter()
+      .enumerate()
+      .map(|(i, ops)| {
+        let offset = page_dimensions.horizontal_offset(first_page + i);
+        PdfPage::new(
+          page_dimensions.width,
+          page_dimensions.height,
+          shift_horizontally(ops, offset),
+        )
+      })
+      .collect()
+  }
+  /// Adds the header and footer to `page`, which is the given page number (starting at 1) of the file at `index`.
+  /// `page_count` is the total number of pages, if it's known
+  fn decorate(
+    &mut self,
+    mut page: PdfPage,
+    page_number: usize,
+    page_count: Option<usize>,
+    index: usize,
+  ) -> PdfPage {
+    let Some(page_dimensions) = self.file_dimensions(&self.file_path(index)) else {
+      return page;
+    };
+    let page_dimensions = page_dimensions.for_page(page_number);
+    let Some(decorations) = &mut self.decorations else {
+      return page;
+    };
+    let file = self.files.get(&index);
+    let variables = TemplateVariables {
+      path: file.map_or(Path::new(""), |file| &file.path),
+      page: page_number,
+      pages: page_count,
+      lines: file.map_or(0, |file| file.lines),
+    };
+    let mut ops = vec![];
+    decorations.header_footer.render(
+      &mut ops,
+      &page_dimensions,
+      &decorations.font_id,
+      &mut decorations.text_wrapper,
+      &variables,
+    );
+    // Page parity is only known now, so move the body into place for mirrored margins
+    let offset = decorations.page_dimensions.horizontal_offset(page_number);
+    ops.append(&mut shift_horizontally(mem::take(&mut page.ops), offset));
+    page.ops = ops;
+    page
+  }
+  /// Lays out every page, putting any images into `doc`.
+  /// Returns each page with the index of the file it's part of, and the page number each file starts on
+  fn lay_out(&mut self, doc: &mut PdfDocument) -> (IndexedPages, HashMap<usize, usize>) {
     let x_obj_map = mem::take(&mut self.x_object_map);
     doc.resources.xobjects.map = x_obj_map;
     let mut pages = mem::take(&mut self.pages);
     // Sort into order from the walker
     // This brings back determinism :)
     pages.sort_by(|a, b| {
-      let ia = a.1;
-      let ib = b.1;
+      let ia = a.index;
+      let ib = b.index;
       if ia > ib {
         Ordering::Greater
       } else if ia < ib {
@@ -81,9 +697,219 @@ impl DocumentSubset {
         Ordering::Equal
       }
     });
-    doc.pages = pages.into_iter().map(|f| f.0).collect();
+    let pages = self.compact_pages(pages);
+    // Pages which aren't part of a file (such as the cover) don't have an index
+    let mut laid_out: Vec<(PdfPage, Option<usize>)> = Vec::with_capacity(pages.len());
+    if let Some(cover) = &self.cover {
+      laid_out.push((self.cover_page(cover, doc), None));
+    }
+    // The number of pages the tree takes up is known now, but its contents aren't until the files are laid out
+    let tree = self.tree.then(|| Tree::new(&self.files));
+    let tree_start = laid_out.len();
+    if let (Some(tree), Some(decorations)) = (&tree, &self.decorations) {
+      let font_size = decorations.text_wrapper.font_size();
+      let max_line_count = decorations.page_dimensions.max_line_count(font_size);
+      let page_dimensions = &decorations.page_dimensions;
+      for _ in 0..tree.page_count(max_line_count) {
+        let page = PdfPage::new(page_dimensions.width, page_dimensions.height, vec![]);
+        laid_out.push((page, None));
+      }
+    }
+    // Page each file starts on
+    let mut file_pages = HashMap::new();
+    // Insert blank pages after files (or the pages before them) that end on an odd page,
+    // so that every file starts on an odd page
+    for GeneratedPage {
+      page,
+      index,
+      other_files,
+      ..
+    } in pages
+    {
+      let starts_file = laid_out
+        .last()
+        .is_some_and(|(_, last)| *last != Some(index));
+      if self.recto_start != RectoStart::Disabled && starts_file && laid_out.len() % 2 == 1 {
+        let blank_page = self.blank_page(laid_out.len() + 1);
+        laid_out.push((blank_page, None));
+      }
+      let page_number = laid_out.len() + 1;
+      for index in other_files.into_iter().chain([index]) {
+        file_pages.entry(index).or_insert(page_number);
+      }
+      laid_out.push((page, Some(index)));
+    }
+    if let (Some(tree), Some(decorations)) = (&tree, &mut self.decorations) {
+      let tree_pages = tree.render(
+        &decorations.page_dimensions,
+        &decorations.font_id,
+        &mut decorations.text_wrapper,
+        &self.files,
+        &file_pages,
+      );
+      for (i, ops) in tree_pages.into_iter().enumerate() {
+        let offset = decorations
+          .page_dimensions
+          .horizontal_offset(tree_start + i + 1);
+        laid_out[tree_start + i].0.ops = shift_horizontally(ops, offset);
+      }
+    }
+    let stats_pages = self.stats_pages(laid_out.len() + 1);
+    laid_out.extend(stats_pages.into_iter().map(|page| (page, None)));
+    let page_count = laid_out.len();
+    let pages = laid_out
+      .into_iter()
+      .enumerate()
+      .map(|(i, (page, index))| match index {
+        Some(index) => (
+          self.decorate(page, i + 1, Some(page_count), index),
+          Some(index),
+        ),
+        // Pages which aren't part of a file don't have a header or footer
+        None => (page, None),
+      })
+      .collect();
+    (pages, file_pages)
   }
 }
+/// Pages, each with the index of the file it's part of (if any)
+type IndexedPages = Vec<(PdfPage, Option<usize>)>;
+/// Bytes taken up by each page in a saved PDF besides its contents (its page object, content stream header,
+/// and entry in the cross-reference table)
+const PAGE_OVERHEAD: u64 = 300;
+/// Bytes taken up by each part besides its pages and fonts (the catalog, document info, metadata, and trailer)
+const PART_OVERHEAD: u64 = 4 * 1024;
+/// Bytes taken up by each embedded font besides its glyphs (such as the tables kept when it's subset)
+const FONT_OVERHEAD: u64 = 16 * 1024;
+/// Bytes taken up by each bookmark in the outline
+const BOOKMARK_SIZE: u64 = 200;
+
+/// Estimates the size of `page` in a saved PDF, in bytes, in the same way as its operations are serialised.
+/// This overestimates, as streams are compressed when saved
+fn estimate_size(page: &PdfPage, xobjects: &BTreeMap<XObjectId, XObject>) -> u64 {
+  let ops = page.ops.iter().map(estimate_op_size).sum::<u64>();
+  let images = page
+    .get_xobject_ids()
+    .iter()
+    .filter_map(|id| match xobjects.get(id)? {
+      XObject::Image(image) => Some((image.width * image.height * 4) as u64),
+      _ => None,
+    })
+    .sum::<u64>();
+  PAGE_OVERHEAD + ops + images
+}
+/// Estimates the size of `op` in a content stream, in bytes. Numbers are assumed to take up 12 bytes each
+fn estimate_op_size(op: &Op) -> u64 {
+  const NUMBER: u64 = 12;
+  // Each point is drawn with an operator such as `x y l`
+  let points = |points: usize| points as u64 * (2 * NUMBER + 3);
+  match op {
+    // Each character is written as its glyph ID, in 4 hexadecimal digits, in a `[<...> offset ...] TJ` array
+    Op::WriteText { items, .. } => {
+      8 + items
+        .iter()
+        .map(|item| match item {
+          TextItem::Text(text) => 2 + 4 * text.chars().count() as u64,
+          TextItem::Offset(_) => NUMBER + 1,
+        })
+        .sum::<u64>()
+    }
+    Op::DrawPolygon { polygon } => {
+      4 + polygon
+        .rings
+        .iter()
+        .map(|ring| points(ring.points.len()) + 2)
+        .sum::<u64>()
+    }
+    Op::DrawLine { line } => 4 + points(line.points.len()),
+    Op::SetTextMatrix { .. } | Op::SetTransformationMatrix { .. } => 6 * (NUMBER + 1) + 3,
+    Op::SetFillColor { .. } | Op::SetOutlineColor { .. } => 4 * (NUMBER + 1) + 3,
+    Op::SetTextCursor { .. } => 2 * (NUMBER + 1) + 3,
+    Op::UseXobject { .. } => 64,
+    _ => NUMBER + 8,
+  }
+}
+/// Estimates the size of everything in each part of a split PDF other than its pages (in bytes),
+/// which is mostly the fonts. Fonts are subset when saved, so each is estimated from the share of its glyphs
+/// which are used anywhere in `pages`, along with a fixed overhead
+fn estimate_part_overhead(doc: &PdfDocument, pages: &IndexedPages) -> u64 {
+  let mut characters = HashSet::new();
+  for (page, _) in pages {
+    for op in &page.ops {
+      if let Op::WriteText { items, .. } = op {
+        for item in items {
+          if let TextItem::Text(text) = item {
+            characters.extend(text.chars());
+          }
+        }
+      }
+    }
+  }
+  let fonts = doc
+    .resources
+    .fonts
+    .map
+    .values()
+    .map(|font| {
+      let glyphs = u64::from(font.num_glyphs.max(1));
+      let used = (characters.len() as u64).min(glyphs);
+      // The glyphs themselves, plus their widths and entries in the `ToUnicode` map
+      FONT_OVERHEAD + font.original_bytes.len() as u64 * used / glyphs + used * 64
+    })
+    .sum::<u64>();
+  PART_OVERHEAD + fonts
+}
+/// Returns whether the file at `path` is an image (by its extension), which is shown as a picture instead of as text
+fn is_image(path: &Path) -> bool {
+  matches!(
+    path.extension().and_then(OsStr::to_str),
+    Some("jpg" | "jpeg" | "png" | "ico" | "bmp" | "webp")
+  )
+}
+/// Moves everything drawn by `ops` to the right by `offset`
+fn shift_horizontally(ops: Vec<Op>, offset: Mm) -> Vec<Op> {
+  if offset == Mm(0.0) {
+    return ops;
+  }
+  let mut shifted = Vec::with_capacity(ops.len() + 3);
+  shifted.extend_from_slice(&[
+    Op::SaveGraphicsState,
+    Op::SetTransformationMatrix {
+      matrix: CurTransMat::Translate(offset.into_pt(), Pt(0.0)),
+    },
+  ]);
+  shifted.extend(ops);
+  shifted.push(Op::RestoreGraphicsState);
+  shifted
+}
+/// Colour of the information shown above the contents of each file
+const FILE_HEADER_COLOUR: Color = Color {
+  r: 102,
+  g: 102,
+  b: 102,
+  a: 255,
+};
+/// Background of lines added in diff mode
+const ADDED_COLOUR: Color = Color {
+  r: 218,
+  g: 251,
+  b: 225,
+  a: 255,
+};
+/// Background of lines removed in diff mode
+const REMOVED_COLOUR: Color = Color {
+  r: 255,
+  g: 235,
+  b: 233,
+  a: 255,
+};
+/// Background of the header at the start of each group of changes in diff mode
+const HUNK_COLOUR: Color = Color {
+  r: 221,
+  g: 244,
+  b: 255,
+  a: 255,
+};
 fn to_rgb(c: Color) -> color::Rgb {
   color::Rgb {
     r: (c.r as f32) / 255.0,
@@ -103,9 +929,22 @@ pub struct CodeToPdf {
   page_dimensions: Dimensions,
   text_wrapper: TextWrapper,
   processed_file_count: usize,
-  include_path: bool,
-  // Text to put at the top of every page
-  page_text: Option<ProcessedText>,
+  /// Column of the current page that text is being written to
+  current_column: u32,
+  /// Font size used unless a file is shrunk to fit
+  base_font_size: f32,
+  /// Minimum font size that files can be shrunk to so that lines don't wrap, if enabled
+  auto_fit: Option<f32>,
+  /// Whether information about each file is shown above its contents
+  file_header: bool,
+  /// Last commit of each file, shown in the file header
+  last_commits: Arc<LastCommits>,
+  /// Whether each line is annotated with the commit which last changed it, in the gutter of each column
+  blame: bool,
+  /// Revision the files are read from, if not the working tree
+  revision: Option<String>,
+  /// Language used to highlight every file, instead of detecting it
+  language: Option<String>,
 }
 impl CodeToPdf {
   /// Initialises a new [`CodeToPdf`]
@@ -114,22 +953,71 @@ impl CodeToPdf {
     font_id: FontId,
     page_dimensions: Dimensions,
     text_wrapper: TextWrapper,
-    page_text: Option<ProcessedText>,
-    include_path: bool,
   ) -> Self {
     Self {
       current_page_contents: vec![],
       doc,
       font_id,
       page_dimensions,
+      base_font_size: text_wrapper.font_size(),
       text_wrapper,
       processed_file_count: 0,
-      page_text,
-      include_path,
+      current_column: 0,
+      auto_fit: None,
+      file_header: false,
+      last_commits: Arc::default(),
+      blame: false,
+      revision: None,
+      language: None,
+    }
+  }
+  /// Shrinks the font of each file (down to `min_font_size`) so that its longest line doesn't wrap
+  pub fn with_auto_fit(mut self, min_font_size: Option<f32>) -> Self {
+    self.auto_fit = min_font_size;
+    self
+  }
+  /// Sets whether information about each file (see [`FileHeader`]) is shown above its contents
+  pub fn with_file_header(mut self, file_header: bool) -> Self {
+    self.file_header = file_header;
+    self
+  }
+  /// Sets where the last commit of each file shown in its header is looked up, so it can be shared between threads
+  pub fn with_last_commits(mut self, last_commits: Arc<LastCommits>) -> Self {
+    self.last_commits = last_commits;
+    self
+  }
+  /// Sets whether each line is annotated with the commit which last changed it (see [`git::blame`]).
+  /// The annotations are written in the gutter set in the page dimensions
+  pub fn with_blame(mut self, blame: bool) -> Self {
+    self.blame = blame;
+    self
+  }
+  /// Sets the git revision that files passed to [`CodeToPdf::process_blob`] are read from
+  pub fn with_revision(mut self, revision: Option<String>) -> Self {
+    self.revision = revision;
+    self
+  }
+  /// Sets the language (a syntax name or extension, such as `rust` or `rs`) used to highlight every file,
+  /// instead of detecting it from the path and first line of each file
+  pub fn with_language(mut self, language: Option<String>) -> Self {
+    self.language = language;
+    self
+  }
+  /// Uses the page dimensions of the file at `path` until the next file, so that the body starts below its header
+  fn start_file(&mut self, path: &Path) {
+    let dimensions = self
+      .doc
+      .lock()
+      .ok()
+      .and_then(|mut doc| doc.file_dimensions(path));
+    if let Some(dimensions) = dimensions {
+      self.page_dimensions = dimensions;
     }
   }
   /// Saves the current page contents to the document, and clears [`CodeToPdf::current_page_contents`]
-  fn save_page(&mut self, index: usize) {
+  ///
+  /// `used_lines` is the number of lines of the body which have been used, or `None` if the whole page has been used
+  fn save_page(&mut self, index: usize, used_lines: Option<u32>) {
     self.current_page_contents.push(Op::EndTextSection);
     let contents = std::mem::take(&mut self.current_page_contents);
     let page = PdfPage::new(
@@ -138,45 +1026,67 @@ impl CodeToPdf {
       contents,
     );
     _ = self.doc.lock().map(|mut doc| {
-      doc.pages.push((page, index));
+      doc.pages.push(GeneratedPage {
+        page,
+        index,
+        used_lines,
+        other_files: vec![],
+      });
     });
     // self.doc.pages.push(page);
   }
 
   /// Initialises [`CodeToPdf::current_page_contents`] with basic contents
-  fn init_page(&mut self, path: &Path) {
+  fn init_page(&mut self) {
     // Should never be called on a non-empty current_pages_contents, so check it in debug mode
     debug_assert_eq!(self.current_page_contents.len(), 0);
 
+    self.current_column = 0;
     init_page(
       &mut self.current_page_contents,
       &self.page_dimensions,
       self.font_id.clone(),
       self.text_wrapper.font_size(),
-      path,
-      self.page_text.as_ref(),
-      self.include_path,
-      &mut self.text_wrapper,
     );
   }
-  /// Computes maximum number of lines that can be displayed on a page
+  /// Moves the text cursor to the top of the next column
+  fn start_next_column(&mut self) {
+    self.current_column += 1;
+    self.current_page_contents.extend_from_slice(&[
+      Op::SetTextMatrix {
+        matrix: TextMatrix::Translate(Pt(0.0), Pt(0.0)),
+      },
+      Op::SetTextCursor {
+        pos: Point {
+          x: self.page_dimensions.column_left(self.current_column).into(),
+          y: self.page_dimensions.body_top().into(),
+        },
+      },
+    ]);
+  }
+  /// Computes maximum number of lines that can be displayed in a column
   fn max_line_count(&self) -> u32 {
-    let max_height = self.page_dimensions.max_text_height();
-    ((max_height).into_pt().0 / (self.text_wrapper.font_size() * 1.2)).floor() as u32
+    self
+      .page_dimensions
+      .max_line_count(self.text_wrapper.font_size())
   }
-  /// Increment given line_count. Begin a new page if it's too high
-  /// Returns `true` if a new page is created
+  /// Increment given line_count. Begin a new column or page if it's too high
+  /// Returns `true` if a new column or page is started
   fn increment_line_count(
     &mut self,
     line_count: &mut u32,
-    path: &Path,
     index: usize,
     has_added_text: &mut bool,
   ) -> bool {
     *line_count += 1;
-    if *line_count > self.max_line_count() {
-      self.save_page(index);
-      self.init_page(path);
+    if *line_count > self.max_line_count() && self.current_column + 1 < self.page_dimensions.columns
+    {
+      self.start_next_column();
+      *line_count = 0;
+      true
+    } else if *line_count > self.max_line_count() {
+      self.save_page(index, None);
+      self.init_page();
       *has_added_text = false;
       *line_count = 0;
       true
@@ -184,29 +1094,131 @@ impl CodeToPdf {
       false
     }
   }
-  /// Generates all the pages for a file
+  /// Writes the information about a file above its contents, followed by a blank line
+  fn write_file_header(
+    &mut self,
+    header: &FileHeader,
+    line_count: &mut u32,
+    index: usize,
+    has_added_text: &mut bool,
+  ) {
+    self.current_page_contents.push(Op::SetFillColor {
+      col: color::Color::Rgb(to_rgb(FILE_HEADER_COLOUR)),
+    });
+    let column_width = self.page_dimensions.column_width().into_pt();
+    for text in header.text_lines() {
+      for (line, _) in self.text_wrapper.split_into_lines(&text, |_| column_width) {
+        let items = self.text_wrapper.text_items(&line);
+        self.current_page_contents.push(Op::WriteText {
+          items,
+          font: self.font_id.clone(),
+        });
+        *has_added_text = true;
+        if !self.increment_line_count(line_count, index, has_added_text) {
+          self.current_page_contents.push(Op::AddLineBreak);
+        }
+      }
+    }
+    // Leave a blank line before the contents
+    if !self.increment_line_count(line_count, index, has_added_text) {
+      self.current_page_contents.push(Op::AddLineBreak);
+    }
+    self.current_page_contents.push(Op::SetFillColor {
+      col: color::Color::Rgb(to_rgb(Color::BLACK)),
+    });
+  }
+  /// Writes `annotation` in the gutter beside the line `line_count` lines into the current column,
+  /// then moves the text cursor back to the start of that line
+  fn write_gutter(&mut self, annotation: &str, line_count: u32) {
+    let font_size = self.text_wrapper.font_size();
+    let y = self.page_dimensions.body_top().into_pt() - Pt(font_size * 1.2) * line_count as f32;
+    let gutter_left = self
+      .page_dimensions
+      .gutter_left(self.current_column)
+      .into_pt();
+    // Keeps a gap between the annotation and the text
+    let x = match self.page_dimensions.gutter_side {
+      GutterSide::Left => gutter_left,
+      GutterSide::Right => gutter_left + Pt(font_size),
+    };
+    let items = self.text_wrapper.text_items(annotation);
+    self.current_page_contents.extend_from_slice(&[
+      Op::SetTextMatrix {
+        matrix: TextMatrix::Translate(Pt(0.0), Pt(0.0)),
+      },
+      Op::SetTextCursor {
+        pos: Point { x, y },
+      },
+      Op::WriteText {
+        items,
+        font: self.font_id.clone(),
+      },
+      Op::SetTextMatrix {
+        matrix: TextMatrix::Translate(Pt(0.0), Pt(0.0)),
+      },
+      Op::SetTextCursor {
+        pos: Point {
+          x: self.page_dimensions.column_left(self.current_column).into(),
+          y,
+        },
+      },
+    ]);
+  }
+  /// Generates all the pages for a file, returning the number of each kind of line in the file.
+  ///
+  /// `annotations` are written in the gutter beside each line of the file (if not empty)
   fn generate_highlighted_pages(
     &mut self,
-    highlighter: &mut HighlightFile,
-    path: &Path,
+    highlighter: &mut FileHighlighter,
     highlighter_config: &HighlighterConfig,
+    header: Option<&FileHeader>,
+    annotations: &[String],
     index: usize,
-  ) {
+  ) -> LineCounts {
     let mut line = String::new();
     let mut line_count = 0;
-    self.init_page(path);
+    let mut line_counts = LineCounts::default();
+    self.init_page();
     let mut has_added_text = false;
+    if let Some(header) = header {
+      self.write_file_header(header, &mut line_count, index, &mut has_added_text);
+    }
     let mut prev_colour = Color::BLACK;
-    while highlighter.reader.read_line(&mut line).unwrap_or(0) > 0 {
+    let mut annotations = annotations.iter();
+    while highlighter.read_line(&mut line) {
       has_added_text = true;
+      if let Cow::Owned(expanded) = self.text_wrapper.expand_tabs(&line) {
+        line = expanded;
+      }
+      if let Some(annotation) = annotations.next().filter(|a| !a.is_empty()) {
+        self.current_page_contents.push(Op::SetFillColor {
+          col: color::Color::Rgb(to_rgb(FILE_HEADER_COLOUR)),
+        });
+        prev_colour = FILE_HEADER_COLOUR;
+        self.write_gutter(annotation, line_count);
+      }
       // Store the char count for the current line
       let mut line_width = 0.0;
       let regions: &[(Style, &str)] = if line.len() < highlighter_config.max_line_len_to_highlight {
-        &highlighter
-          .highlight_lines
-          .highlight_line(&line, &highlighter_config.syntax_set)
-          .unwrap()
+        let ops = highlighter
+          .parse_state
+          .parse_line(&line, &highlighter_config.syntax_set)
+          .unwrap();
+        line_counts.add(highlighter.classifier.classify(&line, &ops));
+        &HighlightIterator::new(
+          &mut highlighter.highlight_state,
+          &ops,
+          &line,
+          &highlighter.highlighter,
+        )
+        .collect::<Vec<_>>()
       } else {
+        // Lines this long aren't parsed, so are assumed to be code
+        line_counts.add(if line.trim().is_empty() {
+          LineKind::Blank
+        } else {
+          LineKind::Code
+        });
         &[(
           Style {
             foreground: Color::BLACK,
@@ -219,7 +1231,7 @@ impl CodeToPdf {
       for (style, text) in regions {
         let text_width = self.text_wrapper.get_width(text).0;
 
-        let line_width_remaining = self.page_dimensions.max_text_width().into_pt().0 - line_width;
+        let line_width_remaining = self.page_dimensions.column_width().into_pt().0 - line_width;
 
         let text_colour = style.foreground;
         // Set PDF text colour if the colour of the current region is different to the colour of the previous region
@@ -232,17 +1244,16 @@ impl CodeToPdf {
         // Split region into multiple lines if it is too long to fit on the current line
         let lines = self.text_wrapper.split_into_lines(text, |i| match i {
           0 => Pt(line_width_remaining),
-          _ => self.page_dimensions.max_text_width().into_pt(),
+          _ => self.page_dimensions.column_width().into_pt(),
         });
         match lines.len() {
           // If only a single line, then no new lines are going to be made (as we're processing a single region here)
           1 => {
+            let items = self
+              .text_wrapper
+              .text_items(text.trim_end_matches(|x| (x == '\n') | (x == '\r')));
             self.current_page_contents.push(Op::WriteText {
-              items: vec![TextItem::Text(
-                text
-                  .trim_end_matches(|x| (x == '\n') | (x == '\r'))
-                  .to_string(),
-              )],
+              items,
               font: self.font_id.clone(),
             });
             line_width += text_width;
@@ -257,42 +1268,256 @@ impl CodeToPdf {
               }
               first = false;
               line_width += width;
+              let items = self.text_wrapper.text_items(&l);
               self.current_page_contents.push(Op::WriteText {
-                items: vec![TextItem::Text(l)],
+                items,
                 font: self.font_id.clone(),
               });
-              self.increment_line_count(&mut line_count, path, index, &mut has_added_text);
+              self.increment_line_count(&mut line_count, index, &mut has_added_text);
             }
           }
         }
       }
 
-      if !self.increment_line_count(&mut line_count, path, index, &mut has_added_text) {
+      if !self.increment_line_count(&mut line_count, index, &mut has_added_text) {
         self.current_page_contents.push(Op::AddLineBreak);
       }
       line.clear();
     }
     // Clear page if no text has been added to it
     if has_added_text {
-      self.save_page(index);
+      // Only pages with a single column of text at the normal size have space left for other files
+      let used_lines = (self.current_column == 0
+        && self.text_wrapper.font_size() == self.base_font_size)
+        .then_some(line_count);
+      self.save_page(index, used_lines);
     } else {
       self.current_page_contents.clear()
     }
+    line_counts
   }
 
-  /// Generates a page containing the image at the path given
-  fn generate_image_page(&mut self, path: &Path, index: usize) {
-    let bytes = if let Ok(b) = fs::read(path) {
-      b
-    } else {
-      return;
-    };
-    let image = if let Ok(img) = RawImage::decode_from_bytes(&bytes, &mut vec![]) {
+  /// Generates the pages showing the changes to a file, given the highlighted lines of each version
+  fn generate_diff_pages(
+    &mut self,
+    old: &[HighlightedLine],
+    new: &[HighlightedLine],
+    rows: &[DiffRow],
+    style: DiffStyle,
+    index: usize,
+  ) {
+    let font_size = self.text_wrapper.font_size();
+    let line_height = Pt(font_size * 1.2);
+    let max_line_count = self.max_line_count().max(1);
+    let left = self.page_dimensions.margin_left.into_pt();
+    let width = self.page_dimensions.max_text_width().into_pt();
+    let gap = Pt(font_size * 0.5);
+    let number_width = self.text_wrapper.get_width("00000");
+    let marker_width = self.text_wrapper.get_width("+");
+    let half_width = (width - gap) / 2.0;
+    let line_number =
+      |line: &Option<DiffLine>| line.map_or(String::new(), |line| (line.index + 1).to_string());
+    fn text<'a>(lines: &'a [HighlightedLine], line: &Option<DiffLine>) -> &'a [(Color, String)] {
+      line
+        .and_then(|line| lines.get(line.index))
+        .map_or(&[], Vec::as_slice)
+    }
+
+    self.init_page();
+    let mut backgrounds = vec![];
+    let mut line_count = 0;
+    let mut prev_colour = None;
+    for row in rows {
+      let hunk_line;
+      let cells = match (row, style) {
+        (DiffRow::Hunk(header), _) => {
+          hunk_line = vec![(FILE_HEADER_COLOUR, header.clone())];
+          vec![DiffCell {
+            left,
+            width,
+            background: Some(HUNK_COLOUR),
+            gutter: vec![],
+            text_left: left,
+            line: &hunk_line,
+          }]
+        }
+        (
+          DiffRow::Lines {
+            old: old_line,
+            new: new_line,
+          },
+          DiffStyle::Unified,
+        ) => {
+          let (line, background, marker) = match (old_line, new_line) {
+            (Some(line), None) if line.changed => (text(old, old_line), Some(REMOVED_COLOUR), "-"),
+            (None, Some(line)) if line.changed => (text(new, new_line), Some(ADDED_COLOUR), "+"),
+            _ => (text(new, new_line), None, ""),
+          };
+          let marker_left = left + number_width * 2.0 + gap * 2.0;
+          vec![DiffCell {
+            left,
+            width,
+            background,
+            gutter: vec![
+              (left + number_width, line_number(old_line)),
+              (left + number_width * 2.0 + gap, line_number(new_line)),
+              (marker_left + marker_width, marker.to_string()),
+            ],
+            text_left: marker_left + marker_width + gap,
+            line,
+          }]
+        }
+        (
+          DiffRow::Lines {
+            old: old_line,
+            new: new_line,
+          },
+          DiffStyle::SideBySide,
+        ) => [
+          (old, old_line, REMOVED_COLOUR),
+          (new, new_line, ADDED_COLOUR),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(side, (lines, line, colour))| {
+          let side_left = left + (half_width + gap) * side as f32;
+          DiffCell {
+            left: side_left,
+            width: half_width,
+            background: line.filter(|line| line.changed).map(|_| colour),
+            gutter: vec![(side_left + number_width, line_number(line))],
+            text_left: side_left + number_width + gap,
+            line: text(lines, line),
+          }
+        })
+        .collect(),
+      };
+      let wrapped = cells
+        .iter()
+        .map(|cell| self.wrap_line(cell.line, cell.left + cell.width - cell.text_left))
+        .collect::<Vec<_>>();
+      let height = wrapped.iter().map(Vec::len).max().unwrap_or(0).max(1);
+      for i in 0..height {
+        if line_count == max_line_count {
+          self.save_diff_page(&mut backgrounds, index);
+          self.init_page();
+          line_count = 0;
+          prev_colour = None;
+        }
+        let y = self.page_dimensions.body_top().into_pt() - line_height * line_count as f32;
+        for (cell, lines) in cells.iter().zip(&wrapped) {
+          if let Some(background) = cell.background {
+            backgrounds.extend_from_slice(&[
+              Op::SetFillColor {
+                col: color::Color::Rgb(to_rgb(background)),
+              },
+              Op::DrawPolygon {
+                polygon: Rect {
+                  x: cell.left,
+                  // This is the top edge. Cover the descenders of this line, but not the next
+                  y: y + line_height * 0.75,
+                  width: cell.width,
+                  height: line_height,
+                }
+                .to_polygon(),
+              },
+            ]);
+          }
+          if i == 0 {
+            for (right, text) in cell.gutter.iter().filter(|(_, text)| !text.is_empty()) {
+              let x = *right - self.text_wrapper.get_width(text);
+              self.write_regions(
+                x,
+                y,
+                &[(FILE_HEADER_COLOUR, text.clone())],
+                &mut prev_colour,
+              );
+            }
+          }
+          if let Some(line) = lines.get(i) {
+            self.write_regions(cell.text_left, y, line, &mut prev_colour);
+          }
+        }
+        line_count += 1;
+      }
+    }
+    self.save_diff_page(&mut backgrounds, index);
+  }
+  /// Saves the current page in diff mode, drawing `backgrounds` behind the text
+  fn save_diff_page(&mut self, backgrounds: &mut Vec<Op>, index: usize) {
+    let mut ops = mem::take(backgrounds);
+    ops.append(&mut self.current_page_contents);
+    self.current_page_contents = ops;
+    self.save_page(index, None);
+  }
+  /// Splits the regions of a line into multiple lines which each fit in `width`
+  fn wrap_line(&mut self, regions: &[(Color, String)], width: Pt) -> Vec<HighlightedLine> {
+    let mut lines = vec![vec![]];
+    let mut line_width = Pt(0.0);
+    for (colour, text) in regions {
+      let mut pieces = self.text_wrapper.split_into_lines(text, |i| match i {
+        0 => width - line_width,
+        _ => width,
+      });
+      // Pieces are trimmed, so keep the whitespace if the region fits on the current line,
+      // or the leading whitespace if it doesn't
+      if pieces.len() == 1 {
+        pieces = vec![(text.clone(), self.text_wrapper.get_width(text).0)];
+      } else if let Some((first, _)) = pieces.first_mut() {
+        let leading = &text[..text.len() - text.trim_start().len()];
+        first.insert_str(0, leading);
+      }
+      for (i, (piece, piece_width)) in pieces.into_iter().enumerate() {
+        if i > 0 {
+          lines.push(vec![]);
+          line_width = Pt(0.0);
+        }
+        line_width += Pt(piece_width);
+        if let Some(line) = lines.last_mut() {
+          line.push((*colour, piece.trim_end_matches('\n').to_string()));
+        }
+      }
+    }
+    lines
+  }
+  /// Writes the regions of a line starting at the given position, only changing the colour when needed
+  fn write_regions(
+    &mut self,
+    x: Pt,
+    y: Pt,
+    regions: &[(Color, String)],
+    prev_colour: &mut Option<Color>,
+  ) {
+    self.current_page_contents.extend_from_slice(&[
+      Op::SetTextMatrix {
+        matrix: TextMatrix::Translate(Pt(0.0), Pt(0.0)),
+      },
+      Op::SetTextCursor {
+        pos: Point { x, y },
+      },
+    ]);
+    for (colour, text) in regions {
+      if *prev_colour != Some(*colour) {
+        self.current_page_contents.push(Op::SetFillColor {
+          col: color::Color::Rgb(to_rgb(*colour)),
+        });
+        *prev_colour = Some(*colour);
+      }
+      let items = self.text_wrapper.text_items(text);
+      self.current_page_contents.push(Op::WriteText {
+        items,
+        font: self.font_id.clone(),
+      });
+    }
+  }
+  /// Generates a page containing the image given
+  fn generate_image_page(&mut self, bytes: &[u8], index: usize) {
+    let image = if let Ok(img) = RawImage::decode_from_bytes(bytes, &mut vec![]) {
       img
     } else {
       return;
     };
-    self.init_page(path);
+    self.init_page();
     // let image_id = self.doc.add_image(&image);
     let image_id = self
       .doc
@@ -302,19 +1527,43 @@ impl CodeToPdf {
     let pg_x_dpi = self.page_dimensions.width.into_pt().into_px(300.0).0;
     let pg_y_dpi = self.page_dimensions.height.into_pt().into_px(300.0).0;
 
-    let x_scaling = pg_x_dpi as f32 / image.width as f32;
-    let y_scaling = pg_y_dpi as f32 / image.height as f32;
+    // If the image is oriented significantly differently to the page, rotate it so it fills more of the page
+    let rotate = if self.page_dimensions.is_landscape() {
+      image.height > (image.width as f32 * 1.25) as usize
+    } else {
+      image.width > (image.height as f32 * 1.25) as usize
+    };
+    // Dimensions of the image once it has been rotated
+    let (displayed_width, displayed_height) = if rotate {
+      (image.height, image.width)
+    } else {
+      (image.width, image.height)
+    };
+    let x_scaling = pg_x_dpi as f32 / displayed_width as f32;
+    let y_scaling = pg_y_dpi as f32 / displayed_height as f32;
 
     let scale = f32::min(x_scaling, y_scaling);
-    // If width is significantly bigger than the height, rotate so it's oriented to fill more of the page
-    let rotation = if image.width > (image.height as f32 * 1.25) as usize {
-      Some(XObjectRotation {
-        angle_ccw_degrees: -90.0,
-        rotation_center_x: Px(((image.width as f32 * scale) / 2.0) as usize),
-        rotation_center_y: Px(((image.height as f32 * scale) / 2.0) as usize),
-      })
+    let (rotation, translate_x, translate_y) = if rotate {
+      let scaled_width = image.width as f32 * scale;
+      let scaled_height = image.height as f32 * scale;
+      // Rotating about the centre moves the corner of the image, so move it back to the origin
+      let offset = Px(((scaled_width - scaled_height).abs() / 2.0) as usize).into_pt(300.0);
+      let (translate_x, translate_y) = if scaled_width > scaled_height {
+        (Pt(-offset.0), offset)
+      } else {
+        (offset, Pt(-offset.0))
+      };
+      (
+        Some(XObjectRotation {
+          angle_ccw_degrees: -90.0,
+          rotation_center_x: Px((scaled_width / 2.0) as usize),
+          rotation_center_y: Px((scaled_height / 2.0) as usize),
+        }),
+        Some(translate_x),
+        Some(translate_y),
+      )
     } else {
-      None
+      (None, None, None)
     };
 
     self.current_page_contents.push(Op::UseXobject {
@@ -323,10 +1572,33 @@ impl CodeToPdf {
         scale_x: Some(scale),
         scale_y: Some(scale),
         rotate: rotation,
+        translate_x,
+        translate_y,
         ..Default::default()
       },
     });
-    self.save_page(index);
+    self.save_page(index, None);
+  }
+  /// Returns the largest font size (between `min_font_size` and the normal size) at which no lines in `contents` wrap
+  fn fitting_font_size(&mut self, contents: &[u8], min_font_size: f32) -> f32 {
+    let mut longest = Pt(0.0);
+    // Lines keep their newline, as they do when they're wrapped
+    for line in contents.split_inclusive(|&byte| byte == b'\n') {
+      // Stop measuring at anything that isn't text, and let highlighting deal with it
+      let Ok(line) = std::str::from_utf8(line) else {
+        break;
+      };
+      let line = self.text_wrapper.expand_tabs(line);
+      longest = longest.max(self.text_wrapper.get_width(&line));
+    }
+    // Lines ending in whitespace (such as the newline) are wrapped if they come within the slack of the edge
+    let available = self.page_dimensions.column_width().into_pt() - WRAP_SLACK;
+    if longest < available {
+      return self.base_font_size;
+    }
+    // Round down so that the longest line is definitely short enough
+    let font_size = (self.base_font_size * (available / longest) * 10.0).floor() / 10.0;
+    font_size.clamp(min_font_size.min(self.base_font_size), self.base_font_size)
   }
   /// Generates pages for a file
   pub fn process_file(
@@ -334,38 +1606,220 @@ impl CodeToPdf {
     file: &Path,
     highlighter_config: &HighlighterConfig,
     index: usize,
+  ) -> Result<(), Box<dyn std::error::Error>> {
+    let mut reader = BufReader::new(File::open(file)?);
+    self.process_reader(file, &mut reader, true, highlighter_config, index)
+  }
+  /// Generates pages for a file read from `reader`.
+  ///
+  /// `path` is the logical path of the file, which is used to select the syntax, and shown in the header.
+  /// Information which can only be read from the file system (such as the modification time)
+  /// is only shown if `on_disk` is `true`.
+  ///
+  /// Text is highlighted a line at a time as it's read, unless something needs the whole file first
+  /// (the file header, blame, or auto-fit)
+  pub fn process_reader(
+    &mut self,
+    path: &Path,
+    reader: &mut dyn BufRead,
+    on_disk: bool,
+    highlighter_config: &HighlighterConfig,
+    index: usize,
+  ) -> Result<(), Box<dyn std::error::Error>> {
+    self.processed_file_count += 1;
+    self.start_file(path);
+    let (language, line_counts, size) = if is_image(path) {
+      let mut contents = vec![];
+      reader.read_to_end(&mut contents)?;
+      self.generate_image_page(&contents, index);
+      (None, LineCounts::default(), contents.len() as u64)
+    } else {
+      // Files which aren't on disk or in a revision (such as stdin) have no history to blame
+      let blame = self.blame && (on_disk || self.revision.is_some());
+      let contents = match self.file_header || blame || self.auto_fit.is_some() {
+        true => {
+          let mut contents = vec![];
+          reader.read_to_end(&mut contents)?;
+          Some(contents)
+        }
+        false => None,
+      };
+      let mut buffered = contents.as_deref().unwrap_or_default();
+      let reader: &mut dyn BufRead = match contents {
+        Some(_) => &mut buffered,
+        None => &mut *reader,
+      };
+      let mut highlighter = FileHighlighter::new(
+        path,
+        reader,
+        self.language.as_deref(),
+        &highlighter_config.syntax_set,
+        &highlighter_config.theme,
+      );
+      let contents = contents.as_deref().unwrap_or_default();
+      if let Some(min_font_size) = self.auto_fit {
+        let font_size = self.fitting_font_size(contents, min_font_size);
+        self.text_wrapper.set_font_size(font_size);
+      }
+      let header = match (self.file_header, on_disk) {
+        (false, _) => None,
+        (true, true) => Some(
+          FileHeader::new(highlighter.language.clone(), contents)
+            .with_disk_info(path, &self.last_commits),
+        ),
+        (true, false) => Some(FileHeader::new(highlighter.language.clone(), contents)),
+      };
+      let annotations = match (blame, on_disk) {
+        (false, _) => None,
+        (true, true) => git::blame(path, contents, None),
+        (true, false) => git::blame(path, contents, self.revision.as_deref()),
+      };
+      let line_counts = self.generate_highlighted_pages(
+        &mut highlighter,
+        highlighter_config,
+        header.as_ref(),
+        annotations.as_deref().unwrap_or_default(),
+        index,
+      );
+      self.text_wrapper.set_font_size(self.base_font_size);
+      // Anything after text which isn't UTF-8 isn't shown, but still counts towards the size
+      let rest = std::io::copy(highlighter.reader, &mut std::io::sink()).unwrap_or(0);
+      (
+        Some(highlighter.language),
+        line_counts,
+        highlighter.size + rest,
+      )
+    };
+    _ = self.doc.lock().map(|mut doc| {
+      doc.add_file(
+        index,
+        FileInfo {
+          path: path.to_path_buf(),
+          lines: line_counts.total(),
+          size,
+          language,
+          line_counts,
+        },
+      )
+    });
+    Ok(())
+  }
+  /// Generates pages showing the changes to a file, in the given style
+  pub fn process_diff(
+    &mut self,
+    diff: &FileDiff,
+    style: DiffStyle,
+    highlighter_config: &HighlighterConfig,
+    index: usize,
   ) -> Result<(), Box<dyn std::error::Error>> {
     self.processed_file_count += 1;
-    match file.extension().and_then(OsStr::to_str) {
-      Some("jpg" | "jpeg" | "png" | "ico" | "bmp" | "webp") => {
-        self.generate_image_page(file, index);
-        Ok(())
+    self.start_file(&diff.path);
+    let latest = diff
+      .new
+      .as_ref()
+      .or(diff.old.as_ref())
+      .cloned()
+      .unwrap_or_default();
+    let is_binary = [&diff.old, &diff.new]
+      .into_iter()
+      .flatten()
+      .any(|contents| is_binary(contents));
+    let (language, line_counts) = match (is_image(&diff.path), is_binary) {
+      // Only the latest version of images is shown
+      (true, _) => {
+        self.generate_image_page(&latest, index);
+        (None, LineCounts::default())
+      }
+      // Binary files can't be compared line by line, so only the change in size is shown
+      (false, true) => {
+        let size = |contents: &Option<Vec<u8>>| {
+          contents
+            .as_ref()
+            .map(|contents| format_size(contents.len() as u64))
+        };
+        let notice = match (size(&diff.old), size(&diff.new)) {
+          (Some(old), Some(new)) => format!("Binary file changed ({old} to {new})"),
+          (None, Some(new)) => format!("Binary file added ({new})"),
+          (Some(old), None) => format!("Binary file deleted ({old})"),
+          (None, None) => "Binary file changed".to_string(),
+        };
+        let rows = [DiffRow::Hunk(notice)];
+        self.generate_diff_pages(&[], &[], &rows, style, index);
+        (None, LineCounts::default())
       }
       _ => {
-        let mut highlighter = HighlightFile::new(
-          file,
-          &highlighter_config.syntax_set,
-          &highlighter_config.theme,
-        )?;
-
-        self.generate_highlighted_pages(&mut highlighter, file, highlighter_config, index);
-
-        Ok(())
+        let old = String::from_utf8_lossy(diff.old.as_deref().unwrap_or_default());
+        let new = String::from_utf8_lossy(diff.new.as_deref().unwrap_or_default());
+        let (old, new) = (
+          self.text_wrapper.expand_tabs(&old).into_owned(),
+          self.text_wrapper.expand_tabs(&new).into_owned(),
+        );
+        let highlight = |contents: &str| {
+          let mut reader = contents.as_bytes();
+          let mut highlighter = FileHighlighter::new(
+            &diff.path,
+            &mut reader,
+            self.language.as_deref(),
+            &highlighter_config.syntax_set,
+            &highlighter_config.theme,
+          );
+          (
+            highlighter.highlight_all_lines(highlighter_config),
+            highlighter.language,
+          )
+        };
+        let ((old_lines, _), _) = highlight(&old);
+        // Lines are counted in the latest version, as for files which aren't diffed
+        let ((new_lines, line_counts), language) = highlight(&new);
+        let rows = diff_rows(&old, &new, style);
+        self.generate_diff_pages(&old_lines, &new_lines, &rows, style, index);
+        (Some(language), line_counts)
       }
-    }
+    };
+    _ = self.doc.lock().map(|mut doc| {
+      doc.add_file(
+        index,
+        FileInfo {
+          path: diff.path.clone(),
+          lines: line_counts.total(),
+          size: latest.len() as u64,
+          language,
+          line_counts,
+        },
+      )
+    });
+    Ok(())
+  }
+  /// Generates pages for a file which isn't on disk (such as a blob from a git revision).
+  ///
+  /// `path` is used to select the syntax, and is shown in the header
+  pub fn process_blob(
+    &mut self,
+    path: &Path,
+    contents: &[u8],
+    highlighter_config: &HighlighterConfig,
+    index: usize,
+  ) -> Result<(), Box<dyn std::error::Error>> {
+    self.process_reader(path, &mut &*contents, false, highlighter_config, index)
   }
   /// Consumes entire walker
   pub fn process_files(&mut self, walker: Walk, highlighter_config: HighlighterConfig) {
-    for result in walker {
-      match result {
-        Ok(entry) => {
-          if entry.file_type().is_some_and(|f| f.is_file())
-            && let Err(err) = self.process_file(entry.path(), &highlighter_config, 0)
-          {
-            error!("ERROR: {}", err)
-          }
-        }
-        Err(err) => error!("ERROR: {}", err),
+    self.process_source(WalkSource(walker), &highlighter_config);
+  }
+  /// Generates pages for every file from `source`, in order
+  pub fn process_source(&mut self, source: impl Source, highlighter_config: &HighlighterConfig) {
+    for (i, result) in source::files(source).enumerate() {
+      let result = result.map_err(Into::into).and_then(|mut file| {
+        self.process_reader(
+          &file.path,
+          &mut file.reader,
+          file.on_disk,
+          highlighter_config,
+          i,
+        )
+      });
+      if let Err(err) = result {
+        error!("ERROR: {}", err)
       }
     }
   }
@@ -375,3 +1829,146 @@ impl CodeToPdf {
     self.processed_file_count
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn recto_start() {
+    let mut subset = DocumentSubset::default().with_recto_start(RectoStart::Blank);
+    // Files with 1, 2, and 1 pages, added out of order
+    for index in [2, 1, 0, 1] {
+      subset.pages.push(GeneratedPage {
+        page: PdfPage::new(Mm(210.0), Mm(297.0), vec![]),
+        index,
+        used_lines: None,
+        other_files: vec![],
+      });
+    }
+    let mut doc = PdfDocument::new("Test");
+    subset.to_document(&mut doc);
+    // A blank page is only needed after the first file
+    assert_eq!(doc.pages.len(), 5);
+  }
+  #[test]
+  fn cover_page() {
+    let mut subset = DocumentSubset::default()
+      .with_recto_start(RectoStart::Blank)
+      .with_cover(Some(Cover::new("Test".into(), Path::new("."))));
+    subset.pages.push(GeneratedPage {
+      page: PdfPage::new(Mm(210.0), Mm(297.0), vec![]),
+      index: 0,
+      used_lines: None,
+      other_files: vec![],
+    });
+    let mut doc = PdfDocument::new("Test");
+    subset.to_document(&mut doc);
+    // The cover is followed by a blank page, so the file starts on page 3
+    assert_eq!(doc.pages.len(), 3);
+  }
+  #[test]
+  fn compact() {
+    let font_bytes = include_bytes!("../../fonts/Helvetica.ttf");
+    let mut subset = DocumentSubset::new(
+      HeaderFooter::default(),
+      Dimensions::default(),
+      FontId::new(),
+      TextWrapper::new(font_bytes, 12.0),
+    )
+    .with_compact(true);
+    let max_line_count = Dimensions::default().max_line_count(12.0);
+    // Three small files, a file that fills a page, then an image
+    for (index, used_lines) in [
+      (0, Some(5)),
+      (1, Some(5)),
+      (2, Some(5)),
+      (3, Some(max_line_count - 10)),
+      (4, None),
+    ] {
+      subset.pages.push(GeneratedPage {
+        page: PdfPage::new(Mm(210.0), Mm(297.0), vec![]),
+        index,
+        used_lines,
+        other_files: vec![],
+      });
+    }
+    let mut doc = PdfDocument::new("Test");
+    subset.to_document(&mut doc);
+    assert_eq!(doc.pages.len(), 3);
+  }
+  #[test]
+  fn selecting_syntax() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let theme = Theme::default();
+    let new = |path: &str, contents: &'static [u8], language: Option<&str>| {
+      FileHighlighter::new(
+        Path::new(path),
+        &mut &*contents,
+        language,
+        &syntax_set,
+        &theme,
+      )
+      .language
+    };
+    assert_eq!(new("main.rs", b"", None), "Rust");
+    // Files without an extension fall back to the first line, which is read from the contents
+    let contents = b"#!/usr/bin/env python3\nprint()\n";
+    assert_eq!(new("script", contents, None), "Python");
+    // The language overrides the path
+    assert_eq!(new("script", contents, Some("rust")), "Rust");
+    assert_eq!(new("stdin", b"", Some("rs")), "Rust");
+  }
+  #[test]
+  fn fitting_lines() {
+    let font_bytes = include_bytes!("../../fonts/Helvetica.ttf");
+    let mut code_to_pdf = CodeToPdf::new(
+      Arc::default(),
+      FontId::new(),
+      Dimensions::default(),
+      TextWrapper::new(font_bytes, 12.0),
+    );
+    let column_width = Dimensions::default().column_width().into_pt();
+    let line = "let x = 1; ".repeat(20) + "\n";
+    assert!(
+      code_to_pdf
+        .text_wrapper
+        .split_into_lines(&line, |_| column_width)
+        .len()
+        > 1
+    );
+    let font_size = code_to_pdf.fitting_font_size(line.as_bytes(), 4.0);
+    assert!(font_size < 12.0);
+    // The line is wrapped in the same way as when it's written
+    code_to_pdf.text_wrapper.set_font_size(font_size);
+    assert_eq!(
+      code_to_pdf
+        .text_wrapper
+        .split_into_lines(&line, |_| column_width)
+        .len(),
+      1
+    );
+  }
+  #[test]
+  fn counting_highlighted_lines() {
+    let config = HighlighterConfig::new(SyntaxSet::load_defaults_newlines(), Theme::default());
+    let mut contents = &b"// Comment\n\nfn main() {}\n"[..];
+    let mut highlighter = FileHighlighter::new(
+      Path::new("main.rs"),
+      &mut contents,
+      None,
+      &config.syntax_set,
+      &config.theme,
+    );
+    let (lines, line_counts) = highlighter.highlight_all_lines(&config);
+    assert_eq!(lines.len(), 3);
+    assert_eq!(
+      line_counts,
+      LineCounts {
+        code: 1,
+        comments: 1,
+        blanks: 1
+      }
+    );
+  }
+}
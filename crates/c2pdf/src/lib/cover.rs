@@ -0,0 +1,172 @@
+//! Contains [`Cover`], which describes the first page of the document
+
+use std::{fs, path::Path, time::SystemTime};
+
+use printpdf::{
+  FontId, Mm, Op, Point, Pt, Px, RawImage, TextMatrix, XObjectId, XObjectTransform, color,
+};
+
+use crate::{dimensions::Dimensions, git, helpers::format_date, text_manipulation::TextWrapper};
+
+/// Size of the title relative to the rest of the text
+const TITLE_SCALE: f32 = 2.0;
+/// Maximum width and height of the logo
+const LOGO_SIZE: (Mm, Mm) = (Mm(80.0), Mm(50.0));
+
+/// Totals for all the files in the document, shown on the cover
+pub struct CoverTotals {
+  /// Number of files
+  pub files: usize,
+  /// Total number of lines across all files
+  pub lines: usize,
+}
+
+/// The cover page of the document
+#[derive(Debug, Clone)]
+pub struct Cover {
+  /// Name of the project
+  pub title: String,
+  /// Free-form description, shown below the title
+  pub description: Option<String>,
+  /// Image shown above the title
+  pub logo: Option<RawImage>,
+  /// Date the document was generated
+  pub date: String,
+  /// Abbreviated hash of the checked out commit, if the project is in a git repository
+  pub git_commit: Option<String>,
+  /// Checked out branch, if the project is in a git repository
+  pub git_branch: Option<String>,
+}
+impl Cover {
+  /// Initialises a new [`Cover`] for the project called `title` at `root`
+  pub fn new(title: String, root: &Path) -> Self {
+    Self {
+      title,
+      description: None,
+      logo: None,
+      date: format_date(SystemTime::now()),
+      git_commit: git::head_commit(root),
+      git_branch: git::head_branch(root),
+    }
+  }
+  /// Sets the logo to the image at `path`
+  pub fn with_logo(mut self, path: &Path) -> Result<Self, String> {
+    let bytes = fs::read(path).map_err(|err| err.to_string())?;
+    self.logo = Some(RawImage::decode_from_bytes(&bytes, &mut vec![])?);
+    Ok(self)
+  }
+  /// Writes the cover into `contents`.
+  ///
+  /// `logo_id` is the ID the logo has been added to the document with
+  pub fn render(
+    &self,
+    contents: &mut Vec<Op>,
+    page_dimensions: &Dimensions,
+    font_id: &FontId,
+    wrapper: &TextWrapper,
+    logo_id: Option<XObjectId>,
+    totals: &CoverTotals,
+  ) {
+    let mut y = (page_dimensions.header_top() - Mm(30.0)).into_pt();
+    if let (Some(logo), Some(logo_id)) = (&self.logo, logo_id) {
+      // Natural size of the image, at the default DPI
+      let width = Px(logo.width).into_pt(300.0);
+      let height = Px(logo.height).into_pt(300.0);
+      let scale = f32::min(
+        LOGO_SIZE.0.into_pt() / width,
+        LOGO_SIZE.1.into_pt() / height,
+      )
+      .min(1.0);
+      y -= height * scale;
+      contents.push(Op::UseXobject {
+        id: logo_id,
+        transform: XObjectTransform {
+          translate_x: Some((page_dimensions.width.into_pt() - width * scale) / 2.0),
+          translate_y: Some(y),
+          scale_x: Some(scale),
+          scale_y: Some(scale),
+          ..Default::default()
+        },
+      });
+      y -= Mm(15.0).into_pt();
+    }
+
+    let mut writer = CentredWriter {
+      contents,
+      page_dimensions,
+      font_id,
+      wrapper: wrapper.clone(),
+      y,
+    };
+    writer.contents.push(Op::StartTextSection);
+    writer.contents.push(Op::SetFillColor {
+      col: color::Color::Rgb(color::Rgb {
+        r: 0.0,
+        g: 0.0,
+        b: 0.0,
+        icc_profile: None,
+      }),
+    });
+    let font_size = wrapper.font_size();
+    writer.write(&self.title, font_size * TITLE_SCALE);
+    writer.skip(font_size);
+    if let Some(description) = &self.description {
+      writer.write(description, font_size);
+      writer.skip(font_size);
+    }
+    let mut details = vec![format!("Generated on {}", self.date)];
+    match (&self.git_commit, &self.git_branch) {
+      (Some(commit), Some(branch)) => details.push(format!("Commit {commit} on {branch}")),
+      (Some(commit), None) => details.push(format!("Commit {commit}")),
+      _ => {}
+    }
+    details.push(format!("{} files, {} lines", totals.files, totals.lines));
+    writer.write(&details.join("\n"), font_size);
+    writer.contents.push(Op::EndTextSection);
+  }
+}
+
+/// Writes lines of text centred horizontally, moving down the page
+struct CentredWriter<'a> {
+  contents: &'a mut Vec<Op>,
+  page_dimensions: &'a Dimensions,
+  font_id: &'a FontId,
+  wrapper: TextWrapper,
+  /// Baseline of the next line
+  y: Pt,
+}
+impl CentredWriter<'_> {
+  /// Writes `text` with the given font size, wrapping lines that are too long
+  fn write(&mut self, text: &str, font_size: f32) {
+    self.wrapper.set_font_size(font_size);
+    self.contents.push(Op::SetFontSize {
+      size: Pt(font_size),
+      font: self.font_id.clone(),
+    });
+    let max_width = self.page_dimensions.max_text_width().into_pt();
+    for line in text.lines() {
+      for (line, _) in self.wrapper.split_into_lines(line, |_| max_width) {
+        let line = line.trim_end();
+        let width = self.wrapper.get_width(line);
+        let x = self.page_dimensions.margin_left.into_pt() + (max_width - width) / 2.0;
+        self.contents.extend_from_slice(&[
+          Op::SetTextMatrix {
+            matrix: TextMatrix::Translate(Pt(0.0), Pt(0.0)),
+          },
+          Op::SetTextCursor {
+            pos: Point { x, y: self.y },
+          },
+          Op::WriteText {
+            items: self.wrapper.text_items(line),
+            font: self.font_id.clone(),
+          },
+        ]);
+        self.y -= Pt(font_size * 1.2);
+      }
+    }
+  }
+  /// Leaves a gap of one line with the given font size
+  fn skip(&mut self, font_size: f32) {
+    self.y -= Pt(font_size * 1.2);
+  }
+}
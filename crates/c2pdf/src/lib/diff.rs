@@ -0,0 +1,359 @@
+//! Finds the files which changed between two versions of a project, and the rows needed to show their changes.
+//!
+//! The rows are laid out onto pages by [`crate::code_to_pdf::CodeToPdf::process_diff`]
+
+use std::{collections::HashMap, fs, path::Path, path::PathBuf, str::FromStr};
+
+use similar::{DiffTag, TextDiff};
+
+use crate::{build_overrides, build_walker, git::RevisionSource, sort::SortOrder, source};
+
+/// Number of unchanged lines shown around each change
+const CONTEXT_LINES: usize = 3;
+
+/// How changes are shown
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum DiffStyle {
+  /// Removed lines are shown above the lines which replaced them
+  #[default]
+  Unified,
+  /// The old version of each file is shown on the left, and the new version on the right
+  SideBySide,
+}
+impl FromStr for DiffStyle {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.trim().to_lowercase().as_str() {
+      "unified" => Ok(Self::Unified),
+      "side-by-side" => Ok(Self::SideBySide),
+      _ => Err(format!(
+        "Invalid diff style `{s}`. Expected `unified` or `side-by-side`"
+      )),
+    }
+  }
+}
+
+/// One version of a project
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiffSource {
+  /// The files in a directory
+  Directory(PathBuf),
+  /// The files as of a git revision (such as a tag or commit), in the repository containing the project
+  Revision(String),
+}
+impl FromStr for DiffSource {
+  type Err = String;
+  /// Treats `s` as a directory if it starts with `dir:`, and as a git revision if it starts with `rev:`.
+  ///
+  /// Otherwise, `s` is a directory if one exists at that path, and a git revision if it doesn't look like a path
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    if let Some(dir) = s.strip_prefix("dir:") {
+      return match Path::new(dir).is_dir() {
+        true => Ok(Self::Directory(PathBuf::from(dir))),
+        false => Err(format!("There's no directory at `{dir}`")),
+      };
+    }
+    if let Some(revision) = s.strip_prefix("rev:") {
+      return Ok(Self::Revision(revision.to_string()));
+    }
+    let path = PathBuf::from(s);
+    if path.is_dir() {
+      Ok(Self::Directory(path))
+    } else if s.starts_with(['.', '/', '~']) || s.ends_with('/') || s.contains('\\') {
+      Err(format!(
+        "There's no directory at `{s}`. Use `rev:{s}` if it's a git revision"
+      ))
+    } else {
+      Ok(Self::Revision(s.to_string()))
+    }
+  }
+}
+impl DiffSource {
+  /// Reads every file, with their paths relative to the root of the project.
+  ///
+  /// `root` is the directory of the project in the working tree, which revisions are read relative to.
+  /// Paths are filtered by `exclusions` and `inclusions`, and ordered by `sort`, in the same way as the walker
+  pub fn read(
+    &self,
+    root: &Path,
+    exclusions: &[String],
+    inclusions: &[String],
+    sort: Option<&SortOrder>,
+  ) -> Result<Vec<(PathBuf, Vec<u8>)>, String> {
+    match self {
+      Self::Directory(dir) => read_directory(dir, exclusions, inclusions, sort),
+      Self::Revision(revision) => {
+        let overrides = build_overrides(root, exclusions, inclusions)?;
+        let files = RevisionSource::new(root, revision, &overrides, sort)
+          .map_err(|err| format!("Unable to read revision `{revision}`: {err}"))?;
+        let files = source::read_all(files).map_err(|err| err.to_string())?;
+        Ok(relative_to(root, files))
+      }
+    }
+  }
+}
+
+/// Describes a comparison between the project and an older version of it
+#[derive(Debug, Clone)]
+pub struct Diff {
+  /// Old version of the project. The new version is the version being printed
+  pub from: DiffSource,
+  /// How changes are shown
+  pub style: DiffStyle,
+}
+impl Diff {
+  /// Returns the files which differ between [`Diff::from`] and the project at `root`
+  /// (as of `revision`, if given, otherwise the working tree), in the order of the project sorted by `sort`
+  pub fn changed_files(
+    &self,
+    root: &Path,
+    revision: Option<&str>,
+    exclusions: &[String],
+    inclusions: &[String],
+    sort: Option<&SortOrder>,
+  ) -> Result<Vec<FileDiff>, String> {
+    let new = match revision {
+      Some(revision) => DiffSource::Revision(revision.to_string()),
+      None => DiffSource::Directory(root.to_path_buf()),
+    };
+    let old = self.from.read(root, exclusions, inclusions, sort)?;
+    let new = new.read(root, exclusions, inclusions, sort)?;
+    Ok(changed_files(root, old, new))
+  }
+}
+
+/// A file which is different between two versions of a project
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileDiff {
+  /// Path of the file in the new version (or the old version, if it was deleted)
+  pub path: PathBuf,
+  /// Contents of the old version, or `None` if the file was added
+  pub old: Option<Vec<u8>>,
+  /// Contents of the new version, or `None` if the file was deleted
+  pub new: Option<Vec<u8>>,
+}
+
+/// Reads every file in the directory at `dir` (in the same way as the working tree), relative to `dir`.
+/// Fails if any of the globs are invalid
+pub fn read_directory(
+  dir: &Path,
+  exclusions: &[String],
+  inclusions: &[String],
+  sort: Option<&SortOrder>,
+) -> Result<Vec<(PathBuf, Vec<u8>)>, String> {
+  let (walker, _) = build_walker(dir, exclusions, inclusions, false, sort)?;
+  let files = walker
+    .filter_map(Result::ok)
+    .filter(|entry| entry.file_type().is_some_and(|f| f.is_file()))
+    .filter_map(|entry| {
+      let contents = fs::read(entry.path()).ok()?;
+      Some((entry.into_path(), contents))
+    })
+    .collect();
+  Ok(relative_to(dir, files))
+}
+
+/// Makes the paths of `files` relative to `root`
+fn relative_to(root: &Path, files: Vec<(PathBuf, Vec<u8>)>) -> Vec<(PathBuf, Vec<u8>)> {
+  files
+    .into_iter()
+    .map(|(path, contents)| {
+      let relative = path.strip_prefix(root).map(Path::to_path_buf);
+      (relative.unwrap_or(path), contents)
+    })
+    .collect()
+}
+
+/// Pairs up the files from each version of a project (by their relative paths), keeping the ones which changed.
+///
+/// Files are kept in the order of the new version, followed by deleted files.
+/// Paths are made relative to `root` again
+pub fn changed_files(
+  root: &Path,
+  old: Vec<(PathBuf, Vec<u8>)>,
+  new: Vec<(PathBuf, Vec<u8>)>,
+) -> Vec<FileDiff> {
+  let mut old_files = HashMap::with_capacity(old.len());
+  let mut old_order = Vec::with_capacity(old.len());
+  for (path, contents) in old {
+    old_order.push(path.clone());
+    old_files.insert(path, contents);
+  }
+  let mut changed = vec![];
+  for (path, contents) in new {
+    let old = old_files.remove(&path);
+    if old.as_ref() != Some(&contents) {
+      changed.push(FileDiff {
+        path: root.join(path),
+        old,
+        new: Some(contents),
+      });
+    }
+  }
+  for path in old_order {
+    if let Some(contents) = old_files.remove(&path) {
+      changed.push(FileDiff {
+        path: root.join(path),
+        old: Some(contents),
+        new: None,
+      });
+    }
+  }
+  changed
+}
+
+/// Returns whether `contents` look like a binary file rather than text.
+///
+/// In the same way as git, files are binary if there's a null byte near the start
+pub fn is_binary(contents: &[u8]) -> bool {
+  contents[..contents.len().min(8000)].contains(&0)
+}
+
+/// A line from one version of a file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DiffLine {
+  /// Index of the line in its version of the file
+  pub index: usize,
+  /// Whether the line was added or removed (rather than being unchanged)
+  pub changed: bool,
+}
+
+/// A row of the changes to a file
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiffRow {
+  /// Header at the start of each group of changes, such as `@@ -1,4 +1,5 @@`
+  Hunk(String),
+  /// A line from the old version, the new version, or both.
+  ///
+  /// In the unified style, unchanged lines have both sides, and changed lines have one
+  Lines {
+    /// Line from the old version
+    old: Option<DiffLine>,
+    /// Line from the new version
+    new: Option<DiffLine>,
+  },
+}
+
+/// Returns the rows showing the changes from `old` to `new`
+pub fn diff_rows(old: &str, new: &str, style: DiffStyle) -> Vec<DiffRow> {
+  let diff = TextDiff::from_lines(old, new);
+  let line = |index, changed| Some(DiffLine { index, changed });
+  let mut rows = vec![];
+  for group in diff.grouped_ops(CONTEXT_LINES) {
+    let (Some(first), Some(last)) = (group.first(), group.last()) else {
+      continue;
+    };
+    let old_range = first.old_range().start..last.old_range().end;
+    let new_range = first.new_range().start..last.new_range().end;
+    rows.push(DiffRow::Hunk(format!(
+      "@@ -{},{} +{},{} @@",
+      old_range.start + 1,
+      old_range.len(),
+      new_range.start + 1,
+      new_range.len()
+    )));
+    for op in group {
+      let (tag, old_range, new_range) = op.as_tag_tuple();
+      match (tag, style) {
+        (DiffTag::Equal, _) => {
+          rows.extend(old_range.zip(new_range).map(|(old, new)| DiffRow::Lines {
+            old: line(old, false),
+            new: line(new, false),
+          }));
+        }
+        (DiffTag::Replace, DiffStyle::SideBySide) => {
+          let count = old_range.len().max(new_range.len());
+          rows.extend((0..count).map(|i| DiffRow::Lines {
+            old: old_range.clone().nth(i).and_then(|index| line(index, true)),
+            new: new_range.clone().nth(i).and_then(|index| line(index, true)),
+          }));
+        }
+        // Removed lines, then added lines
+        _ => {
+          rows.extend(old_range.map(|index| DiffRow::Lines {
+            old: line(index, true),
+            new: None,
+          }));
+          rows.extend(new_range.map(|index| DiffRow::Lines {
+            old: None,
+            new: line(index, true),
+          }));
+        }
+      }
+    }
+  }
+  rows
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn pairing_files() {
+    let file = |path: &str, contents: &str| (PathBuf::from(path), contents.as_bytes().to_vec());
+    let old = vec![file("a", "1"), file("b", "2"), file("c", "3")];
+    let new = vec![file("d", "4"), file("a", "1"), file("b", "5")];
+    let changed = changed_files(Path::new("root"), old, new)
+      .into_iter()
+      .map(|diff| (diff.path, diff.old.is_some(), diff.new.is_some()))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      changed,
+      vec![
+        (PathBuf::from("root/d"), false, true),
+        (PathBuf::from("root/b"), true, true),
+        (PathBuf::from("root/c"), true, false),
+      ]
+    );
+  }
+  #[test]
+  fn parsing_sources() {
+    assert_eq!(
+      "src".parse(),
+      Ok(DiffSource::Directory(PathBuf::from("src")))
+    );
+    assert_eq!("v1.3".parse(), Ok(DiffSource::Revision("v1.3".to_string())));
+    assert_eq!(
+      "rev:src".parse(),
+      Ok(DiffSource::Revision("src".to_string()))
+    );
+    assert_eq!(
+      "dir:src".parse(),
+      Ok(DiffSource::Directory(PathBuf::from("src")))
+    );
+    // Typos in paths aren't treated as revisions
+    assert!("./old_versoin".parse::<DiffSource>().is_err());
+    assert!("dir:old_versoin".parse::<DiffSource>().is_err());
+  }
+  #[test]
+  fn detecting_binary_files() {
+    assert!(!is_binary(b"fn main() {}\n"));
+    assert!(is_binary(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"));
+    assert!(!is_binary(b""));
+  }
+  #[test]
+  fn building_rows() {
+    let old = "a\nb\nc\n";
+    let new = "a\nB\nc\nd\n";
+    let lines = |old: Option<(usize, bool)>, new: Option<(usize, bool)>| DiffRow::Lines {
+      old: old.map(|(index, changed)| DiffLine { index, changed }),
+      new: new.map(|(index, changed)| DiffLine { index, changed }),
+    };
+    assert_eq!(
+      diff_rows(old, new, DiffStyle::Unified),
+      vec![
+        DiffRow::Hunk("@@ -1,3 +1,4 @@".to_string()),
+        lines(Some((0, false)), Some((0, false))),
+        lines(Some((1, true)), None),
+        lines(None, Some((1, true))),
+        lines(Some((2, false)), Some((2, false))),
+        lines(None, Some((3, true))),
+      ]
+    );
+    assert_eq!(
+      diff_rows(old, new, DiffStyle::SideBySide)[2],
+      lines(Some((1, true)), Some((1, true)))
+    );
+  }
+}
@@ -1,7 +1,125 @@
 //! Contains [`Dimensions`] struct and implementations to simplify passing
 //! document dimensions where needed
 
-use printpdf::Mm;
+use std::str::FromStr;
+
+use printpdf::{Mm, Pt};
+
+/// Named paper sizes, or a custom size
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PaperSize {
+  /// 297mm by 420mm
+  A3,
+  /// 210mm by 297mm
+  A4,
+  /// 148mm by 210mm
+  A5,
+  /// 8.5in by 11in
+  Letter,
+  /// 8.5in by 14in
+  Legal,
+  /// 11in by 17in
+  Tabloid,
+  /// Custom width and height
+  Custom(Mm, Mm),
+}
+impl PaperSize {
+  /// Returns the (portrait) width and height of the paper
+  pub fn size(&self) -> (Mm, Mm) {
+    match *self {
+      Self::A3 => (Mm(297.0), Mm(420.0)),
+      Self::A4 => (Mm(210.0), Mm(297.0)),
+      Self::A5 => (Mm(148.0), Mm(210.0)),
+      Self::Letter => (inches(8.5), inches(11.0)),
+      Self::Legal => (inches(8.5), inches(14.0)),
+      Self::Tabloid => (inches(11.0), inches(17.0)),
+      Self::Custom(width, height) => (width, height),
+    }
+  }
+}
+fn inches(value: f32) -> Mm {
+  Mm(value * 25.4)
+}
+/// Parses a positive length with an optional `mm`, `in`, or `pt` suffix, returning the number and its unit
+fn parse_length(s: &str) -> Result<(f32, Option<&str>), String> {
+  let s = s.trim();
+  let (number, unit) = match s.find(|c: char| c.is_ascii_alphabetic()) {
+    Some(i) => (&s[..i], Some(&s[i..])),
+    None => (s, None),
+  };
+  let number = number
+    .trim()
+    .parse::<f32>()
+    .map_err(|_| format!("Invalid length `{s}`"))?;
+  if !number.is_finite() || number <= 0.0 {
+    return Err(format!(
+      "Invalid length `{s}`. Lengths must be greater than 0"
+    ));
+  }
+  match unit {
+    None | Some("mm" | "in" | "pt") => Ok((number, unit)),
+    Some(unit) => Err(format!(
+      "Unknown unit `{unit}`. Expected `mm`, `in`, or `pt`"
+    )),
+  }
+}
+fn to_mm(value: f32, unit: &str) -> Mm {
+  match unit {
+    "in" => inches(value),
+    "pt" => Pt(value).into(),
+    _ => Mm(value),
+  }
+}
+impl FromStr for PaperSize {
+  type Err = String;
+  /// Parses a named paper size (such as `a4` or `letter`), or a custom `WxH` size.
+  ///
+  /// Custom sizes may have a unit (`mm`, `in`, or `pt`) after each length, or just after the height.
+  /// Lengths without a unit are in millimetres
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_ascii_lowercase().as_str() {
+      "a3" => Ok(Self::A3),
+      "a4" => Ok(Self::A4),
+      "a5" => Ok(Self::A5),
+      "letter" => Ok(Self::Letter),
+      "legal" => Ok(Self::Legal),
+      "tabloid" => Ok(Self::Tabloid),
+      custom => {
+        let (width, height) = custom.split_once('x').ok_or_else(|| {
+          format!("Unknown paper size `{s}`. Expected a4, a3, a5, letter, legal, tabloid, or WxH")
+        })?;
+        let (width, width_unit) = parse_length(width)?;
+        let (height, height_unit) = parse_length(height)?;
+        let height_unit = height_unit.unwrap_or("mm");
+        let width_unit = width_unit.unwrap_or(height_unit);
+        Ok(Self::Custom(
+          to_mm(width, width_unit),
+          to_mm(height, height_unit),
+        ))
+      }
+    }
+  }
+}
+
+/// Which side of each column the gutter is on
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum GutterSide {
+  /// Between the left edge of the column and the text
+  #[default]
+  Left,
+  /// After the text
+  Right,
+}
+impl FromStr for GutterSide {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.trim().to_ascii_lowercase().as_str() {
+      "left" => Ok(Self::Left),
+      "right" => Ok(Self::Right),
+      _ => Err(format!("Invalid side `{s}`. Expected `left` or `right`")),
+    }
+  }
+}
 
 /// Stores the dimensions of the page
 #[allow(missing_docs)]
@@ -11,8 +129,25 @@ pub struct Dimensions {
   pub height: Mm,
   pub margin_left: Mm,
   pub margin_right: Mm,
+  /// Distance from the top of the page to the body
   pub margin_top: Mm,
   pub margin_bottom: Mm,
+  /// Height of the header region, which is the part of the top margin directly above the body
+  pub header_height: Mm,
+  /// Height of the footer region, between the body and the bottom margin
+  pub footer_height: Mm,
+  /// Whether the margins are mirrored for duplex printing.
+  /// If so, `margin_left` is the inner (binding) margin, and `margin_right` is the outer margin,
+  /// so they swap on even pages
+  pub mirror_margins: bool,
+  /// Number of columns the body is split into
+  pub columns: u32,
+  /// Space between adjacent columns
+  pub column_gap: Mm,
+  /// Width of the gutter beside each column (such as for blame annotations), which is taken from the text
+  pub gutter_width: Mm,
+  /// Side of each column the gutter is on
+  pub gutter_side: GutterSide,
 }
 impl Default for Dimensions {
   /// Initialises a default `Dimensions`.
@@ -25,6 +160,13 @@ impl Default for Dimensions {
       margin_bottom: Mm(5.0),
       margin_left: Mm(10.0),
       margin_right: Mm(10.0),
+      header_height: Mm(15.0),
+      footer_height: Mm(0.0),
+      mirror_margins: false,
+      columns: 1,
+      column_gap: Mm(5.0),
+      gutter_width: Mm(0.0),
+      gutter_side: GutterSide::Left,
     }
   }
 }
@@ -37,7 +179,28 @@ impl Dimensions {
       ..Default::default()
     }
   }
-  /// Initialises a new [`Dimensions`]
+  /// Initialises new [`Dimensions`] with default margins, and the size of the given `paper`
+  pub fn from_paper_size(paper: PaperSize) -> Self {
+    let (width, height) = paper.size();
+    Self::new_default_margins(width, height)
+  }
+  /// Rotates the page into landscape orientation, so that it's wider than it is tall
+  pub fn landscape(self) -> Self {
+    if self.is_landscape() {
+      self
+    } else {
+      Self {
+        width: self.height,
+        height: self.width,
+        ..self
+      }
+    }
+  }
+  /// Returns `true` if the page is wider than it is tall
+  pub fn is_landscape(&self) -> bool {
+    self.width > self.height
+  }
+  /// Initialises a new [`Dimensions`], with the default header and footer heights
   pub fn new(
     width: Mm,
     height: Mm,
@@ -53,6 +216,7 @@ impl Dimensions {
       margin_right,
       margin_top,
       margin_bottom,
+      ..Default::default()
     }
   }
   /// Computes the maximum text width (in millimetres)
@@ -62,6 +226,171 @@ impl Dimensions {
   /// Computes the maximum height that all the lines of text can be
   /// on a single page
   pub fn max_text_height(&self) -> Mm {
-    self.height - self.margin_top - self.margin_bottom
+    self.body_top() - self.body_bottom()
+  }
+  /// Vertical position of the top of the body (below the header)
+  pub fn body_top(&self) -> Mm {
+    self.height - self.margin_top
+  }
+  /// Vertical position of the top of the header region. This is kept on the page if the header is taller than the top margin
+  pub fn header_top(&self) -> Mm {
+    self.height - (self.margin_top - self.header_height).max(Mm(0.0))
+  }
+  /// Makes the header region at least `height` tall, moving the body down (rather than the header up) if it needs to grow
+  pub fn with_header_space(mut self, height: Mm) -> Self {
+    if height > self.header_height {
+      self.margin_top += height - self.header_height;
+      self.header_height = height;
+    }
+    self
+  }
+  /// Vertical position of the bottom of the body (above the footer)
+  pub fn body_bottom(&self) -> Mm {
+    self.margin_bottom + self.footer_height
+  }
+  /// Returns the width of the text in a column of the body, excluding its gutter
+  pub fn column_width(&self) -> Mm {
+    let columns = self.columns.max(1);
+    (self.max_text_width() - self.column_gap * (columns - 1) as f32) / columns as f32
+      - self.gutter_width
+  }
+  /// Returns the distance from the left edge of the page to the text of the given column (starting at 0)
+  pub fn column_left(&self, column: u32) -> Mm {
+    let gutter = match self.gutter_side {
+      GutterSide::Left => self.gutter_width,
+      GutterSide::Right => Mm(0.0),
+    };
+    self.margin_left
+      + (self.column_width() + self.gutter_width + self.column_gap) * column as f32
+      + gutter
+  }
+  /// Returns the distance from the left edge of the page to the gutter of the given column (starting at 0)
+  pub fn gutter_left(&self, column: u32) -> Mm {
+    match self.gutter_side {
+      GutterSide::Left => self.column_left(column) - self.gutter_width,
+      GutterSide::Right => self.column_left(column) + self.column_width(),
+    }
+  }
+  /// Returns the maximum number of lines of text with the given font size that fit in a column of the body
+  pub fn max_line_count(&self, font_size: f32) -> u32 {
+    (self.max_text_height().into_pt().0 / (font_size * 1.2)).floor() as u32
+  }
+  /// Returns the dimensions of the given page (starting at 1).
+  /// With mirrored margins, even pages have their left and right margins swapped
+  pub fn for_page(&self, page: usize) -> Self {
+    let mut dimensions = self.clone();
+    if self.mirror_margins && page.is_multiple_of(2) {
+      dimensions.margin_left = self.margin_right;
+      dimensions.margin_right = self.margin_left;
+    }
+    dimensions
+  }
+  /// Returns how far the body of the given page (starting at 1) must be moved right,
+  /// if it was laid out using these dimensions
+  pub fn horizontal_offset(&self, page: usize) -> Mm {
+    self.for_page(page).margin_left - self.margin_left
+  }
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parse_paper_sizes() {
+    assert_eq!("A4".parse::<PaperSize>(), Ok(PaperSize::A4));
+    assert_eq!("letter".parse::<PaperSize>(), Ok(PaperSize::Letter));
+    assert_eq!(
+      "100x200".parse::<PaperSize>(),
+      Ok(PaperSize::Custom(Mm(100.0), Mm(200.0)))
+    );
+    // A single unit applies to both lengths
+    assert_eq!(
+      "8.5x11in".parse::<PaperSize>().unwrap().size(),
+      PaperSize::Letter.size()
+    );
+    assert_eq!(
+      "216mmx11in".parse::<PaperSize>().unwrap().size(),
+      (Mm(216.0), PaperSize::Letter.size().1)
+    );
+    assert!("612x792pt".parse::<PaperSize>().is_ok());
+    assert!("a10".parse::<PaperSize>().is_err());
+    assert!("10x20furlongs".parse::<PaperSize>().is_err());
+    // Lengths must be positive and finite
+    assert!("0x0".parse::<PaperSize>().is_err());
+    assert!("-10x20".parse::<PaperSize>().is_err());
+    assert!("10x-20mm".parse::<PaperSize>().is_err());
+    assert!("nanxnan".parse::<PaperSize>().is_err());
+    assert!(
+      format!("{}x10", "9".repeat(40))
+        .parse::<PaperSize>()
+        .is_err()
+    );
+  }
+  #[test]
+  fn landscape() {
+    let dimensions = Dimensions::from_paper_size(PaperSize::A4).landscape();
+    assert_eq!(dimensions.width, Mm(297.0));
+    assert_eq!(dimensions.height, Mm(210.0));
+    // Already landscape pages are left as they are
+    assert_eq!(dimensions.clone().landscape().width, dimensions.width);
+  }
+  #[test]
+  fn mirrored_margins() {
+    let dimensions = Dimensions {
+      margin_left: Mm(25.0),
+      margin_right: Mm(10.0),
+      mirror_margins: true,
+      ..Default::default()
+    };
+    assert_eq!(dimensions.for_page(1).margin_left, Mm(25.0));
+    assert_eq!(dimensions.for_page(2).margin_left, Mm(10.0));
+    assert_eq!(dimensions.for_page(2).margin_right, Mm(25.0));
+    assert_eq!(dimensions.horizontal_offset(1), Mm(0.0));
+    assert_eq!(dimensions.horizontal_offset(2), Mm(-15.0));
+  }
+  #[test]
+  fn header_space() {
+    let dimensions = Dimensions::default();
+    assert_eq!(dimensions.body_top(), Mm(277.0));
+    assert_eq!(dimensions.header_top(), Mm(292.0));
+    // A taller header keeps its top where it is, and pushes the body down
+    let taller = dimensions.clone().with_header_space(Mm(20.0));
+    assert_eq!(taller.header_top(), Mm(292.0));
+    assert_eq!(taller.body_top(), Mm(272.0));
+    // A shorter header doesn't change anything
+    let shorter = dimensions.clone().with_header_space(Mm(5.0));
+    assert_eq!(shorter.body_top(), dimensions.body_top());
+  }
+  #[test]
+  fn columns() {
+    let dimensions = Dimensions {
+      columns: 2,
+      column_gap: Mm(10.0),
+      ..Default::default()
+    };
+    assert_eq!(dimensions.column_width(), Mm(90.0));
+    assert_eq!(dimensions.column_left(0), Mm(10.0));
+    assert_eq!(dimensions.column_left(1), Mm(110.0));
+    assert_eq!(Dimensions::default().column_width(), Mm(190.0));
+  }
+  #[test]
+  fn gutters() {
+    let dimensions = Dimensions {
+      columns: 2,
+      column_gap: Mm(10.0),
+      gutter_width: Mm(20.0),
+      ..Default::default()
+    };
+    assert_eq!(dimensions.column_width(), Mm(70.0));
+    assert_eq!(dimensions.gutter_left(0), Mm(10.0));
+    assert_eq!(dimensions.column_left(0), Mm(30.0));
+    assert_eq!(dimensions.gutter_left(1), Mm(110.0));
+    assert_eq!(dimensions.column_left(1), Mm(130.0));
+    let dimensions = Dimensions {
+      gutter_side: GutterSide::Right,
+      ..dimensions
+    };
+    assert_eq!(dimensions.column_left(1), Mm(110.0));
+    assert_eq!(dimensions.gutter_left(1), Mm(180.0));
   }
 }
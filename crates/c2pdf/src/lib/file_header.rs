@@ -0,0 +1,88 @@
+//! Contains [`FileHeader`], a block of information shown at the top of the first page of each file
+
+use std::{fs, path::Path};
+
+use crate::{
+  git::{LastCommit, LastCommits},
+  helpers::{format_date_time, format_size},
+};
+
+/// Information about a file, shown above its contents
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileHeader {
+  /// Name of the syntax used to highlight the file
+  pub language: String,
+  /// Size of the file in bytes
+  pub size: u64,
+  /// Number of lines in the file
+  pub lines: usize,
+  /// Time the file was last modified, if the platform supports it
+  pub modified: Option<String>,
+  /// Most recent commit which changed the file, if it's in a git repository
+  pub last_commit: Option<LastCommit>,
+}
+impl FileHeader {
+  /// Initialises a new [`FileHeader`] for a file containing `contents`, which is highlighted as `language`
+  pub fn new(language: String, contents: &[u8]) -> Self {
+    // The last line doesn't always end with a newline
+    let lines = contents.split(|&byte| byte == b'\n').count()
+      - usize::from(contents.is_empty() || contents.ends_with(b"\n"));
+    Self {
+      language,
+      size: contents.len() as u64,
+      lines,
+      modified: None,
+      last_commit: None,
+    }
+  }
+  /// Adds the modification time and last commit (looked up in `last_commits`) of the file at `path`
+  pub fn with_disk_info(mut self, path: &Path, last_commits: &LastCommits) -> Self {
+    self.modified = fs::metadata(path)
+      .and_then(|metadata| metadata.modified())
+      .ok()
+      .map(format_date_time);
+    self.last_commit = last_commits.get(path);
+    self
+  }
+  /// Returns the lines of text to show
+  pub fn text_lines(&self) -> Vec<String> {
+    let plural = if self.lines == 1 { "" } else { "s" };
+    let mut lines = vec![format!(
+      "{}, {} line{plural}, {}",
+      self.language,
+      self.lines,
+      format_size(self.size)
+    )];
+    if let Some(modified) = &self.modified {
+      lines.push(format!("Modified {modified}"));
+    }
+    if let Some(LastCommit { hash, author, date }) = &self.last_commit {
+      lines.push(format!("Last commit {hash} by {author} on {date}"));
+    }
+    lines
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn text_lines() {
+    let header = FileHeader {
+      last_commit: Some(LastCommit {
+        hash: "abc1234".to_string(),
+        author: "Jane Doe".to_string(),
+        date: "2024-01-01".to_string(),
+      }),
+      ..FileHeader::new("Rust".to_string(), b"fn main() {}")
+    };
+    assert_eq!(
+      header.text_lines(),
+      vec![
+        "Rust, 1 line, 12 B",
+        "Last commit abc1234 by Jane Doe on 2024-01-01"
+      ]
+    );
+  }
+}
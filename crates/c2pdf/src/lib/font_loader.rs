@@ -1,4 +1,4 @@
-//! Functions for loading fonts from the system fonts, a path, or using the bundled `Helvetica` font
+//! Functions for loading fonts from the system fonts, a path, or using the bundled `Helvetica` or `DejaVu Sans Mono` fonts
 
 #[cfg(feature = "font-loading")]
 use font_kit::{family_name::FamilyName, properties::Properties, source::SystemSource};
@@ -38,6 +38,11 @@ fn bundled_font_bytes() -> Arc<Vec<u8>> {
   let bytes = include_bytes!("../../fonts/Helvetica.ttf").to_vec();
   Arc::new(bytes)
 }
+/// Loads bytes from bundled monospace font
+fn bundled_monospace_font_bytes() -> Arc<Vec<u8>> {
+  let bytes = include_bytes!("../../fonts/DejaVuSansMono.ttf").to_vec();
+  Arc::new(bytes)
+}
 fn is_path(s: &str) -> bool {
   PathBuf::from(s).extension().is_some() || s.len() > 31 || s.starts_with('.')
 }
@@ -52,6 +57,16 @@ pub enum FontLoaded {
 }
 /// Loads a given font - falling back to the bundled font if loading from the system, or from the given path fails
 pub fn load_font(name_or_path: Option<String>) -> (Arc<Vec<u8>>, FontLoaded) {
+  load_font_or(name_or_path, bundled_font_bytes)
+}
+/// Loads a given font - falling back to the bundled monospace font if loading from the system, or from the given path fails
+pub fn load_monospace_font(name_or_path: Option<String>) -> (Arc<Vec<u8>>, FontLoaded) {
+  load_font_or(name_or_path, bundled_monospace_font_bytes)
+}
+fn load_font_or(
+  name_or_path: Option<String>,
+  fallback: fn() -> Arc<Vec<u8>>,
+) -> (Arc<Vec<u8>>, FontLoaded) {
   if let Some(name_or_path) = name_or_path {
     if let Ok(data) = {
       if is_path(&name_or_path) {
@@ -62,9 +77,9 @@ pub fn load_font(name_or_path: Option<String>) -> (Arc<Vec<u8>>, FontLoaded) {
     } {
       (data, FontLoaded::SuccessProvided)
     } else {
-      (bundled_font_bytes(), FontLoaded::FailProvided)
+      (fallback(), FontLoaded::FailProvided)
     }
   } else {
-    (bundled_font_bytes(), FontLoaded::NoneProvided)
+    (fallback(), FontLoaded::NoneProvided)
   }
 }
@@ -0,0 +1,506 @@
+//! Functions for reading information from the git repository containing the files being processed
+//!
+//! These always return `None` if the `git` feature is disabled
+
+#[cfg(feature = "git")]
+use std::{
+  collections::{HashMap, HashSet},
+  sync::OnceLock,
+  time::{Duration, UNIX_EPOCH},
+};
+use std::{
+  io,
+  path::{Path, PathBuf},
+};
+
+use ignore::overrides::Override;
+
+#[cfg(feature = "git")]
+use crate::sort::EntryMetadata;
+use crate::{
+  sort::SortOrder,
+  source::{Source, SourceFile},
+};
+
+#[cfg(feature = "git")]
+use crate::helpers::format_date;
+
+/// The most recent commit which changed a file
+#[derive(Debug, Clone, PartialEq)]
+pub struct LastCommit {
+  /// Abbreviated hash of the commit
+  pub hash: String,
+  /// Name of the author of the commit
+  pub author: String,
+  /// Date of the commit, as `YYYY-MM-DD`
+  pub date: String,
+}
+
+/// Returns the abbreviated hash of the commit checked out in the repository containing `path`
+pub fn head_commit(path: &Path) -> Option<String> {
+  #[cfg(not(feature = "git"))]
+  {
+    _ = path;
+    None
+  }
+  #[cfg(feature = "git")]
+  {
+    let repo = git2::Repository::discover(path).ok()?;
+    let commit = repo.head().ok()?.peel_to_commit().ok()?;
+    Some(short_hash(commit.id()))
+  }
+}
+
+/// Returns the name of the branch checked out in the repository containing `path`
+pub fn head_branch(path: &Path) -> Option<String> {
+  #[cfg(not(feature = "git"))]
+  {
+    _ = path;
+    None
+  }
+  #[cfg(feature = "git")]
+  {
+    let repo = git2::Repository::discover(path).ok()?;
+    let head = repo.head().ok()?;
+    if !head.is_branch() {
+      return None;
+    }
+    head.shorthand().map(str::to_string)
+  }
+}
+
+/// The most recent commit reachable from `HEAD` which changed each file, in the repository containing the
+/// first file looked up.
+///
+/// The history is only walked once, the first time a file is looked up, so this can be shared between threads
+#[derive(Debug, Default)]
+pub struct LastCommits {
+  /// Working directory of the repository, and the last commit of each file relative to it
+  #[cfg(feature = "git")]
+  commits: OnceLock<Option<(PathBuf, HashMap<PathBuf, LastCommit>)>>,
+}
+impl LastCommits {
+  /// Returns the most recent commit which changed the file at `path`.
+  ///
+  /// The first call walks the history of the repository, so it can be slow for repositories with many commits
+  pub fn get(&self, path: &Path) -> Option<LastCommit> {
+    #[cfg(not(feature = "git"))]
+    {
+      _ = path;
+      None
+    }
+    #[cfg(feature = "git")]
+    {
+      let (workdir, commits) = self
+        .commits
+        .get_or_init(|| {
+          let repo = git2::Repository::discover(path).ok()?;
+          let workdir = repo.workdir()?.canonicalize().ok()?;
+          Some((workdir, last_commits(&repo).ok()?))
+        })
+        .as_ref()?;
+      commits.get(&relative_to(workdir, path)?).cloned()
+    }
+  }
+}
+
+/// Finds the most recent commit which changed each file in `HEAD`, with a single walk of the history
+#[cfg(feature = "git")]
+fn last_commits(repo: &git2::Repository) -> Result<HashMap<PathBuf, LastCommit>, git2::Error> {
+  let head = repo.head()?.peel_to_tree()?;
+  // Files which aren't in `HEAD` are never looked up, so the walk stops once every file in it is found
+  let mut remaining = HashSet::new();
+  head.walk(git2::TreeWalkMode::PreOrder, |dir, entry| {
+    if entry.kind() == Some(git2::ObjectType::Blob) {
+      remaining.insert(Path::new(dir).join(entry.name().unwrap_or_default()));
+    }
+    git2::TreeWalkResult::Ok
+  })?;
+  let mut commits = HashMap::new();
+  let mut revwalk = repo.revwalk()?;
+  revwalk.push_head()?;
+  revwalk.set_sorting(git2::Sort::TIME)?;
+  for id in revwalk {
+    if remaining.is_empty() {
+      break;
+    }
+    let commit = repo.find_commit(id?)?;
+    let tree = commit.tree()?;
+    let changed_from = |parent: Option<&git2::Tree>| {
+      let diff = repo.diff_tree_to_tree(parent, Some(&tree), None)?;
+      Ok::<_, git2::Error>(
+        diff
+          .deltas()
+          .filter_map(|delta| delta.new_file().path().or(delta.old_file().path()))
+          .map(Path::to_path_buf)
+          .collect::<HashSet<_>>(),
+      )
+    };
+    // A file was changed if it's different in every parent (or there are no parents)
+    let mut changed = None::<HashSet<PathBuf>>;
+    for parent in commit.parents() {
+      let paths = changed_from(Some(&parent.tree()?))?;
+      changed = Some(match changed {
+        Some(changed) => changed.intersection(&paths).cloned().collect(),
+        None => paths,
+      });
+    }
+    let changed = match changed {
+      Some(changed) => changed,
+      None => changed_from(None)?,
+    };
+    let changed = changed
+      .into_iter()
+      .filter(|path| remaining.remove(path))
+      .collect::<Vec<_>>();
+    if changed.is_empty() {
+      continue;
+    }
+    let time = UNIX_EPOCH + Duration::from_secs(commit.time().seconds().max(0) as u64);
+    let last_commit = LastCommit {
+      hash: short_hash(commit.id()),
+      author: commit.author().name().unwrap_or_default().to_string(),
+      date: format_date(time),
+    };
+    for path in changed {
+      commits.insert(path, last_commit.clone());
+    }
+  }
+  Ok(commits)
+}
+
+/// Returns the abbreviated hash of the commit that `revision` (such as a tag or branch) refers to,
+/// in the repository containing `path`
+pub fn revision_commit(path: &Path, revision: &str) -> Option<String> {
+  #[cfg(not(feature = "git"))]
+  {
+    _ = (path, revision);
+    None
+  }
+  #[cfg(feature = "git")]
+  {
+    let repo = git2::Repository::discover(path).ok()?;
+    let commit = repo.revparse_single(revision).ok()?.peel_to_commit().ok()?;
+    Some(short_hash(commit.id()))
+  }
+}
+
+/// Returns the paths of the files below `root` which are in the index of the repository containing it
+/// (in other words, the files tracked by git), joined onto `root` in the same way as the walker.
+///
+/// Returns `None` if `root` isn't in a git repository
+pub fn tracked_files(root: &Path) -> Option<Vec<PathBuf>> {
+  #[cfg(not(feature = "git"))]
+  {
+    _ = root;
+    None
+  }
+  #[cfg(feature = "git")]
+  {
+    let repo = git2::Repository::discover(root).ok()?;
+    let relative_root = relative_path(&repo, root)?;
+    let index = repo.index().ok()?;
+    let files = index
+      .iter()
+      .filter_map(|entry| {
+        let path = PathBuf::from(String::from_utf8_lossy(&entry.path).as_ref());
+        let below_root = path.strip_prefix(&relative_root).ok()?;
+        Some(root.join(below_root))
+      })
+      .collect();
+    Some(files)
+  }
+}
+
+/// Returns the blame annotation of each line of the file at `path` containing `contents`:
+/// the abbreviated hash of the commit which last changed the line, and the initials of its author.
+///
+/// Lines are blamed as of `revision` if given, otherwise the working tree.
+/// Lines which haven't been committed have an empty annotation
+pub fn blame(path: &Path, contents: &[u8], revision: Option<&str>) -> Option<Vec<String>> {
+  #[cfg(not(feature = "git"))]
+  {
+    _ = (path, contents, revision);
+    None
+  }
+  #[cfg(feature = "git")]
+  {
+    let repo = git2::Repository::discover(path).ok()?;
+    let relative = relative_path(&repo, path)?;
+    let mut options = git2::BlameOptions::new();
+    if let Some(revision) = revision {
+      let commit = repo.revparse_single(revision).ok()?.peel_to_commit().ok()?;
+      options.newest_commit(commit.id());
+    }
+    // Blaming the contents (rather than the committed file) accounts for uncommitted changes
+    let committed = repo.blame_file(&relative, Some(&mut options)).ok()?;
+    let blame = committed.blame_buffer(contents).ok()?;
+    let lines = contents.split(|&byte| byte == b'\n').count();
+    // Hunks from blaming a buffer don't have signatures, so the author is read from each commit
+    let mut authors = HashMap::new();
+    let annotations = (1..=lines)
+      .map(|line| {
+        let Some(id) = blame
+          .get_line(line)
+          .map(|hunk| hunk.final_commit_id())
+          .filter(|id| !id.is_zero())
+        else {
+          return String::new();
+        };
+        let author = authors.entry(id).or_insert_with(|| {
+          repo
+            .find_commit(id)
+            .map(|commit| initials(&String::from_utf8_lossy(commit.author().name_bytes())))
+            .unwrap_or_default()
+        });
+        format!("{} {author}", short_hash(id))
+      })
+      .collect();
+    Some(annotations)
+  }
+}
+
+/// The files below a directory as of a git revision, read directly from the repository without checking it out.
+///
+/// Files are in the same order as the walker (files before directories), with their paths below the directory.
+/// Git doesn't store modification times, so [`SortOrder::Modified`] orders entries by name.
+/// Paths ignored by the overrides are skipped, as are hidden files, symbolic links, and submodules.
+/// Only the list of files is read up front, and the contents of each file are read when it's needed
+pub struct RevisionSource {
+  #[cfg(feature = "git")]
+  repo: git2::Repository,
+  /// Path and ID of each file which hasn't been read yet
+  #[cfg(feature = "git")]
+  blobs: std::vec::IntoIter<(PathBuf, git2::Oid)>,
+}
+impl RevisionSource {
+  /// Lists the files below `root` as of `revision`, in the repository containing `root`.
+  /// Paths ignored by `overrides` are skipped, and entries in each directory are ordered by `sort` (by name if not given)
+  pub fn new(
+    root: &Path,
+    revision: &str,
+    overrides: &Override,
+    sort: Option<&SortOrder>,
+  ) -> Result<Self, String> {
+    #[cfg(not(feature = "git"))]
+    {
+      _ = (root, revision, overrides, sort);
+      Err("Reading git revisions requires the `git` feature".to_string())
+    }
+    #[cfg(feature = "git")]
+    {
+      let to_string = |err: git2::Error| err.message().to_string();
+      let repo = git2::Repository::discover(root).map_err(to_string)?;
+      let workdir = repo
+        .workdir()
+        .ok_or("The repository doesn't have a working directory")?
+        .canonicalize()
+        .map_err(|err| err.to_string())?;
+      let absolute_root = root.canonicalize().map_err(|err| err.to_string())?;
+      let relative_root = absolute_root
+        .strip_prefix(&workdir)
+        .map_err(|err| err.to_string())?;
+      let mut blobs = vec![];
+      {
+        let mut tree = repo
+          .revparse_single(revision)
+          .and_then(|object| object.peel_to_commit())
+          .and_then(|commit| commit.tree())
+          .map_err(to_string)?;
+        if !relative_root.as_os_str().is_empty() {
+          tree = tree
+            .get_path(relative_root)
+            .and_then(|entry| entry.to_object(&repo))
+            .and_then(|object| object.peel_to_tree())
+            .map_err(to_string)?;
+        }
+        let sort = sort.cloned().unwrap_or(SortOrder::Name).with_root(root);
+        collect_blobs(&repo, &tree, root, overrides, &sort, &mut blobs).map_err(to_string)?;
+      }
+      Ok(Self {
+        repo,
+        blobs: blobs.into_iter(),
+      })
+    }
+  }
+}
+impl Source for RevisionSource {
+  fn next_file(&mut self) -> Option<io::Result<SourceFile>> {
+    #[cfg(not(feature = "git"))]
+    {
+      None
+    }
+    #[cfg(feature = "git")]
+    {
+      let (path, id) = self.blobs.next()?;
+      let file = self
+        .repo
+        .find_blob(id)
+        .map(|blob| SourceFile::from_bytes(path, blob.content().to_vec()))
+        .map_err(io::Error::other);
+      Some(file)
+    }
+  }
+}
+
+/// Adds the path and ID of every file in `tree` (which is at `dir`) and its subtrees to `blobs`
+#[cfg(feature = "git")]
+fn collect_blobs(
+  repo: &git2::Repository,
+  tree: &git2::Tree,
+  dir: &Path,
+  overrides: &Override,
+  sort: &SortOrder,
+  blobs: &mut Vec<(PathBuf, git2::Oid)>,
+) -> Result<(), git2::Error> {
+  /// File mode of symbolic links
+  const SYMLINK: i32 = 0o120000;
+  let odb = repo.odb()?;
+  // Only the headers of blobs are read to find their sizes, and directories don't have a size
+  let metadata = |entry: &git2::TreeEntry| EntryMetadata {
+    size: match entry.kind() {
+      Some(git2::ObjectType::Blob) => odb
+        .read_header(entry.id())
+        .map_or(0, |(size, _)| size as u64),
+      _ => 0,
+    },
+    modified: None,
+  };
+  let mut entries = tree
+    .iter()
+    .map(|entry| {
+      let path = dir.join(entry.name().unwrap_or_default());
+      let metadata = metadata(&entry);
+      (path, metadata, entry)
+    })
+    .collect::<Vec<_>>();
+  entries.sort_by(|(a, a_metadata, _), (b, b_metadata, _)| {
+    sort.compare_with(a, b, |path| match path == a {
+      true => *a_metadata,
+      false => *b_metadata,
+    })
+  });
+  let (subtrees, files): (Vec<_>, Vec<_>) = entries
+    .into_iter()
+    .map(|(_, _, entry)| entry)
+    .filter(|entry| entry.name().is_some_and(|name| !name.starts_with('.')))
+    .filter(|entry| {
+      matches!(
+        entry.kind(),
+        Some(git2::ObjectType::Blob | git2::ObjectType::Tree)
+      )
+    })
+    .partition(|entry| entry.kind() == Some(git2::ObjectType::Tree));
+  for entry in files {
+    let path = dir.join(entry.name().unwrap_or_default());
+    if entry.filemode() == SYMLINK || overrides.matched(&path, false).is_ignore() {
+      continue;
+    }
+    blobs.push((path, entry.id()));
+  }
+  for entry in subtrees {
+    let path = dir.join(entry.name().unwrap_or_default());
+    if overrides.matched(&path, true).is_ignore() {
+      continue;
+    }
+    let subtree = entry.to_object(repo)?.peel_to_tree()?;
+    collect_blobs(repo, &subtree, &path, overrides, sort, blobs)?;
+  }
+  Ok(())
+}
+
+/// Returns the path of `path` relative to the working directory of `repo`.
+/// `path` doesn't need to exist, as long as its parent directory does
+#[cfg(feature = "git")]
+fn relative_path(repo: &git2::Repository, path: &Path) -> Option<PathBuf> {
+  relative_to(&repo.workdir()?.canonicalize().ok()?, path)
+}
+
+/// Returns the path of `path` relative to `workdir`, which must be canonical
+#[cfg(feature = "git")]
+fn relative_to(workdir: &Path, path: &Path) -> Option<PathBuf> {
+  let absolute = path
+    .canonicalize()
+    .or_else(|_| {
+      let parent = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty());
+      let parent = parent.unwrap_or(Path::new(".")).canonicalize()?;
+      Ok::<_, std::io::Error>(parent.join(path.file_name().unwrap_or_default()))
+    })
+    .ok()?;
+  absolute.strip_prefix(workdir).ok().map(Path::to_path_buf)
+}
+
+/// Returns the initials of a name (up to 3), such as `JD` for `Jane Doe`
+#[cfg(feature = "git")]
+fn initials(name: &str) -> String {
+  name
+    .split_whitespace()
+    .filter_map(|word| word.chars().next())
+    .flat_map(char::to_uppercase)
+    .take(3)
+    .collect()
+}
+
+/// Abbreviates a commit hash
+#[cfg(feature = "git")]
+fn short_hash(id: git2::Oid) -> String {
+  let mut hash = id.to_string();
+  hash.truncate(7);
+  hash
+}
+
+#[cfg(all(test, feature = "git"))]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn author_initials() {
+    assert_eq!(initials("Jane Doe"), "JD");
+    assert_eq!(initials("jane"), "J");
+    assert_eq!(initials("Jane Mary Ann Doe"), "JMA");
+    assert_eq!(initials(""), "");
+  }
+  #[test]
+  fn finding_last_commits() {
+    let dir = std::env::temp_dir().join(format!("c2pdf-git-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let repo = git2::Repository::init(&dir).unwrap();
+    // Commits a tree with the given files, at the given time
+    let commit = |files: &[(&str, &str)], seconds: i64, parents: &[git2::Oid]| {
+      let mut builder = repo.treebuilder(None).unwrap();
+      for (name, contents) in files {
+        let blob = repo.blob(contents.as_bytes()).unwrap();
+        builder.insert(name, blob, 0o100644).unwrap();
+      }
+      let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+      let signature =
+        git2::Signature::new("Jane Doe", "jane@example.com", &git2::Time::new(seconds, 0)).unwrap();
+      let parents = parents
+        .iter()
+        .map(|id| repo.find_commit(*id).unwrap())
+        .collect::<Vec<_>>();
+      let parents = parents.iter().collect::<Vec<_>>();
+      repo
+        .commit(None, &signature, &signature, "", &tree, &parents)
+        .unwrap()
+    };
+    let root = commit(&[("x.txt", "1")], 100, &[]);
+    let added = commit(&[("x.txt", "1"), ("a.txt", "a")], 200, &[root]);
+    // A more recent commit on another branch, which doesn't have `a.txt`
+    let other = commit(&[("x.txt", "2")], 300, &[root]);
+    let merge = commit(&[("x.txt", "2"), ("a.txt", "a")], 400, &[added, other]);
+    repo.set_head_detached(merge).unwrap();
+    for (name, contents) in [("x.txt", "2"), ("a.txt", "a")] {
+      std::fs::write(dir.join(name), contents).unwrap();
+    }
+
+    let last_commits = LastCommits::default();
+    let hash = |path: &str| last_commits.get(&dir.join(path)).map(|commit| commit.hash);
+    assert_eq!(hash("a.txt"), Some(short_hash(added)));
+    assert_eq!(hash("x.txt"), Some(short_hash(other)));
+    assert_eq!(hash("missing.txt"), None);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -0,0 +1,477 @@
+//! Contains [`HeaderFooter`], which describes the text to put in the header and footer of every page.
+//!
+//! Text can contain template variables (such as `{page}`), which are filled in once every page
+//! has been generated
+
+use std::{path::Path, time::SystemTime};
+
+use printpdf::{FontId, Line, LinePoint, Mm, Op, Point, Pt, TextMatrix, color};
+
+use crate::{dimensions::Dimensions, git, helpers::format_date, text_manipulation::TextWrapper};
+
+/// Text for the left, centre, and right of a header or footer
+#[derive(Debug, Clone, Default)]
+pub struct Slots {
+  /// Left-aligned text. This is wrapped if it's too long to fit beside the other slots
+  pub left: Option<String>,
+  /// Centred text
+  pub centre: Option<String>,
+  /// Right-aligned text
+  pub right: Option<String>,
+}
+impl Slots {
+  /// Returns `true` if none of the slots contain any text
+  pub fn is_empty(&self) -> bool {
+    self.left.is_none() && self.centre.is_none() && self.right.is_none()
+  }
+}
+
+/// Space between the top of the header region and the first line of the header
+const HEADER_PADDING: Mm = Mm(2.5);
+
+/// Values for the template variables available in headers and footers
+pub struct TemplateVariables<'a> {
+  /// Path of the file on the page
+  pub path: &'a Path,
+  /// Page number (starting at 1)
+  pub page: usize,
+  /// Total number of pages, if it's known
+  pub pages: Option<usize>,
+  /// Number of lines in the file on the page
+  pub lines: usize,
+}
+
+/// Describes the header and footer of every page.
+///
+/// The following template variables can be used in any slot:
+/// `{path}`, `{file_name}`, `{page}`, `{pages}`, `{date}`, `{project}`, `{git_commit}`, and `{lines}`
+#[derive(Debug, Clone)]
+pub struct HeaderFooter {
+  /// Header text
+  pub header: Slots,
+  /// Footer text
+  pub footer: Slots,
+  /// Whether to draw a rule between the header and the body
+  pub header_separator: bool,
+  /// Whether to draw a rule between the body and the footer
+  pub footer_separator: bool,
+  /// Value of `{project}`
+  pub project: String,
+  /// Value of `{git_commit}`
+  pub git_commit: Option<String>,
+  /// Value of `{date}`
+  pub date: String,
+}
+impl Default for HeaderFooter {
+  /// Header containing the path of the file, and no footer
+  fn default() -> Self {
+    Self {
+      header: Slots {
+        left: Some("{path}".into()),
+        ..Default::default()
+      },
+      footer: Slots::default(),
+      header_separator: false,
+      footer_separator: false,
+      project: String::new(),
+      git_commit: None,
+      date: format_date(SystemTime::now()),
+    }
+  }
+}
+impl HeaderFooter {
+  /// Initialises a new [`HeaderFooter`] for the project called `project` at `root`, using the default slots
+  pub fn new(project: String, root: &Path) -> Self {
+    Self {
+      project,
+      git_commit: git::head_commit(root),
+      ..Default::default()
+    }
+  }
+  /// Returns `true` if any slot uses the template variable `name` (such as `pages`)
+  pub fn uses_variable(&self, name: &str) -> bool {
+    let variable = format!("{{{name}}}");
+    [&self.header, &self.footer]
+      .into_iter()
+      .flat_map(|slots| [&slots.left, &slots.centre, &slots.right])
+      .flatten()
+      .any(|text| text.contains(&variable))
+  }
+  /// Replaces template variables in `template` with their values
+  pub fn expand(&self, template: &str, variables: &TemplateVariables) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+      result.push_str(&rest[..start]);
+      rest = &rest[start..];
+      let Some(end) = rest.find('}') else {
+        break;
+      };
+      let value = match &rest[1..end] {
+        "path" => variables.path.display().to_string(),
+        "file_name" => variables
+          .path
+          .file_name()
+          .map(|name| name.to_string_lossy().into_owned())
+          .unwrap_or_default(),
+        "page" => variables.page.to_string(),
+        "pages" => variables
+          .pages
+          .map(|pages| pages.to_string())
+          .unwrap_or_default(),
+        "date" => self.date.clone(),
+        "project" => self.project.clone(),
+        "git_commit" => self.git_commit.clone().unwrap_or_default(),
+        "lines" => variables.lines.to_string(),
+        // Leave unknown variables as they are
+        _ => rest[..=end].to_string(),
+      };
+      result.push_str(&value);
+      rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    result
+  }
+  /// Writes the header and footer for a page into `contents`
+  pub fn render(
+    &self,
+    contents: &mut Vec<Op>,
+    page_dimensions: &Dimensions,
+    font_id: &FontId,
+    wrapper: &mut TextWrapper,
+    variables: &TemplateVariables,
+  ) {
+    let font_size = wrapper.font_size();
+    let line_height = Pt(font_size * 1.2);
+    let mut writer = Writer {
+      contents,
+      page_dimensions,
+      font_id,
+      wrapper,
+    };
+    writer.contents.extend_from_slice(&[
+      Op::StartTextSection,
+      Op::SetLineHeight { lh: line_height },
+      Op::SetFontSize {
+        size: Pt(font_size),
+        font: font_id.clone(),
+      },
+    ]);
+    let header_y = (page_dimensions.header_top() - HEADER_PADDING).into_pt();
+    self.render_slots(&mut writer, &self.header, header_y, variables);
+    let footer_y = page_dimensions.body_bottom().into_pt() - line_height;
+    self.render_slots(&mut writer, &self.footer, footer_y, variables);
+    writer.contents.push(Op::EndTextSection);
+
+    if self.header_separator {
+      // Just above the tallest characters on the first line of the body
+      writer.draw_rule(page_dimensions.body_top().into_pt() + Pt(font_size));
+    }
+    if self.footer_separator {
+      writer.draw_rule(page_dimensions.body_bottom().into_pt());
+    }
+  }
+  /// Returns the height the header needs for a page with `variables`, from the top of the header region
+  /// to the top of the first line of the body
+  pub fn header_height(
+    &self,
+    page_dimensions: &Dimensions,
+    wrapper: &mut TextWrapper,
+    variables: &TemplateVariables,
+  ) -> Mm {
+    let lines = self
+      .lay_out_slots(wrapper, page_dimensions, &self.header, variables)
+      .iter()
+      .map(|(_, lines)| lines.len())
+      .max()
+      .unwrap_or(0);
+    if lines == 0 {
+      return Mm(0.0);
+    }
+    // The first line of the body reaches a font size above the top of the body
+    let font_size = wrapper.font_size();
+    HEADER_PADDING + Pt(font_size * 1.2 * lines as f32 + font_size).into()
+  }
+  /// Writes the text for a set of slots, with the first line of text at `y`
+  fn render_slots(&self, writer: &mut Writer, slots: &Slots, y: Pt, variables: &TemplateVariables) {
+    let slots = self.lay_out_slots(writer.wrapper, writer.page_dimensions, slots, variables);
+    for (x, lines) in slots {
+      writer.write_lines(&lines, x, y);
+    }
+  }
+  /// Expands and wraps the text in each slot, returning the lines of each, with the position of their left edge
+  fn lay_out_slots(
+    &self,
+    wrapper: &mut TextWrapper,
+    page_dimensions: &Dimensions,
+    slots: &Slots,
+    variables: &TemplateVariables,
+  ) -> Vec<(Pt, Vec<String>)> {
+    let max_line_width = |wrapper: &mut TextWrapper, text: &str| {
+      text.lines().map(|line| wrapper.get_width(line)).max()
+    };
+    let max_text_width = page_dimensions.max_text_width().into_pt();
+    let mut laid_out = vec![];
+    let left = slots.left.as_ref().map(|text| self.expand(text, variables));
+    let centre = slots
+      .centre
+      .as_ref()
+      .map(|text| self.expand(text, variables));
+    let right = slots
+      .right
+      .as_ref()
+      .map(|text| self.expand(text, variables));
+    let centre_width = centre
+      .as_deref()
+      .and_then(|text| max_line_width(wrapper, text));
+    let right_width = right
+      .as_deref()
+      .and_then(|text| max_line_width(wrapper, text));
+
+    if let Some(left) = left {
+      // Leave room for the other slots
+      let mut max_width = max_text_width;
+      if let Some(width) = right_width {
+        max_width -= width + Mm(5.0).into_pt();
+      }
+      if let Some(width) = centre_width {
+        max_width = max_width.min((max_text_width - width) / 2.0 - Mm(5.0).into_pt());
+      }
+      let lines = left
+        .lines()
+        .flat_map(|line| wrapper.split_into_lines(line, |_| max_width))
+        .map(|(line, _)| line)
+        .collect::<Vec<_>>();
+      laid_out.push((page_dimensions.margin_left.into_pt(), lines));
+    }
+    if let (Some(centre), Some(width)) = (centre, centre_width) {
+      let x = page_dimensions.margin_left.into_pt() + (max_text_width - width) / 2.0;
+      laid_out.push((x, centre.lines().map(str::to_string).collect()));
+    }
+    if let (Some(right), Some(width)) = (right, right_width) {
+      let x = (page_dimensions.width - page_dimensions.margin_right).into_pt() - width;
+      laid_out.push((x, right.lines().map(str::to_string).collect()));
+    }
+    laid_out
+  }
+}
+
+/// Writes `text` in the centre of the body of a page, such as "This page intentionally left blank"
+pub fn render_notice(
+  contents: &mut Vec<Op>,
+  page_dimensions: &Dimensions,
+  font_id: &FontId,
+  wrapper: &mut TextWrapper,
+  text: &str,
+) {
+  let font_size = wrapper.font_size();
+  let mut writer = Writer {
+    contents,
+    page_dimensions,
+    font_id,
+    wrapper,
+  };
+  writer.contents.extend_from_slice(&[
+    Op::StartTextSection,
+    Op::SetLineHeight {
+      lh: Pt(font_size * 1.2),
+    },
+    Op::SetFontSize {
+      size: Pt(font_size),
+      font: font_id.clone(),
+    },
+  ]);
+  let width = writer.max_line_width(text).unwrap_or(Pt(0.0));
+  let x = page_dimensions.margin_left.into_pt()
+    + (page_dimensions.max_text_width().into_pt() - width) / 2.0;
+  let y = (page_dimensions.body_bottom() + page_dimensions.max_text_height() / 2.0).into_pt();
+  writer.write_lines(&text.lines().collect::<Vec<_>>(), x, y);
+  writer.contents.push(Op::EndTextSection);
+}
+
+/// Returns the number of lines taken up by a separator followed by `text` (see [`render_separator`])
+pub fn separator_line_count(
+  page_dimensions: &Dimensions,
+  wrapper: &mut TextWrapper,
+  text: &str,
+) -> u32 {
+  1 + separator_text_lines(page_dimensions, wrapper, text).len() as u32
+}
+/// Wraps the text after a separator to the width of the body
+fn separator_text_lines(
+  page_dimensions: &Dimensions,
+  wrapper: &mut TextWrapper,
+  text: &str,
+) -> Vec<String> {
+  wrapper
+    .split_into_lines(text, |_| page_dimensions.max_text_width().into_pt())
+    .into_iter()
+    .map(|(line, _)| line)
+    .collect()
+}
+
+/// Draws a rule across the body on the line with its baseline at `y`, followed by `text` (wrapped if needed) on the next lines.
+///
+/// This separates files which share a page
+pub fn render_separator(
+  contents: &mut Vec<Op>,
+  page_dimensions: &Dimensions,
+  font_id: &FontId,
+  wrapper: &mut TextWrapper,
+  text: &str,
+  y: Pt,
+) {
+  let font_size = wrapper.font_size();
+  let mut writer = Writer {
+    contents,
+    page_dimensions,
+    font_id,
+    wrapper,
+  };
+  // Roughly through the middle of the line
+  writer.draw_rule(y + Pt(font_size * 0.35));
+  writer.contents.extend_from_slice(&[
+    Op::StartTextSection,
+    Op::SetLineHeight {
+      lh: Pt(font_size * 1.2),
+    },
+    Op::SetFontSize {
+      size: Pt(font_size),
+      font: font_id.clone(),
+    },
+    // The previous file may have left a different colour set
+    Op::SetFillColor {
+      col: color::Color::Rgb(color::Rgb {
+        r: 0.0,
+        g: 0.0,
+        b: 0.0,
+        icc_profile: None,
+      }),
+    },
+  ]);
+  let lines = separator_text_lines(page_dimensions, writer.wrapper, text);
+  writer.write_lines(
+    &lines,
+    page_dimensions.margin_left.into_pt(),
+    y - Pt(font_size * 1.2),
+  );
+  writer.contents.push(Op::EndTextSection);
+}
+
+/// Writes decorations onto a page
+struct Writer<'a> {
+  contents: &'a mut Vec<Op>,
+  page_dimensions: &'a Dimensions,
+  font_id: &'a FontId,
+  wrapper: &'a mut TextWrapper,
+}
+impl Writer<'_> {
+  /// Returns the width of the widest line in `text`
+  fn max_line_width(&mut self, text: &str) -> Option<Pt> {
+    text.lines().map(|line| self.wrapper.get_width(line)).max()
+  }
+  /// Writes lines of text, with the first line starting at (`x`, `y`)
+  fn write_lines<S: AsRef<str>>(&mut self, lines: &[S], x: Pt, y: Pt) {
+    self.contents.extend_from_slice(&[
+      Op::SetTextMatrix {
+        matrix: TextMatrix::Translate(Pt(0.0), Pt(0.0)),
+      },
+      Op::SetTextCursor {
+        pos: Point { x, y },
+      },
+    ]);
+    for line in lines {
+      self.contents.push(Op::WriteText {
+        items: self.wrapper.text_items(line.as_ref()),
+        font: self.font_id.clone(),
+      });
+      self.contents.push(Op::AddLineBreak);
+    }
+  }
+  /// Draws a horizontal rule across the text area at `y`
+  fn draw_rule(&mut self, y: Pt) {
+    let point = |x: Mm| LinePoint {
+      p: Point { x: x.into(), y },
+      bezier: false,
+    };
+    self.contents.extend_from_slice(&[
+      Op::SetOutlineColor {
+        col: color::Color::Rgb(color::Rgb {
+          r: 0.6,
+          g: 0.6,
+          b: 0.6,
+          icc_profile: None,
+        }),
+      },
+      Op::SetOutlineThickness { pt: Pt(0.5) },
+      Op::DrawLine {
+        line: Line {
+          points: vec![
+            point(self.page_dimensions.margin_left),
+            point(self.page_dimensions.width - self.page_dimensions.margin_right),
+          ],
+          is_closed: false,
+        },
+      },
+    ]);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn expanding_templates() {
+    let header_footer = HeaderFooter {
+      project: "Project".into(),
+      git_commit: Some("abcdef0".into()),
+      date: "2025-01-01".into(),
+      ..Default::default()
+    };
+    let variables = TemplateVariables {
+      path: Path::new("src/main.rs"),
+      page: 3,
+      pages: Some(10),
+      lines: 42,
+    };
+    assert_eq!(
+      header_footer.expand("{project} @ {git_commit}: {path}", &variables),
+      "Project @ abcdef0: src/main.rs"
+    );
+    assert_eq!(
+      header_footer.expand("{file_name} ({lines} lines) - {date}", &variables),
+      "main.rs (42 lines) - 2025-01-01"
+    );
+    assert_eq!(
+      header_footer.expand("Page {page} of {pages}", &variables),
+      "Page 3 of 10"
+    );
+    // Unknown variables and unclosed braces are left alone
+    assert_eq!(
+      header_footer.expand("{unknown} {page", &variables),
+      "{unknown} {page"
+    );
+  }
+  #[test]
+  fn measuring_headers() {
+    let header_footer = HeaderFooter::default();
+    let page_dimensions = Dimensions::default();
+    let mut wrapper = TextWrapper::new(include_bytes!("../../fonts/Helvetica.ttf"), 12.0);
+    let mut height = |path: &str| {
+      let variables = TemplateVariables {
+        path: Path::new(path),
+        page: 1,
+        pages: None,
+        lines: 0,
+      };
+      header_footer.header_height(&page_dimensions, &mut wrapper, &variables)
+    };
+    let short = height("src/main.rs");
+    // A single line fits in the default header
+    assert!(short < page_dimensions.header_height);
+    // A path which wraps onto several lines needs more room
+    let long = height(&"directory/".repeat(30));
+    assert!(long > page_dimensions.header_height);
+  }
+}
@@ -1,41 +1,19 @@
 //! Miscellaneous helper functions
 
-use std::{path::Path, str::Lines};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use printpdf::{FontId, Mm, Op, Point, Pt, TextItem, TextMatrix, TextRenderingMode};
+use printpdf::{FontId, Op, Point, Pt, TextMatrix, TextRenderingMode};
 
-use crate::{dimensions::Dimensions, text_manipulation::TextWrapper};
-/// Processed additional text.
+use crate::dimensions::Dimensions;
+
+/// Generates a new page with basic contents, leaving the text cursor at the start of the body.
 ///
-/// Pretty much just serves to cache the maximum line width within the text so it doesn't have to be recalculated
-#[derive(Clone)]
-pub struct ProcessedText {
-  text: String,
-  width: f32,
-}
-impl ProcessedText {
-  /// Creates a new instance of [`ProcessedText`]
-  pub fn new(text: String, wrapper: &mut TextWrapper) -> Option<Self> {
-    let width = text
-      .lines()
-      .map(|line| wrapper.get_width(line).0)
-      .reduce(f32::max)?;
-    Some(Self { text, width })
-  }
-  fn lines<'a>(&'a self) -> Lines<'a> {
-    self.text.lines()
-  }
-}
-/// Generates a new page with basic contents
+/// Headers and footers are added once every page has been generated (see [`crate::header_footer`])
 pub fn init_page(
   contents: &mut Vec<Op>,
   page_dimensions: &Dimensions,
   font_id: FontId,
   font_size: f32,
-  path: &Path,
-  additional_text: Option<&ProcessedText>,
-  include_path: bool,
-  wrapper: &mut TextWrapper,
 ) {
   contents.extend_from_slice(&[
     Op::StartTextSection,
@@ -44,69 +22,16 @@ pub fn init_page(
     },
     Op::SetFontSize {
       size: Pt(font_size),
-      font: font_id.clone(),
+      font: font_id,
     },
-  ]);
-  // Write additional text
-  if let Some(text) = additional_text {
-    contents.extend_from_slice(&[
-      Op::SetTextMatrix {
-        matrix: TextMatrix::Translate(Pt(0.0), Pt(0.0)),
-      },
-      Op::SetTextCursor {
-        pos: Point {
-          x: (page_dimensions.width - page_dimensions.margin_right).into_pt() - Pt(text.width),
-          y: (page_dimensions.height - Mm(7.5)).into(),
-        },
-      },
-    ]);
-    for line in text.lines() {
-      contents.push(Op::WriteText {
-        items: vec![TextItem::Text(line.to_string())],
-        font: font_id.clone(),
-      });
-      contents.push(Op::AddLineBreak);
-    }
-  }
-  if include_path {
-    contents.extend_from_slice(&[
-      Op::SetTextMatrix {
-        matrix: TextMatrix::Translate(Pt(0.0), Pt(0.0)),
-      },
-      // Write metadata
-      Op::SetTextCursor {
-        pos: Point {
-          x: page_dimensions.margin_left.into(),
-          y: (page_dimensions.height - Mm(7.5)).into(),
-        },
-      },
-    ]);
-    let max_path_width = (page_dimensions.max_text_width()
-      - if let Some(text) = &additional_text {
-        Mm::from(Pt(text.width)) + Mm(5.0)
-      } else {
-        Mm(0.0)
-      })
-    .into_pt();
-    for (line, _) in wrapper.split_into_lines(&path.display().to_string(), |_| max_path_width) {
-      contents.push(Op::WriteText {
-        items: vec![TextItem::Text(line)],
-        font: font_id.clone(),
-      });
-      contents.push(Op::AddLineBreak);
-    }
-  }
-
-  // Set cursor to main body
-  contents.extend_from_slice(&[
     // This allows me to reset the text cursor for some reason
     Op::SetTextMatrix {
       matrix: TextMatrix::Translate(Pt(0.0), Pt(0.0)),
     },
     Op::SetTextCursor {
       pos: Point {
-        x: page_dimensions.margin_left.into(),
-        y: (page_dimensions.height - page_dimensions.margin_top).into(),
+        x: page_dimensions.column_left(0).into(),
+        y: page_dimensions.body_top().into(),
       },
     },
     Op::SetTextRenderingMode {
@@ -114,3 +39,75 @@ pub fn init_page(
     },
   ]);
 }
+
+/// Formats the (UTC) date of `time` as `YYYY-MM-DD`
+pub fn format_date(time: SystemTime) -> String {
+  let days = time
+    .duration_since(UNIX_EPOCH)
+    .map(|duration| duration.as_secs() / 86_400)
+    .unwrap_or(0) as i64;
+  // Converts days since the epoch into a civil date (http://howardhinnant.github.io/date_algorithms.html)
+  let z = days + 719_468;
+  let era = z.div_euclid(146_097);
+  let day_of_era = z.rem_euclid(146_097);
+  let year_of_era =
+    (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+  let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+  let mp = (5 * day_of_year + 2) / 153;
+  let day = day_of_year - (153 * mp + 2) / 5 + 1;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 };
+  let year = year_of_era + era * 400 + i64::from(month <= 2);
+  format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// Formats the (UTC) date and time of `time` as `YYYY-MM-DD HH:MM UTC`
+pub fn format_date_time(time: SystemTime) -> String {
+  let seconds = time
+    .duration_since(UNIX_EPOCH)
+    .map(|duration| duration.as_secs() % 86_400)
+    .unwrap_or(0);
+  format!(
+    "{} {:02}:{:02} UTC",
+    format_date(time),
+    seconds / 3600,
+    seconds % 3600 / 60
+  )
+}
+
+/// Formats a number of bytes with binary units, such as `1.5 KiB`
+pub fn format_size(bytes: u64) -> String {
+  const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+  if bytes < 1024 {
+    return format!("{bytes} B");
+  }
+  let mut size = bytes as f64 / 1024.0;
+  let mut unit = 0;
+  while size >= 1024.0 && unit < UNITS.len() - 1 {
+    size /= 1024.0;
+    unit += 1;
+  }
+  format!("{size:.1} {}", UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use super::*;
+
+  #[test]
+  fn formatting_dates() {
+    assert_eq!(format_date(UNIX_EPOCH), "1970-01-01");
+    let leap_day = UNIX_EPOCH + Duration::from_secs(951_782_400);
+    assert_eq!(format_date(leap_day), "2000-02-29");
+    let new_years_eve = UNIX_EPOCH + Duration::from_secs(1_735_689_599);
+    assert_eq!(format_date(new_years_eve), "2024-12-31");
+    assert_eq!(format_date_time(new_years_eve), "2024-12-31 23:59 UTC");
+  }
+  #[test]
+  fn formatting_sizes() {
+    assert_eq!(format_size(512), "512 B");
+    assert_eq!(format_size(1536), "1.5 KiB");
+    assert_eq!(format_size(5 * 1024 * 1024), "5.0 MiB");
+  }
+}
@@ -0,0 +1,215 @@
+//! Arranges several logical pages on each physical sheet (N-up), and orders pages for booklets.
+//!
+//! This runs over the pages produced by [`crate::code_to_pdf::DocumentSubset::to_document`]
+
+use std::{mem, str::FromStr};
+
+use printpdf::{CurTransMat, Op, PaintMode, PdfDocument, PdfPage, Pt, Rect};
+
+/// Number of logical pages put on each side of a sheet
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum PagesPerSheet {
+  /// Pages are left as they are
+  #[default]
+  One,
+  /// Two pages side by side on a landscape sheet (or one above the other on a portrait sheet, for landscape pages)
+  Two,
+  /// Four pages in a 2x2 grid
+  Four,
+}
+impl FromStr for PagesPerSheet {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.trim() {
+      "1" => Ok(Self::One),
+      "2" => Ok(Self::Two),
+      "4" => Ok(Self::Four),
+      _ => Err(format!(
+        "Invalid number of pages per sheet `{s}`. Expected 1, 2, or 4"
+      )),
+    }
+  }
+}
+
+/// Describes how pages are arranged on physical sheets
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Imposition {
+  /// Number of pages on each side of a sheet
+  pub pages_per_sheet: PagesPerSheet,
+  /// Whether pages are ordered for a saddle-stitched booklet.
+  /// Booklets always have two pages on each side of a sheet
+  pub booklet: bool,
+}
+impl Imposition {
+  /// Arranges the pages of `doc` onto sheets, moving its bookmarks to the sheets their pages are on
+  pub fn impose_document(&self, doc: &mut PdfDocument) {
+    let page_count = doc.pages.len();
+    doc.pages = self.impose(mem::take(&mut doc.pages));
+    let order = if self.booklet {
+      booklet_order(page_count)
+    } else {
+      (0..page_count).map(Some).collect()
+    };
+    let pages_per_sheet = match (self.booklet, self.pages_per_sheet) {
+      (true, _) | (false, PagesPerSheet::Two) => 2,
+      (false, PagesPerSheet::One) => 1,
+      (false, PagesPerSheet::Four) => 4,
+    };
+    for bookmark in doc.bookmarks.map.values_mut() {
+      let page = bookmark.page.saturating_sub(1);
+      let position = order.iter().position(|&p| p == Some(page)).unwrap_or(0);
+      bookmark.page = position / pages_per_sheet + 1;
+    }
+  }
+  /// Arranges `pages` onto sheets. All pages are assumed to be the same size as the first page
+  pub fn impose(&self, pages: Vec<PdfPage>) -> Vec<PdfPage> {
+    let Some(first) = pages.first() else {
+      return pages;
+    };
+    let (width, height) = (first.media_box.width, first.media_box.height);
+    let pages_per_sheet = if self.booklet {
+      PagesPerSheet::Two
+    } else {
+      self.pages_per_sheet
+    };
+    // Size of each sheet, and the number of pages across and down it
+    let (sheet_width, sheet_height, across, down) = match pages_per_sheet {
+      PagesPerSheet::One => return pages,
+      // The sheet is the page turned sideways, so pages go across it if they're portrait, and down it if they're landscape
+      PagesPerSheet::Two if width <= height => (height, width, 2, 1),
+      PagesPerSheet::Two => (height, width, 1, 2),
+      PagesPerSheet::Four => (width, height, 2, 2),
+    };
+    let cell_width = sheet_width / across as f32;
+    let cell_height = sheet_height / down as f32;
+    let scale = f32::min(cell_width / width, cell_height / height);
+
+    let mut pages = pages.into_iter().map(Some).collect::<Vec<_>>();
+    let order = if self.booklet {
+      booklet_order(pages.len())
+    } else {
+      (0..pages.len()).map(Some).collect()
+    };
+    order
+      .chunks(across * down)
+      .map(|cells| {
+        let mut ops = vec![];
+        for (i, page) in cells.iter().enumerate() {
+          let Some(mut page) = (*page).and_then(|page| pages[page].take()) else {
+            continue;
+          };
+          let (column, row) = (i % across, i / across);
+          // Centre each page in its cell, with the first row at the top of the sheet
+          let x = cell_width * column as f32 + (cell_width - width * scale) / 2.0;
+          let y =
+            sheet_height - cell_height * (row + 1) as f32 + (cell_height - height * scale) / 2.0;
+          ops.extend_from_slice(&[
+            Op::SaveGraphicsState,
+            Op::SetTransformationMatrix {
+              matrix: CurTransMat::Raw([scale, 0.0, 0.0, scale, x.0, y.0]),
+            },
+            // Keep anything drawn outside the page out of the neighbouring cells
+            Op::DrawPolygon {
+              polygon: clip_rect(width, height),
+            },
+          ]);
+          ops.append(&mut page.ops);
+          ops.push(Op::RestoreGraphicsState);
+        }
+        PdfPage::new(sheet_width.into(), sheet_height.into(), ops)
+      })
+      .collect()
+  }
+}
+
+/// Returns a clipping path covering a page of the given size
+fn clip_rect(width: Pt, height: Pt) -> printpdf::Polygon {
+  let mut polygon = Rect {
+    x: Pt(0.0),
+    // This is the top edge
+    y: height,
+    width,
+    height,
+  }
+  .to_polygon();
+  polygon.mode = PaintMode::Clip;
+  polygon
+}
+
+/// Returns the order in which pages are placed on the sides of the sheets of a saddle-stitched booklet,
+/// two pages per side. `None` represents a blank page added to make the page count a multiple of 4
+fn booklet_order(page_count: usize) -> Vec<Option<usize>> {
+  let padded_count = page_count.div_ceil(4) * 4;
+  let page = |i: usize| (i < page_count).then_some(i);
+  (0..padded_count / 4)
+    .flat_map(|sheet| {
+      let (front, back) = (2 * sheet, padded_count - 1 - 2 * sheet);
+      // The front of the sheet, then the back
+      [page(back), page(front), page(front + 1), page(back - 1)]
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use printpdf::Mm;
+
+  use super::*;
+
+  #[test]
+  fn booklet_ordering() {
+    assert_eq!(
+      booklet_order(5),
+      vec![
+        None,
+        Some(0),
+        Some(1),
+        None,
+        None,
+        Some(2),
+        Some(3),
+        Some(4)
+      ]
+    );
+  }
+  #[test]
+  fn n_up() {
+    let pages = (0..5)
+      .map(|_| PdfPage::new(Mm(210.0), Mm(297.0), vec![]))
+      .collect::<Vec<_>>();
+    let imposition = Imposition {
+      pages_per_sheet: PagesPerSheet::Four,
+      booklet: false,
+    };
+    assert_eq!(imposition.impose(pages.clone()).len(), 2);
+    let imposition = Imposition {
+      pages_per_sheet: PagesPerSheet::Two,
+      booklet: false,
+    };
+    let sheets = imposition.impose(pages);
+    assert_eq!(sheets.len(), 3);
+    // Sheets are landscape
+    assert!(sheets[0].media_box.width > sheets[0].media_box.height);
+  }
+  #[test]
+  fn n_up_landscape() {
+    let pages = (0..2)
+      .map(|_| PdfPage::new(Mm(297.0), Mm(210.0), vec![]))
+      .collect::<Vec<_>>();
+    let imposition = Imposition {
+      pages_per_sheet: PagesPerSheet::Two,
+      booklet: false,
+    };
+    let sheets = imposition.impose(pages);
+    assert_eq!(sheets.len(), 1);
+    // Landscape pages are stacked on a portrait sheet, at the same scale as portrait pages side by side
+    assert!(sheets[0].media_box.width < sheets[0].media_box.height);
+    let Op::SetTransformationMatrix {
+      matrix: CurTransMat::Raw([scale, ..]),
+    } = sheets[0].ops[1]
+    else {
+      panic!("Expected the page to be scaled");
+    };
+    assert!((scale - 210.0 / 297.0).abs() < 0.001);
+  }
+}
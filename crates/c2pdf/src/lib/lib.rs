@@ -8,129 +8,841 @@
 
 use std::{
   cmp::Ordering,
+  collections::{BTreeMap, HashSet},
+  fs::File,
+  io::{self, BufWriter, Write},
   num::NonZeroU8,
-  path::PathBuf,
+  path::{Component, Path, PathBuf},
   sync::{Arc, Mutex},
+  thread,
 };
 
-use code_to_pdf::{CodeToPdf, DocumentSubset, HighlighterConfig};
-use dimensions::Dimensions;
-use helpers::ProcessedText;
-use ignore::{WalkBuilder, overrides::OverrideBuilder};
-use log::{error, trace};
-use printpdf::FontId;
+use archive::{ArchiveKind, ArchiveSource};
+use code_to_pdf::{CodeToPdf, DocumentSubset, FilePages, HighlighterConfig, RectoStart};
+use cover::Cover;
+use diff::{Diff, FileDiff};
+use dimensions::{Dimensions, GutterSide};
+use git::{LastCommits, RevisionSource};
+use header_footer::HeaderFooter;
+use ignore::{
+  Walk, WalkBuilder,
+  overrides::{Override, OverrideBuilder},
+};
+use log::{error, trace, warn};
+use printpdf::{FontId, Pt};
 use rayon::iter::{ParallelBridge, ParallelIterator};
-use text_manipulation::TextWrapper;
+use sort::{MetadataCache, SortOrder};
+use source::{FileListSource, MemorySource, Source, SourceFile, WalkSource};
+use stream::{ReorderBuffer, StreamWriter};
+use text_manipulation::{GridMode, TextWrapper};
 use thread_local::ThreadLocal;
 
+pub mod archive;
+pub mod batch;
 pub mod code_to_pdf;
+pub mod cover;
+pub mod diff;
 pub mod dimensions;
+pub mod file_header;
 pub mod font_loader;
+pub mod git;
+pub mod header_footer;
 pub mod helpers;
+pub mod imposition;
 pub mod logging;
+pub mod sort;
+pub mod source;
+pub mod split;
+pub mod stats;
+pub mod stream;
 pub mod text_manipulation;
+pub mod tree;
 
 pub use printpdf::{ParsedFont, PdfDocument, PdfSaveOptions};
 
+/// Options for [`CodeToPdf::run_parallel`]
+#[derive(Clone)]
+pub struct RunOptions {
+  /// Globs to exclude from the walk
+  pub exclusions: Vec<String>,
+  /// Dimensions of each page
+  pub page_dimensions: Dimensions,
+  /// Size of the font in point
+  pub font_size: f32,
+  /// Header and footer of every page
+  pub header_footer: HeaderFooter,
+  /// Number of threads to use for processing
+  pub threads: Option<NonZeroU8>,
+  /// How characters are positioned along each line
+  pub grid: GridMode,
+  /// Whether every file starts on an odd page
+  pub recto_start: RectoStart,
+  /// Whether small files are put on the same page as the previous file, if they fit
+  pub compact: bool,
+  /// Minimum font size that files can be shrunk to so that lines don't wrap, if enabled
+  pub auto_fit: Option<f32>,
+  /// Cover page added before all the files
+  pub cover: Option<Cover>,
+  /// Whether an overview of every file is added before the files
+  pub tree: bool,
+  /// Whether an appendix with statistics for each language is added after the files
+  pub stats: bool,
+  /// Whether information about each file is shown above its contents
+  pub file_header: bool,
+  /// Git revision (such as a tag or commit) to read files from, instead of the working tree
+  pub revision: Option<String>,
+  /// Older version of the project to compare against, showing only the changes to each file
+  pub diff: Option<Diff>,
+  /// Globs of paths to include. If not empty, files which don't match any of them are skipped
+  pub inclusions: Vec<String>,
+  /// Explicit list of files to include, in order, instead of walking the path
+  pub files: Option<Vec<PathBuf>>,
+  /// Order of the entries in each directory. If `None`, entries other than directories are left in the order they're read
+  pub sort: Option<SortOrder>,
+  /// Whether only files tracked by git are included
+  pub git_tracked_only: bool,
+  /// Side of each column to annotate lines with the commit which last changed them, if enabled
+  pub blame: Option<GutterSide>,
+  /// Language (a syntax name or extension, such as `rust` or `rs`) used to highlight every file,
+  /// instead of detecting it from each file
+  pub language: Option<String>,
+  /// Resources loaded beforehand, so they can be shared between runs. If `None`, they're loaded for this run
+  pub resources: Option<Resources>,
+  /// Path to write the PDF to as pages are generated, instead of keeping every page in memory.
+  /// The returned [`DocumentSubset`] then has no pages, and the cover, tree, and compact mode aren't supported
+  pub stream: Option<PathBuf>,
+}
+impl Default for RunOptions {
+  fn default() -> Self {
+    Self {
+      exclusions: vec![],
+      page_dimensions: Dimensions::default(),
+      font_size: 12.0,
+      header_footer: HeaderFooter::default(),
+      threads: None,
+      grid: GridMode::Proportional,
+      recto_start: RectoStart::Disabled,
+      compact: false,
+      auto_fit: None,
+      cover: None,
+      tree: false,
+      stats: false,
+      file_header: false,
+      revision: None,
+      diff: None,
+      inclusions: vec![],
+      files: None,
+      sort: None,
+      git_tracked_only: false,
+      blame: None,
+      language: None,
+      resources: None,
+      stream: None,
+    }
+  }
+}
+
+/// Resources which are slow to load, so can be loaded once and shared between runs (such as in batch mode)
+#[derive(Clone)]
+pub struct Resources {
+  /// Syntaxes and theme used to highlight files
+  pub highlighter_config: Arc<HighlighterConfig>,
+  /// Parsed font used to measure text
+  pub wrapper: TextWrapper,
+}
+impl Resources {
+  /// Loads the bundled syntaxes and theme, and parses `font_bytes` to measure text at `font_size` with `grid`
+  pub fn load(font_bytes: &[u8], font_size: f32, grid: GridMode) -> Self {
+    let ts = two_face::theme::extra();
+    Self {
+      highlighter_config: Arc::new(HighlighterConfig::new(
+        two_face::syntax::extra_newlines(),
+        ts.get(two_face::theme::EmbeddedThemeName::InspiredGithub)
+          .clone(),
+      )),
+      wrapper: TextWrapper::new(font_bytes, font_size).with_grid(grid),
+    }
+  }
+}
+
+/// Builds the overrides for paths below `path`, which exclude paths matching `exclusions`.
+/// If there are any `inclusions`, files which don't match at least one of them are excluded too.
+///
+/// Fails if any of the globs are invalid
+fn build_overrides(
+  path: &Path,
+  exclusions: &[String],
+  inclusions: &[String],
+) -> Result<Override, String> {
+  let invalid = |glob: &str, err: ignore::Error| format!("Invalid glob `{glob}`: {err}");
+  let mut builder = OverrideBuilder::new(path);
+  // Exclusions are added last, so they take precedence
+  for inclusion in inclusions {
+    builder
+      .add(inclusion)
+      .map_err(|err| invalid(inclusion, err))?;
+  }
+  for exclusion in exclusions {
+    builder
+      .add(&("!".to_string() + exclusion))
+      .map_err(|err| invalid(exclusion, err))?;
+  }
+  builder.build().map_err(|err| err.to_string())
+}
+
+/// Builds the walker over the files in `path`, along with the overrides which exclude paths matching `exclusions`.
+/// If there are any `inclusions`, only files matching at least one of them are included.
+///
+/// If `tracked_only` is `true`, files which aren't tracked by the git repository containing `path` are skipped.
+/// Entries in each directory are ordered by `sort` (after files are put before directories), if given.
+/// Fails if any of the globs are invalid
+fn build_walker(
+  path: &Path,
+  exclusions: &[String],
+  inclusions: &[String],
+  tracked_only: bool,
+  sort: Option<&SortOrder>,
+) -> Result<(Walk, Override), String> {
+  let overrides = build_overrides(path, exclusions, inclusions)?;
+  let mut builder = WalkBuilder::new(path);
+  if let Some(tracked) = tracked_only.then(|| TrackedFiles::load(path)).flatten() {
+    builder.filter_entry(move |entry| {
+      tracked.contains(entry.path(), entry.file_type().is_some_and(|f| f.is_dir()))
+    });
+  }
+  let sort = sort.cloned().map(|sort| sort.with_root(path));
+  let metadata = MetadataCache::default();
+  let walker = builder
+    .overrides(overrides.clone())
+    // Ensure that files are given higher precidence than folders
+    // (want files in a folder to be printed breadth-first)
+    .sort_by_file_path(move |x, y| {
+      {
+        if x.is_dir() && !y.is_dir() {
+          Ordering::Less
+        } else if y.is_dir() && !x.is_dir() {
+          Ordering::Greater
+        } else {
+          Ordering::Equal
+        }
+      }
+      .reverse()
+      .then_with(|| {
+        sort.as_ref().map_or(Ordering::Equal, |sort| {
+          sort.compare_with(x, y, |path| metadata.get(path))
+        })
+      })
+    })
+    .build();
+  Ok((walker, overrides))
+}
+
+/// The files tracked by git below a path, as returned by [`git::tracked_files`]
+struct TrackedFiles {
+  files: HashSet<PathBuf>,
+  /// Directories containing any tracked files
+  directories: HashSet<PathBuf>,
+}
+impl TrackedFiles {
+  /// Finds the files tracked by the git repository containing `path`,
+  /// or logs a warning and returns `None` if it isn't in one
+  fn load(path: &Path) -> Option<Self> {
+    let Some(files) = git::tracked_files(path) else {
+      warn!(
+        "{} isn't in a git repository, so untracked files are included",
+        path.display()
+      );
+      return None;
+    };
+    let directories = files
+      .iter()
+      .flat_map(|file| file.ancestors().skip(1))
+      .map(Path::to_path_buf)
+      .collect();
+    Some(Self {
+      files: files.into_iter().collect(),
+      directories,
+    })
+  }
+  /// Returns whether the file (or directory, if `is_dir`) at `path` is tracked
+  fn contains(&self, path: &Path, is_dir: bool) -> bool {
+    match is_dir {
+      true => self.directories.contains(path),
+      false => self.files.contains(path),
+    }
+  }
+}
+
+/// Resolves the paths of `files` against `path`, keeping the ones which aren't ignored by `overrides`
+/// (or any of their directories below `path`), and are in `tracked` if given.
+///
+/// These are the same filters as the walker, apart from `.gitignore` and `.ignore` files
+fn listed_files(
+  path: &Path,
+  files: Vec<PathBuf>,
+  overrides: &Override,
+  tracked: Option<&TrackedFiles>,
+) -> Vec<PathBuf> {
+  files
+    .into_iter()
+    .filter_map(|file| {
+      // Leading `./` components are removed, so the paths match the ones from the walker
+      let relative = file
+        .components()
+        .filter(|component| *component != Component::CurDir)
+        .collect::<PathBuf>();
+      let directories = relative
+        .ancestors()
+        .skip(1)
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .map(|dir| (path.join(dir), true));
+      let resolved = path.join(&relative);
+      let ignored = std::iter::once((resolved.clone(), false))
+        .chain(directories)
+        .any(|(path, is_dir)| {
+          overrides.matched(&path, is_dir).is_ignore()
+            || tracked.is_some_and(|tracked| !tracked.contains(&path, is_dir))
+        });
+      match ignored {
+        true => {
+          trace!("Skipping {}, as it's excluded", resolved.display());
+          None
+        }
+        false => Some(resolved),
+      }
+    })
+    .collect()
+}
+
+/// A file to generate pages for
+enum Input {
+  /// A file from a [`Source`]
+  File(SourceFile),
+  /// A file which changed, in diff mode
+  Diff(FileDiff),
+}
+
+/// Returns the inputs for every file from `source`, logging (and skipping) any errors
+fn source_inputs(source: impl Source + 'static) -> Box<dyn Iterator<Item = Input> + Send> {
+  Box::new(source::files(source).filter_map(|result| match result {
+    Ok(file) => Some(Input::File(file)),
+    Err(err) => {
+      error!("ERROR: {}", err);
+      None
+    }
+  }))
+}
+
 // Do this here, until I find a good name for a module to plate it in :)
 // Maybe `easy`, like what `syntect` has
 impl CodeToPdf {
-  /// Helper function that handles everything for the basic use-case
+  /// Helper function that handles everything for the basic use-case.
+  ///
+  /// Returns an error if the files can't be found at all (such as if the revision doesn't exist)
   pub fn run_parallel(
     font_id: FontId,
     font_bytes: &[u8],
     path: PathBuf,
-    exclusions: Vec<String>,
-    page_dimensions: Dimensions,
-    font_size: f32,
-    page_text: Option<String>,
-    include_path: bool,
-    threads: Option<NonZeroU8>,
-  ) -> (Arc<Mutex<DocumentSubset>>, usize) {
-    let doc_subset = DocumentSubset::default();
-    let ss = two_face::syntax::extra_newlines();
-    let ts = two_face::theme::extra();
-    let walker = WalkBuilder::new(path.clone())
-      .overrides({
-        let mut builder = OverrideBuilder::new(path);
-        for exclusion in exclusions.clone() {
-          builder.add(&("!".to_string() + &exclusion)).unwrap();
-        }
-        builder.build().unwrap()
-      })
-      // Ensure that files are given higher precidence than folders
-      // (want files in a folder to be printed breadth-first)
-      .sort_by_file_path(|x, y| {
-        {
-          if x.is_dir() && !y.is_dir() {
-            Ordering::Less
-          } else if y.is_dir() && !x.is_dir() {
-            Ordering::Greater
-          } else {
-            Ordering::Equal
-          }
-        }
-        .reverse()
-      })
-      .build();
+    mut options: RunOptions,
+  ) -> Result<(Arc<Mutex<DocumentSubset>>, usize), String> {
+    let overrides = build_overrides(&path, &options.exclusions, &options.inclusions)?;
+    let archive = ArchiveKind::from_path(&path).filter(|_| path.is_file());
+    let inputs: Box<dyn Iterator<Item = Input> + Send> = match (
+      &options.diff,
+      options.files.take(),
+      archive,
+      &options.revision,
+    ) {
+      (Some(diff), _, _, revision) => {
+        let changed = diff.changed_files(
+          &path,
+          revision.as_deref(),
+          &options.exclusions,
+          &options.inclusions,
+          options.sort.as_ref(),
+        );
+        let files = changed.map_err(|err| format!("Unable to compare files: {err}"))?;
+        Box::new(files.into_iter().map(Input::Diff))
+      }
+      (None, Some(_), _, Some(_)) => {
+        return Err(
+          "The listed files are read from the working tree, so they can't be read from a revision"
+            .to_string(),
+        );
+      }
+      (None, Some(_), _, _) if options.sort.is_some() => {
+        return Err(
+          "The listed files are printed in the order they're listed, so they can't be sorted"
+            .to_string(),
+        );
+      }
+      // The files are given in the order they should be printed, so they don't go through the walker
+      (None, Some(files), _, None) => {
+        let tracked = options
+          .git_tracked_only
+          .then(|| TrackedFiles::load(&path))
+          .flatten();
+        let files = listed_files(&path, files, &overrides, tracked.as_ref());
+        source_inputs(FileListSource::new(files))
+      }
+      (None, None, Some(kind), _) => {
+        let files = ArchiveSource::new(&path, kind, &overrides, options.sort.as_ref())
+          .map_err(|err| format!("Unable to read {}: {err}", path.display()))?;
+        source_inputs(files)
+      }
+      (None, None, None, Some(revision)) => {
+        let files = RevisionSource::new(&path, revision, &overrides, options.sort.as_ref())
+          .map_err(|err| format!("Unable to read revision `{revision}`: {err}"))?;
+        source_inputs(files)
+      }
+      (None, None, None, None) => {
+        let (walker, _) = build_walker(
+          &path,
+          &options.exclusions,
+          &options.inclusions,
+          options.git_tracked_only,
+          options.sort.as_ref(),
+        )?;
+        source_inputs(WalkSource(walker))
+      }
+    };
+    Self::run_inputs(font_id, font_bytes, inputs, options)
+  }
+  /// Generates pages for every file from `source` in parallel, in the same way as [`CodeToPdf::run_parallel`].
+  ///
+  /// Options which select the files (such as [`RunOptions::exclusions`] and [`RunOptions::diff`]) are ignored,
+  /// as the files come from `source` instead.
+  ///
+  /// Returns an error if the PDF can't be written when streaming
+  pub fn run_source(
+    font_id: FontId,
+    font_bytes: &[u8],
+    source: impl Source + 'static,
+    options: RunOptions,
+  ) -> Result<(Arc<Mutex<DocumentSubset>>, usize), String> {
+    let options = RunOptions {
+      diff: None,
+      ..options
+    };
+    Self::run_inputs(font_id, font_bytes, source_inputs(source), options)
+  }
+  /// Generates pages for every input in parallel, keeping them in the order they're given.
+  ///
+  /// Returns an error if the PDF can't be written when streaming
+  fn run_inputs(
+    font_id: FontId,
+    font_bytes: &[u8],
+    inputs: Box<dyn Iterator<Item = Input> + Send>,
+    options: RunOptions,
+  ) -> Result<(Arc<Mutex<DocumentSubset>>, usize), String> {
+    let RunOptions {
+      page_dimensions,
+      font_size,
+      header_footer,
+      threads,
+      grid,
+      recto_start,
+      compact,
+      auto_fit,
+      cover,
+      tree,
+      stats,
+      file_header,
+      revision,
+      diff,
+      blame,
+      language,
+      resources,
+      stream,
+      ..
+    } = options;
+    let mut page_dimensions = page_dimensions;
+    let diff_style = diff.as_ref().map(|diff| diff.style).unwrap_or_default();
+    let Resources {
+      highlighter_config,
+      wrapper,
+    } = resources.unwrap_or_else(|| Resources::load(font_bytes, font_size, grid));
+    let mut wrapper = wrapper;
 
     let local_c2pdf = ThreadLocal::<Arc<Mutex<CodeToPdf>>>::new();
-    let local_highlighter_config = ThreadLocal::<Arc<Mutex<HighlighterConfig>>>::new();
+    // The history is only walked once for all the files, rather than once for each file
+    let last_commits = Arc::new(LastCommits::default());
 
-    let doc_subset = Arc::new(Mutex::new(doc_subset));
     if let Some(threads) = threads {
-      // Build the global threadpool with the correct number of threads
-      rayon::ThreadPoolBuilder::new()
+      // Build the global threadpool with the correct number of threads.
+      // It can only be built once, so later runs (such as in batch mode) reuse it
+      if rayon::ThreadPoolBuilder::new()
         .num_threads(u8::from(threads) as usize)
         .build_global()
-        .unwrap();
+        .is_err()
+      {
+        trace!("Global thread pool has already been built");
+      }
+    }
+    if grid != GridMode::Proportional && !wrapper.is_monospace() {
+      warn!("The selected font isn't monospaced, so characters will be spaced out to fit the grid");
+    }
+    if let Some(side) = blame {
+      // Wide enough for a hash and 3 initials, with a gap before the text
+      let width = wrapper.get_width("0000000 WWW") + Pt(font_size);
+      page_dimensions.gutter_width = width.into();
+      page_dimensions.gutter_side = side;
+    }
+    let (cover, tree, compact) = match &stream {
+      Some(_) if cover.is_some() || tree || compact => {
+        warn!(
+          "The cover, tree, and compact mode aren't supported when streaming, so they're left out"
+        );
+        (None, false, false)
+      }
+      _ => (cover, tree, compact),
+    };
+    if stream.is_some() && header_footer.uses_variable("pages") {
+      warn!("The total number of pages isn't known when streaming, so `{{pages}}` is left empty");
     }
-    let mut wrapper = TextWrapper::new(font_bytes, font_size);
-    let additional_text = page_text.and_then(|text| ProcessedText::new(text, &mut wrapper));
-    walker.enumerate().par_bridge().for_each(|(i, result)| {
-      // let mut doc = PdfDocument::new(&args.name);
-      let c2pdf_mutex = local_c2pdf.get_or(|| {
-        Arc::new(Mutex::new(CodeToPdf::new(
-          doc_subset.clone(),
-          font_id.clone(),
-          page_dimensions.clone(),
-          wrapper.clone(),
-          additional_text.clone(),
-          include_path,
-        )))
+    let title = header_footer.project.clone();
+    let doc_subset = Arc::new(Mutex::new(
+      DocumentSubset::new(
+        header_footer,
+        page_dimensions.clone(),
+        font_id.clone(),
+        wrapper.clone(),
+      )
+      .with_recto_start(recto_start)
+      .with_compact(compact)
+      .with_cover(cover)
+      .with_tree(tree)
+      .with_stats(stats),
+    ));
+    // Pages are written in order as soon as each file is done, and the walker waits
+    // while too many files are ahead of the next one to be written
+    let writer = match stream {
+      Some(path) => {
+        let writer = File::create(&path).and_then(|file| {
+          StreamWriter::new(
+            BufWriter::new(file),
+            wrapper.font().clone(),
+            font_bytes,
+            &title,
+          )
+        });
+        Some(writer.map_err(|err| format!("Unable to create {}: {err}", path.display()))?)
+      }
+      None => None,
+    };
+    let buffer = writer
+      .is_some()
+      .then(|| ReorderBuffer::new(rayon::current_num_threads() * 2));
+    let written = thread::scope(|scope| {
+      let writer_thread = writer.map(|writer| {
+        let (buffer, doc_subset) = (buffer.as_ref(), &doc_subset);
+        scope.spawn(move || {
+          let buffer = buffer.expect("buffer should exist when streaming");
+          let written = write_stream(writer, buffer, doc_subset);
+          if written.is_err() {
+            // Keep taking files, so the walker doesn't wait forever
+            while buffer.take().is_some() {}
+          }
+          written
+        })
       });
-      let highlight_config_mutex = local_highlighter_config.get_or(|| {
-        Arc::new(Mutex::new(HighlighterConfig::new(
-          ss.clone(),
-          ts.get(two_face::theme::EmbeddedThemeName::InspiredGithub)
-            .clone(),
-        )))
+      let inputs = inputs.enumerate().inspect(|(i, _)| {
+        if let Some(buffer) = &buffer {
+          buffer.wait_for_space(*i);
+        }
       });
-      match result {
-        Ok(entry) => {
-          if entry.file_type().is_some_and(|f| f.is_file()) {
-            let path = entry.path();
-            trace!("Generating pages for {}, index {i}", path.display());
-            if let Err(err) = c2pdf_mutex.lock().unwrap().process_file(
-              path,
-              &highlight_config_mutex.lock().unwrap(),
+      inputs.par_bridge().for_each(|(i, mut input)| {
+        // let mut doc = PdfDocument::new(&args.name);
+        let c2pdf_mutex = local_c2pdf.get_or(|| {
+          Arc::new(Mutex::new(
+            CodeToPdf::new(
+              doc_subset.clone(),
+              font_id.clone(),
+              page_dimensions.clone(),
+              wrapper.clone(),
+            )
+            .with_auto_fit(auto_fit)
+            .with_file_header(file_header)
+            .with_last_commits(last_commits.clone())
+            .with_blame(blame.is_some())
+            .with_revision(revision.clone())
+            .with_language(language.clone()),
+          ))
+        });
+        let mut c2pdf = c2pdf_mutex.lock().unwrap();
+        let result = match &mut input {
+          Input::File(file) => {
+            trace!("Generating pages for {}, index {i}", file.path.display());
+            c2pdf.process_reader(
+              &file.path,
+              &mut file.reader,
+              file.on_disk,
+              &highlighter_config,
               i,
-            ) {
-              error!("ERROR: {}", err);
-            }
+            )
           }
-        }
-        Err(err) => {
+          Input::Diff(file_diff) => {
+            trace!(
+              "Generating changes for {}, index {i}",
+              file_diff.path.display()
+            );
+            c2pdf.process_diff(file_diff, diff_style, &highlighter_config, i)
+          }
+        };
+        if let Err(err) = result {
           error!("ERROR: {}", err);
         }
+        if let Some(buffer) = &buffer {
+          buffer.insert(i, doc_subset.lock().unwrap().take_file(i));
+        }
+      });
+      if let Some(buffer) = &buffer {
+        buffer.close();
+      }
+      match writer_thread.map(|writer_thread| writer_thread.join()) {
+        Some(Ok(written)) => written.map_err(|err| format!("Unable to write the PDF: {err}")),
+        Some(Err(_)) => Err("Unable to write the PDF".to_string()),
+        None => Ok(()),
       }
     });
+    written?;
     let mut processed_file_count = 0;
     for local in local_c2pdf.iter() {
       processed_file_count += local.lock().unwrap().processed_file_count();
     }
 
     // doc_subset.lock().unwrap().to_document(doc);
-    (doc_subset, processed_file_count)
+    Ok((doc_subset, processed_file_count))
+  }
+}
+
+/// Writes the pages of each file from `buffer` to `writer` in order, as soon as they're ready,
+/// followed by the appendix with statistics (if enabled)
+fn write_stream<W: Write>(
+  mut writer: StreamWriter<W>,
+  buffer: &ReorderBuffer<FilePages>,
+  doc_subset: &Mutex<DocumentSubset>,
+) -> io::Result<()> {
+  while let Some(mut file) = buffer.take() {
+    let (pages, bookmark) = doc_subset
+      .lock()
+      .unwrap()
+      .lay_out_file(&mut file, writer.page_count() + 1);
+    if let Some((name, page)) = bookmark {
+      writer.add_bookmark(&name, page);
+    }
+    for page in &pages {
+      writer.write_page(page, &file.xobjects)?;
+    }
+  }
+  let stats_pages = doc_subset
+    .lock()
+    .unwrap()
+    .stats_pages(writer.page_count() + 1);
+  for page in &stats_pages {
+    writer.write_page(page, &BTreeMap::new())?;
+  }
+  writer.finish()?;
+  Ok(())
+}
+
+/// Renders `code` to the bytes of a PDF, using the bundled font.
+///
+/// `title` is shown in the header, and is used to select the syntax (by its extension) unless
+/// [`RunOptions::language`] is set. [`RunOptions::stream`] is ignored, as the PDF is returned instead
+pub fn render_str(code: &str, title: &str, options: RunOptions) -> Vec<u8> {
+  let mut doc = PdfDocument::new(title);
+  let (font_bytes, _) = match options.grid {
+    GridMode::Proportional => font_loader::load_font(None),
+    _ => font_loader::load_monospace_font(None),
+  };
+  let font =
+    ParsedFont::from_bytes(&font_bytes, 0, &mut vec![]).expect("bundled font should be valid");
+  let font_id = doc.add_font(&font);
+  let source = MemorySource::new(vec![(PathBuf::from(title), code.as_bytes().to_vec())]);
+  let options = RunOptions {
+    stream: None,
+    ..options
+  };
+  let (doc_subset, _) = CodeToPdf::run_source(font_id, &font_bytes, source, options)
+    .expect("only streaming can fail, which is ignored");
+  doc_subset.lock().unwrap().to_document(&mut doc);
+  doc.save(&PdfSaveOptions::default(), &mut vec![])
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn rendering_strings() {
+    let options = RunOptions {
+      language: Some("rust".to_string()),
+      ..Default::default()
+    };
+    let pdf = render_str("fn main() {}\n", "snippet", options);
+    assert!(pdf.starts_with(b"%PDF"));
+    // Streaming is ignored, so the PDF is still returned
+    let out = std::env::temp_dir().join(format!("c2pdf-render-{}.pdf", std::process::id()));
+    let options = RunOptions {
+      stream: Some(out.clone()),
+      ..Default::default()
+    };
+    let pdf = render_str("fn main() {}\n", "snippet.rs", options);
+    let doc = PdfDocument::parse(&pdf, &Default::default(), &mut vec![]).unwrap();
+    assert_eq!(doc.pages.len(), 1);
+    assert!(!out.exists());
+  }
+  #[test]
+  fn streaming_pages() {
+    let dir = create_files("stream", &[]);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("a.rs"), "fn main() {}\n").unwrap();
+    std::fs::write(dir.join("b.rs"), "fn b() {}\n".repeat(100)).unwrap();
+    std::fs::copy(
+      Path::new(env!("CARGO_MANIFEST_DIR")).join("../../assets/logo.png"),
+      dir.join("logo.png"),
+    )
+    .unwrap();
+    let out = dir.with_extension("pdf");
+    let (font_bytes, _) = font_loader::load_font(None);
+    let mut doc = PdfDocument::new("stream");
+    let font = ParsedFont::from_bytes(&font_bytes, 0, &mut vec![]).unwrap();
+    let font_id = doc.add_font(&font);
+    let options = RunOptions {
+      header_footer: HeaderFooter {
+        footer: header_footer::Slots {
+          right: Some("Page {page} of {pages}.".to_string()),
+          ..Default::default()
+        },
+        ..HeaderFooter::new("stream".to_string(), &dir)
+      },
+      sort: Some(SortOrder::Name),
+      stream: Some(out.clone()),
+      ..Default::default()
+    };
+    let (_, file_count) =
+      CodeToPdf::run_parallel(font_id.clone(), &font_bytes, dir.clone(), options).unwrap();
+    assert_eq!(file_count, 3);
+    let pdf = std::fs::read(&out).unwrap();
+    let doc = PdfDocument::parse(&pdf, &Default::default(), &mut vec![]).unwrap();
+    assert_eq!(doc.pages.len(), 4);
+    // The total number of pages isn't known while streaming
+    for (i, page) in doc.pages.iter().enumerate() {
+      let text = page.extract_text(&doc.resources);
+      assert!(text[0].ends_with(&format!("Page {} of .", i + 1)));
+    }
+    let mut bookmarks = doc
+      .bookmarks
+      .map
+      .values()
+      .map(|bookmark| (bookmark.name.clone(), bookmark.page))
+      .collect::<Vec<_>>();
+    bookmarks.sort_by_key(|(_, page)| *page);
+    let file = |name: &str| dir.join(name).to_string_lossy().into_owned();
+    assert_eq!(
+      bookmarks,
+      [(file("a.rs"), 1), (file("b.rs"), 2), (file("logo.png"), 4)]
+    );
+    // The image is drawn on the last page, and embedded in the file
+    assert!(
+      doc.pages[3]
+        .ops
+        .iter()
+        .any(|op| matches!(op, printpdf::Op::UseXobject { .. }))
+    );
+    assert!(String::from_utf8_lossy(&pdf).contains("/Subtype/Image"));
+    // Failing to create the output is an error, rather than an empty result
+    let options = RunOptions {
+      stream: Some(dir.join("missing").join("out.pdf")),
+      ..Default::default()
+    };
+    let result = CodeToPdf::run_parallel(font_id, &font_bytes, dir.clone(), options);
+    assert!(result.is_err_and(|err| err.starts_with("Unable to create")));
+    std::fs::remove_dir_all(&dir).unwrap();
+    std::fs::remove_file(&out).unwrap();
+  }
+  /// Creates a directory containing the given files, with a unique name
+  fn create_files(name: &str, files: &[&str]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("c2pdf-{name}-{}", std::process::id()));
+    for file in files {
+      let path = dir.join(file);
+      std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+      std::fs::write(path, "").unwrap();
+    }
+    dir
+  }
+  #[test]
+  fn including_files() {
+    let dir = create_files("include", &["a.rs", "b.txt", "src/c.rs", "src/d.toml"]);
+    let walk = |inclusions: &[&str], exclusions: &[&str]| {
+      let to_strings = |globs: &[&str]| {
+        globs
+          .iter()
+          .map(|glob| glob.to_string())
+          .collect::<Vec<_>>()
+      };
+      let (walker, _) = build_walker(
+        &dir,
+        &to_strings(exclusions),
+        &to_strings(inclusions),
+        false,
+        Some(&SortOrder::Name),
+      )
+      .unwrap();
+      walker
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_some_and(|f| f.is_file()))
+        .map(|entry| entry.path().strip_prefix(&dir).unwrap().to_path_buf())
+        .collect::<Vec<_>>()
+    };
+    assert_eq!(
+      walk(&["*.rs"], &[]),
+      vec![PathBuf::from("a.rs"), PathBuf::from("src/c.rs")]
+    );
+    // Exclusions take precedence
+    assert_eq!(walk(&["*.rs"], &["src"]), vec![PathBuf::from("a.rs")]);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+  #[test]
+  fn listing_files() {
+    let dir = Path::new("project");
+    let overrides = build_overrides(dir, &["target".to_string()], &["*.rs".to_string()]).unwrap();
+    assert!(
+      build_overrides(dir, &[], &["src/[".to_string()])
+        .is_err_and(|err| err.starts_with("Invalid glob `src/[`"))
+    );
+    let files = ["./src/b.rs", "a.rs", "notes.txt", "target/c.rs"]
+      .into_iter()
+      .map(PathBuf::from)
+      .collect();
+    // Paths are resolved against the directory, keeping the order they're listed in
+    assert_eq!(
+      listed_files(dir, files, &overrides, None),
+      vec![dir.join("src/b.rs"), dir.join("a.rs")]
+    );
+    let tracked = TrackedFiles {
+      files: HashSet::from([dir.join("a.rs")]),
+      directories: HashSet::from([dir.to_path_buf()]),
+    };
+    let files = vec![PathBuf::from("src/b.rs"), PathBuf::from("a.rs")];
+    assert_eq!(
+      listed_files(dir, files, &overrides, Some(&tracked)),
+      vec![dir.join("a.rs")]
+    );
+  }
+  #[test]
+  fn listing_files_from_revisions() {
+    let options = RunOptions {
+      files: Some(vec![PathBuf::from("a.rs")]),
+      revision: Some("HEAD".to_string()),
+      ..Default::default()
+    };
+    let font_bytes = include_bytes!("../../fonts/Helvetica.ttf");
+    let mut doc = printpdf::PdfDocument::new("");
+    let font = printpdf::ParsedFont::from_bytes(font_bytes, 0, &mut vec![]).unwrap();
+    let font_id = doc.add_font(&font);
+    let result = CodeToPdf::run_parallel(font_id, font_bytes, PathBuf::from("."), options);
+    assert!(result.is_err());
   }
 }
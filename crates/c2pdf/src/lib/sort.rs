@@ -0,0 +1,245 @@
+//! Orders for the files (and directories) within each directory of the walk
+
+use std::{
+  cmp::Ordering,
+  collections::HashMap,
+  fs,
+  path::{Path, PathBuf},
+  str::FromStr,
+  sync::Mutex,
+  time::SystemTime,
+};
+
+/// How files and directories are ordered within each directory.
+///
+/// Files are always printed before directories. Entries which are equal under an order are sorted by name
+#[derive(Debug, Clone, PartialEq)]
+pub enum SortOrder {
+  /// By name
+  Name,
+  /// By name, comparing runs of digits by their value (so `file2` comes before `file10`)
+  Natural,
+  /// By extension, then by name
+  Extension,
+  /// Smallest files first
+  Size,
+  /// Least recently modified files first
+  Modified,
+  /// The paths listed (relative to the root of the walk) come first, in the order they're listed,
+  /// followed by everything else by name. Listing a file also brings the directories containing it forwards
+  Manual(Vec<PathBuf>),
+}
+impl FromStr for SortOrder {
+  type Err = String;
+  /// Parses the name of an order. The paths for [`SortOrder::Manual`] are left empty
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.trim().to_ascii_lowercase().as_str() {
+      "name" => Ok(Self::Name),
+      "natural" => Ok(Self::Natural),
+      "extension" => Ok(Self::Extension),
+      "size" => Ok(Self::Size),
+      "mtime" => Ok(Self::Modified),
+      "manual" => Ok(Self::Manual(vec![])),
+      _ => Err(format!(
+        "Invalid sort order `{s}`. Expected `name`, `natural`, `extension`, `size`, `mtime`, or `manual`"
+      )),
+    }
+  }
+}
+impl SortOrder {
+  /// Makes the paths of [`SortOrder::Manual`] relative to the current directory, in the same way as the walker,
+  /// given the `root` of the walk
+  pub fn with_root(self, root: &Path) -> Self {
+    match self {
+      Self::Manual(paths) => Self::Manual(paths.iter().map(|path| root.join(path)).collect()),
+      order => order,
+    }
+  }
+  /// Compares two entries in the same directory, reading their metadata from disk if needed
+  pub fn compare(&self, a: &Path, b: &Path) -> Ordering {
+    self.compare_with(a, b, EntryMetadata::read)
+  }
+  /// Compares two entries in the same directory, using `metadata` to find the size and modification time
+  /// of each entry if needed
+  pub fn compare_with(
+    &self,
+    a: &Path,
+    b: &Path,
+    metadata: impl Fn(&Path) -> EntryMetadata,
+  ) -> Ordering {
+    let by_name = || a.file_name().cmp(&b.file_name());
+    match self {
+      Self::Name => by_name(),
+      Self::Natural => natural_cmp(&file_name(a), &file_name(b)),
+      Self::Extension => a.extension().cmp(&b.extension()).then_with(by_name),
+      Self::Size => {
+        let size = |path: &Path| metadata(path).size;
+        size(a).cmp(&size(b)).then_with(by_name)
+      }
+      Self::Modified => {
+        let modified = |path: &Path| metadata(path).modified;
+        modified(a).cmp(&modified(b)).then_with(by_name)
+      }
+      Self::Manual(paths) => {
+        // Position of the first listed path which is (or is inside) the entry
+        let rank = |entry: &Path| {
+          paths
+            .iter()
+            .position(|path| path.starts_with(entry))
+            .unwrap_or(usize::MAX)
+        };
+        rank(a).cmp(&rank(b)).then_with(by_name)
+      }
+    }
+  }
+}
+
+/// The metadata of an entry compared by [`SortOrder::Size`] and [`SortOrder::Modified`]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct EntryMetadata {
+  /// Size in bytes
+  pub size: u64,
+  /// Time the entry was last modified, if known
+  pub modified: Option<SystemTime>,
+}
+impl EntryMetadata {
+  /// Reads the metadata of the entry at `path` on disk, or returns the default if it can't be read
+  pub fn read(path: &Path) -> Self {
+    fs::metadata(path)
+      .map(|metadata| Self {
+        size: metadata.len(),
+        modified: metadata.modified().ok(),
+      })
+      .unwrap_or_default()
+  }
+}
+
+/// Reads the metadata of entries on disk, keeping it so that each entry is only read once while sorting
+#[derive(Debug, Default)]
+pub struct MetadataCache(Mutex<HashMap<PathBuf, EntryMetadata>>);
+impl MetadataCache {
+  /// Returns the metadata of the entry at `path`, reading it if it hasn't been read yet
+  pub fn get(&self, path: &Path) -> EntryMetadata {
+    let mut cache = self.0.lock().unwrap();
+    if let Some(metadata) = cache.get(path) {
+      return *metadata;
+    }
+    let metadata = EntryMetadata::read(path);
+    cache.insert(path.to_path_buf(), metadata);
+    metadata
+  }
+}
+
+/// Returns the file name of `path` as a string, replacing invalid UTF-8
+fn file_name(path: &Path) -> String {
+  path
+    .file_name()
+    .map(|name| name.to_string_lossy().into_owned())
+    .unwrap_or_default()
+}
+
+/// Compares two strings, treating each run of digits as a number
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+  let mut a = a.chars().peekable();
+  let mut b = b.chars().peekable();
+  loop {
+    match (a.peek().copied(), b.peek().copied()) {
+      (None, None) => return Ordering::Equal,
+      (None, Some(_)) => return Ordering::Less,
+      (Some(_), None) => return Ordering::Greater,
+      (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+        let number = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+          let mut digits = String::new();
+          while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+            digits.push(digit);
+          }
+          digits
+        };
+        let (x, y) = (number(&mut a), number(&mut b));
+        // Compares the values without parsing them, so any number of digits works
+        let (x_trimmed, y_trimmed) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+        let ordering = x_trimmed
+          .len()
+          .cmp(&y_trimmed.len())
+          .then_with(|| x_trimmed.cmp(y_trimmed))
+          .then_with(|| x.len().cmp(&y.len()));
+        if ordering != Ordering::Equal {
+          return ordering;
+        }
+      }
+      (Some(x), Some(y)) => {
+        if x != y {
+          return x.cmp(&y);
+        }
+        a.next();
+        b.next();
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn natural_order() {
+    let mut names = vec!["file10.rs", "file2.rs", "file1.rs", "file02.rs", "a.rs"];
+    names.sort_by(|a, b| natural_cmp(a, b));
+    assert_eq!(
+      names,
+      vec!["a.rs", "file1.rs", "file2.rs", "file02.rs", "file10.rs"]
+    );
+  }
+  #[test]
+  fn sorting_with_metadata() {
+    let metadata = |path: &Path| EntryMetadata {
+      size: match path.to_str() {
+        Some("big.rs") => 100,
+        _ => 10,
+      },
+      modified: None,
+    };
+    let mut entries = vec!["big.rs", "c.rs", "a.rs"]
+      .into_iter()
+      .map(PathBuf::from)
+      .collect::<Vec<_>>();
+    entries.sort_by(|a, b| SortOrder::Size.compare_with(a, b, metadata));
+    assert_eq!(
+      entries,
+      vec!["a.rs", "c.rs", "big.rs"]
+        .into_iter()
+        .map(PathBuf::from)
+        .collect::<Vec<_>>()
+    );
+    // Entries without a modification time are sorted by name
+    entries.sort_by(|a, b| SortOrder::Modified.compare_with(a, b, metadata));
+    assert_eq!(
+      entries,
+      vec!["a.rs", "big.rs", "c.rs"]
+        .into_iter()
+        .map(PathBuf::from)
+        .collect::<Vec<_>>()
+    );
+  }
+  #[test]
+  fn manual_order() {
+    let order = SortOrder::Manual(vec![
+      PathBuf::from("README.md"),
+      PathBuf::from("src/main.rs"),
+    ])
+    .with_root(Path::new("root"));
+    let mut entries = vec!["root/b.rs", "root/src", "root/README.md", "root/a.rs"]
+      .into_iter()
+      .map(PathBuf::from)
+      .collect::<Vec<_>>();
+    entries.sort_by(|a, b| order.compare(a, b));
+    assert_eq!(
+      entries,
+      vec!["root/README.md", "root/src", "root/a.rs", "root/b.rs"]
+        .into_iter()
+        .map(PathBuf::from)
+        .collect::<Vec<_>>()
+    );
+  }
+}
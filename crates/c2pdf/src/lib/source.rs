@@ -0,0 +1,144 @@
+//! Contains the [`Source`] trait, which provides the files to generate pages for, along with its implementations
+//!
+//! Files don't need to be on disk, so sources can read them from anywhere (such as an archive or a database)
+
+use std::{
+  fs::File,
+  io::{self, BufRead, BufReader, Cursor, Read},
+  path::PathBuf,
+  vec,
+};
+
+use ignore::Walk;
+use log::warn;
+
+/// A file to generate pages for
+pub struct SourceFile {
+  /// Logical path of the file, which is used to select the syntax, and shown in the header
+  pub path: PathBuf,
+  /// Contents of the file
+  pub reader: Box<dyn BufRead + Send>,
+  /// Whether `path` is the path of the file on disk,
+  /// so that information such as its modification time and git history can be read
+  pub on_disk: bool,
+}
+impl SourceFile {
+  /// Initialises a [`SourceFile`] which isn't on disk, containing `contents`
+  pub fn from_bytes(path: PathBuf, contents: Vec<u8>) -> Self {
+    Self {
+      path,
+      reader: Box::new(Cursor::new(contents)),
+      on_disk: false,
+    }
+  }
+  /// Opens the file on disk at `path`
+  pub fn open(path: PathBuf) -> io::Result<Self> {
+    let file = File::open(&path)?;
+    Ok(Self {
+      path,
+      reader: Box::new(BufReader::new(file)),
+      on_disk: true,
+    })
+  }
+}
+
+/// Provides the files to generate pages for, in the order they appear in the PDF
+pub trait Source: Send {
+  /// Returns the next file, or `None` once every file has been returned
+  fn next_file(&mut self) -> Option<io::Result<SourceFile>>;
+}
+
+/// Returns an iterator over the files from `source`
+pub fn files(mut source: impl Source) -> impl Iterator<Item = io::Result<SourceFile>> + Send {
+  std::iter::from_fn(move || source.next_file())
+}
+
+/// Reads the contents of every file from `source` into memory
+pub fn read_all(source: impl Source) -> io::Result<Vec<(PathBuf, Vec<u8>)>> {
+  files(source)
+    .map(|file| {
+      let mut file = file?;
+      let mut contents = vec![];
+      file.reader.read_to_end(&mut contents)?;
+      Ok((file.path, contents))
+    })
+    .collect()
+}
+
+/// The files found by a walker, which are read from disk
+pub struct WalkSource(pub Walk);
+impl Source for WalkSource {
+  fn next_file(&mut self) -> Option<io::Result<SourceFile>> {
+    loop {
+      let entry = match self.0.next()? {
+        Ok(entry) => entry,
+        Err(err) => return Some(Err(io::Error::other(err))),
+      };
+      if entry.file_type().is_some_and(|f| f.is_file()) {
+        return Some(SourceFile::open(entry.into_path()));
+      }
+    }
+  }
+}
+
+/// A list of paths of files on disk, in the order they're given. Paths which aren't files are skipped
+pub struct FileListSource(vec::IntoIter<PathBuf>);
+impl FileListSource {
+  /// Initialises a new [`FileListSource`] with the given paths
+  pub fn new(paths: Vec<PathBuf>) -> Self {
+    Self(paths.into_iter())
+  }
+}
+impl Source for FileListSource {
+  fn next_file(&mut self) -> Option<io::Result<SourceFile>> {
+    loop {
+      let path = self.0.next()?;
+      if path.is_file() {
+        return Some(SourceFile::open(path));
+      }
+      warn!("Skipping {}, as it isn't a file", path.display());
+    }
+  }
+}
+
+/// Files which have already been read into memory, along with their logical paths
+pub struct MemorySource(vec::IntoIter<(PathBuf, Vec<u8>)>);
+impl MemorySource {
+  /// Initialises a new [`MemorySource`] with the given files
+  pub fn new(files: Vec<(PathBuf, Vec<u8>)>) -> Self {
+    Self(files.into_iter())
+  }
+}
+impl Source for MemorySource {
+  fn next_file(&mut self) -> Option<io::Result<SourceFile>> {
+    let (path, contents) = self.0.next()?;
+    Some(Ok(SourceFile::from_bytes(path, contents)))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn reading_sources() {
+    let memory = MemorySource::new(vec![(PathBuf::from("a.rs"), b"fn a() {}".to_vec())]);
+    let mut file = files(memory).next().unwrap().unwrap();
+    let mut contents = String::new();
+    file.reader.read_to_string(&mut contents).unwrap();
+    assert_eq!(file.path, PathBuf::from("a.rs"));
+    assert_eq!(contents, "fn a() {}");
+    assert!(!file.on_disk);
+    // Paths which aren't files are skipped
+    let list = FileListSource::new(vec![PathBuf::from("src"), PathBuf::from("Cargo.toml")]);
+    let paths = files(list)
+      .map(|file| file.unwrap().path)
+      .collect::<Vec<_>>();
+    assert_eq!(paths, vec![PathBuf::from("Cargo.toml")]);
+    let memory = MemorySource::new(vec![(PathBuf::from("b.rs"), b"fn b() {}".to_vec())]);
+    assert_eq!(
+      read_all(memory).unwrap(),
+      vec![(PathBuf::from("b.rs"), b"fn b() {}".to_vec())]
+    );
+  }
+}
@@ -0,0 +1,178 @@
+//! Splits the output into several PDFs (such as to stay under an upload limit)
+//!
+//! Pages are numbered across every part, so the first page of the second part follows on from the last page of the first
+
+use std::{
+  num::NonZeroUsize,
+  path::{Path, PathBuf},
+};
+
+/// How the output is split into parts
+#[derive(Debug, Clone, PartialEq)]
+pub enum Split {
+  /// At most this many pages in each part
+  Pages(NonZeroUsize),
+  /// Each part is at most roughly this many bytes.
+  /// Sizes are estimated conservatively from the contents of each page and the fonts each part embeds,
+  /// so parts can end up smaller than this
+  Size(u64),
+  /// A part for each directory directly inside the walk path. Files directly inside the walk path share a part
+  TopLevelDir,
+}
+impl Split {
+  /// Parses the value of `--split-by`
+  pub fn parse_by(s: &str) -> Result<Self, String> {
+    match s.trim().to_ascii_lowercase().as_str() {
+      "top-level-dir" => Ok(Self::TopLevelDir),
+      _ => Err(format!(
+        "Invalid way to split the output `{s}`. Expected `top-level-dir`"
+      )),
+    }
+  }
+  /// Returns the number of pages in each part, given the estimated size of each page in bytes,
+  /// and the top-level directory of the file on each page (`None` for pages which aren't part of a file,
+  /// which are kept with the file after them). `part_overhead` is the estimated size of everything else in each part,
+  /// such as its fonts
+  pub fn part_lengths(
+    &self,
+    page_sizes: &[u64],
+    page_dirs: &[Option<PathBuf>],
+    part_overhead: u64,
+  ) -> Vec<usize> {
+    let page_count = page_sizes.len();
+    if page_count == 0 {
+      return vec![];
+    }
+    match self {
+      Self::Pages(pages) => {
+        let pages = pages.get();
+        (0..page_count.div_ceil(pages))
+          .map(|part| pages.min(page_count - part * pages))
+          .collect()
+      }
+      Self::Size(max_size) => {
+        let mut lengths = vec![];
+        let (mut length, mut size) = (0, part_overhead);
+        for &page_size in page_sizes {
+          if length > 0 && size + page_size > *max_size {
+            lengths.push(length);
+            (length, size) = (0, part_overhead);
+          }
+          length += 1;
+          size += page_size;
+        }
+        lengths.push(length);
+        lengths
+      }
+      Self::TopLevelDir => {
+        // Pages which aren't part of a file take the directory of the next file (or the previous one, at the end)
+        let mut dirs = page_dirs.to_vec();
+        for i in (0..dirs.len().saturating_sub(1)).rev() {
+          if dirs[i].is_none() {
+            dirs[i] = dirs[i + 1].clone();
+          }
+        }
+        for i in 1..dirs.len() {
+          if dirs[i].is_none() {
+            dirs[i] = dirs[i - 1].clone();
+          }
+        }
+        let mut lengths: Vec<usize> = vec![];
+        for (i, dir) in dirs.iter().enumerate() {
+          match lengths.last_mut() {
+            Some(length) if *dir == dirs[i - 1] => *length += 1,
+            _ => lengths.push(1),
+          }
+        }
+        lengths
+      }
+    }
+  }
+}
+
+/// Parses a size in bytes, with an optional unit, such as `50MB`, `1.5 GiB`, or `2000`. Fails if it's less than a byte
+pub fn parse_size(s: &str) -> Result<u64, String> {
+  let s = s.trim();
+  let split = s
+    .find(|c: char| !c.is_ascii_digit() && c != '.')
+    .unwrap_or(s.len());
+  let (number, unit) = s.split_at(split);
+  let number = number
+    .parse::<f64>()
+    .map_err(|_| format!("Invalid size `{s}`"))?;
+  let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+    "" | "b" => 1,
+    "kb" => 1000,
+    "mb" => 1_000_000,
+    "gb" => 1_000_000_000,
+    "kib" => 1 << 10,
+    "mib" => 1 << 20,
+    "gib" => 1 << 30,
+    _ => {
+      return Err(format!(
+        "Invalid unit in size `{s}`. Expected B, KB, MB, GB, KiB, MiB, or GiB"
+      ));
+    }
+  };
+  match (number * multiplier as f64) as u64 {
+    0 => Err(format!("The size `{s}` must be at least 1 byte")),
+    size => Ok(size),
+  }
+}
+
+/// Returns the path of the part with the given (1-based) `number`, such as `output-001.pdf` for `output.pdf`
+pub fn part_path(out: &Path, number: usize) -> PathBuf {
+  let stem = out
+    .file_stem()
+    .map(|stem| stem.to_string_lossy().into_owned())
+    .unwrap_or_default();
+  let name = match out.extension() {
+    Some(extension) => format!("{stem}-{number:03}.{}", extension.to_string_lossy()),
+    None => format!("{stem}-{number:03}"),
+  };
+  out.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parsing_sizes() {
+    assert_eq!(parse_size("50MB"), Ok(50_000_000));
+    assert_eq!(parse_size("1.5 KiB"), Ok(1536));
+    assert_eq!(parse_size("2000"), Ok(2000));
+    assert!(parse_size("50 parsecs").is_err());
+    assert!(parse_size("0MB").is_err());
+    assert!(parse_size("0.1").is_err());
+  }
+  #[test]
+  fn naming_parts() {
+    assert_eq!(
+      part_path(Path::new("out/output.pdf"), 2),
+      PathBuf::from("out/output-002.pdf")
+    );
+  }
+  #[test]
+  fn splitting_pages() {
+    let sizes = [10, 10, 30, 10, 10];
+    let no_dirs = [None, None, None, None, None];
+    let pages = Split::Pages(NonZeroUsize::new(2).unwrap());
+    assert_eq!(pages.part_lengths(&sizes, &no_dirs, 0), vec![2, 2, 1]);
+    // A page bigger than the limit gets a part of its own
+    assert_eq!(
+      Split::Size(25).part_lengths(&sizes, &no_dirs, 0),
+      vec![2, 1, 2]
+    );
+    // Each part has room for fewer pages once its fonts are counted
+    assert_eq!(
+      Split::Size(25).part_lengths(&sizes, &no_dirs, 6),
+      vec![1; 5]
+    );
+    let dirs = [None, Some("a"), Some("a"), None, Some("b")].map(|dir| dir.map(PathBuf::from));
+    assert_eq!(
+      Split::TopLevelDir.part_lengths(&sizes, &dirs, 0),
+      vec![3, 2]
+    );
+  }
+}
@@ -0,0 +1,331 @@
+//! Per-language statistics (in the style of `tokei`), shown in an appendix and written to a JSON report
+
+use std::collections::{BTreeMap, HashMap};
+
+use printpdf::{FontId, Op, Point, Pt, TextMatrix};
+use serde_json::{Value, json};
+use syntect::{
+  easy::ScopeRangeIterator,
+  parsing::{Scope, ScopeStack, ScopeStackOp},
+};
+
+use crate::{
+  code_to_pdf::FileInfo, dimensions::Dimensions, helpers::init_page, text_manipulation::TextWrapper,
+};
+
+/// Title at the top of the first page of the appendix
+const TITLE: &str = "Statistics";
+/// Headings of the columns of the table, after the language
+const HEADINGS: [&str; 4] = ["Files", "Code", "Comments", "Blanks"];
+
+/// Number of each kind of line in a file (or group of files)
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LineCounts {
+  /// Lines containing code
+  pub code: usize,
+  /// Lines only containing comments
+  pub comments: usize,
+  /// Lines only containing whitespace
+  pub blanks: usize,
+}
+impl LineCounts {
+  /// Total number of lines
+  pub fn total(&self) -> usize {
+    self.code + self.comments + self.blanks
+  }
+  /// Counts a single line of the given kind
+  pub fn add(&mut self, kind: LineKind) {
+    match kind {
+      LineKind::Code => self.code += 1,
+      LineKind::Comment => self.comments += 1,
+      LineKind::Blank => self.blanks += 1,
+    }
+  }
+}
+
+/// Kind of a single line
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineKind {
+  /// Contains at least some code
+  Code,
+  /// Everything other than whitespace is in a `comment.*` scope
+  Comment,
+  /// Only contains whitespace
+  Blank,
+}
+
+/// Keeps track of the scopes from [`syntect`]'s parser to classify each line of a file
+pub struct LineClassifier {
+  stack: ScopeStack,
+  comment: Scope,
+}
+impl Default for LineClassifier {
+  fn default() -> Self {
+    Self {
+      stack: ScopeStack::new(),
+      comment: Scope::new("comment").expect("`comment` should be a valid scope"),
+    }
+  }
+}
+impl LineClassifier {
+  /// Classifies `line`, given the operations from parsing it.
+  ///
+  /// Must be called for every parsed line, in order, to keep the scopes in sync with the parser
+  pub fn classify(&mut self, line: &str, ops: &[(usize, ScopeStackOp)]) -> LineKind {
+    let mut has_code = false;
+    let mut has_comment = false;
+    for (range, op) in ScopeRangeIterator::new(ops, line) {
+      // The scopes are still kept in sync if applying an op fails, as it only fails on unmatched pops
+      _ = self.stack.apply(op);
+      if line[range].trim().is_empty() {
+        continue;
+      }
+      let in_comment = self
+        .stack
+        .as_slice()
+        .iter()
+        .any(|scope| self.comment.is_prefix_of(*scope));
+      if in_comment {
+        has_comment = true;
+      } else {
+        has_code = true;
+      }
+    }
+    match (has_code, has_comment) {
+      (true, _) => LineKind::Code,
+      (false, true) => LineKind::Comment,
+      (false, false) => LineKind::Blank,
+    }
+  }
+}
+
+/// Totals for every file in a language
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LanguageStats {
+  /// Name of the language
+  pub language: String,
+  /// Number of files
+  pub files: usize,
+  /// Lines across all the files
+  pub lines: LineCounts,
+}
+
+/// Groups `files` by language, ordered by the number of lines of code (most first).
+/// Files without a language (such as images) are left out
+pub fn language_stats(files: &BTreeMap<usize, FileInfo>) -> Vec<LanguageStats> {
+  let mut languages: HashMap<&str, LanguageStats> = HashMap::new();
+  for file in files.values() {
+    let Some(language) = &file.language else {
+      continue;
+    };
+    let stats = languages.entry(language).or_insert_with(|| LanguageStats {
+      language: language.clone(),
+      ..Default::default()
+    });
+    stats.files += 1;
+    stats.lines.code += file.line_counts.code;
+    stats.lines.comments += file.line_counts.comments;
+    stats.lines.blanks += file.line_counts.blanks;
+  }
+  let mut languages = languages.into_values().collect::<Vec<_>>();
+  languages.sort_by(|a, b| {
+    b.lines
+      .code
+      .cmp(&a.lines.code)
+      .then_with(|| a.language.cmp(&b.language))
+  });
+  languages
+}
+
+/// Returns the report of every file and language, ready to be written as JSON
+pub fn report(files: &BTreeMap<usize, FileInfo>) -> Value {
+  let counts = |lines: &LineCounts| {
+    json!({
+      "lines": lines.total(),
+      "code": lines.code,
+      "comments": lines.comments,
+      "blanks": lines.blanks,
+    })
+  };
+  let files_report = files
+    .values()
+    .map(|file| {
+      let mut entry = counts(&file.line_counts);
+      entry["path"] = json!(file.path.display().to_string());
+      entry["language"] = json!(file.language);
+      entry["size"] = json!(file.size);
+      entry
+    })
+    .collect::<Vec<_>>();
+  let languages_report = language_stats(files)
+    .iter()
+    .map(|stats| {
+      let mut entry = counts(&stats.lines);
+      entry["language"] = json!(stats.language);
+      entry["files"] = json!(stats.files);
+      entry
+    })
+    .collect::<Vec<_>>();
+  json!({
+    "files": files_report,
+    "languages": languages_report,
+  })
+}
+
+/// Writes the appendix with a table of the statistics for each language, returning the contents of each page
+pub fn render(
+  page_dimensions: &Dimensions,
+  font_id: &FontId,
+  wrapper: &mut TextWrapper,
+  files: &BTreeMap<usize, FileInfo>,
+) -> Vec<Vec<Op>> {
+  let languages = language_stats(files);
+  let mut total = LanguageStats {
+    language: "Total".to_string(),
+    ..Default::default()
+  };
+  for stats in &languages {
+    total.files += stats.files;
+    total.lines.code += stats.lines.code;
+    total.lines.comments += stats.lines.comments;
+    total.lines.blanks += stats.lines.blanks;
+  }
+  let font_size = wrapper.font_size();
+  let line_height = Pt(font_size * 1.2);
+  let max_line_count = page_dimensions.max_line_count(font_size).max(1) as usize;
+  let left = page_dimensions.margin_left.into_pt();
+  let right = (page_dimensions.width - page_dimensions.margin_right).into_pt();
+  // Every column of numbers is the same width, and right-aligned
+  let column_width = wrapper.get_width("000000000000");
+  let column_right = |column: usize| right - column_width * (HEADINGS.len() - 1 - column) as f32;
+
+  let mut pages = vec![];
+  let mut ops = vec![];
+  let mut write = |ops: &mut Vec<Op>, text: &str, x: Pt, y: Pt, right_aligned: bool| {
+    let x = if right_aligned {
+      x - wrapper.get_width(text)
+    } else {
+      x
+    };
+    ops.extend_from_slice(&[
+      Op::SetTextMatrix {
+        matrix: TextMatrix::Translate(Pt(0.0), Pt(0.0)),
+      },
+      Op::SetTextCursor {
+        pos: Point { x, y },
+      },
+      Op::WriteText {
+        items: wrapper.text_items(text),
+        font: font_id.clone(),
+      },
+    ]);
+  };
+  let headings = ["Language"]
+    .into_iter()
+    .chain(HEADINGS)
+    .map(str::to_string)
+    .collect::<Vec<_>>();
+  let row = |stats: &LanguageStats| {
+    vec![
+      stats.language.clone(),
+      stats.files.to_string(),
+      stats.lines.code.to_string(),
+      stats.lines.comments.to_string(),
+      stats.lines.blanks.to_string(),
+    ]
+  };
+  // The title and a blank line, then the headings, each language, a blank line, and the total
+  let rows = [Some(vec![TITLE.to_string()]), None, Some(headings)]
+    .into_iter()
+    .chain(languages.iter().map(|stats| Some(row(stats))))
+    .chain([None, Some(row(&total))])
+    .enumerate();
+  for (i, cells) in rows {
+    let row_on_page = i % max_line_count;
+    if row_on_page == 0 {
+      if !ops.is_empty() {
+        ops.push(Op::EndTextSection);
+        pages.push(std::mem::take(&mut ops));
+      }
+      init_page(&mut ops, page_dimensions, font_id.clone(), font_size);
+    }
+    let y = page_dimensions.body_top().into_pt() - line_height * row_on_page as f32;
+    for (column, cell) in cells.iter().flatten().enumerate() {
+      match column {
+        0 => write(&mut ops, cell, left, y, false),
+        _ => write(&mut ops, cell, column_right(column - 1), y, true),
+      }
+    }
+  }
+  ops.push(Op::EndTextSection);
+  pages.push(ops);
+  pages
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::PathBuf;
+
+  use syntect::parsing::{ParseState, SyntaxSet};
+
+  use super::*;
+
+  #[test]
+  fn classifying_lines() {
+    let ss = SyntaxSet::load_defaults_newlines();
+    let syntax = ss.find_syntax_by_extension("rs").unwrap();
+    let mut parse_state = ParseState::new(syntax);
+    let mut classifier = LineClassifier::default();
+    let source = "// Comment\n\nfn main() { // Trailing comment\n  /* Block\n  comment */\n}\n";
+    let kinds = source
+      .split_inclusive('\n')
+      .map(|line| {
+        let ops = parse_state.parse_line(line, &ss).unwrap();
+        classifier.classify(line, &ops)
+      })
+      .collect::<Vec<_>>();
+    assert_eq!(
+      kinds,
+      vec![
+        LineKind::Comment,
+        LineKind::Blank,
+        LineKind::Code,
+        LineKind::Comment,
+        LineKind::Comment,
+        LineKind::Code,
+      ]
+    );
+  }
+  #[test]
+  fn grouping_languages() {
+    let files = [
+      ("a.rs", "Rust", 10),
+      ("b.rs", "Rust", 5),
+      ("c.py", "Python", 20),
+    ]
+    .into_iter()
+    .enumerate()
+    .map(|(index, (path, language, code))| {
+      let info = FileInfo {
+        path: PathBuf::from(path),
+        lines: code,
+        size: 1,
+        language: Some(language.to_string()),
+        line_counts: LineCounts {
+          code,
+          ..Default::default()
+        },
+      };
+      (index, info)
+    })
+    .collect();
+    let languages = language_stats(&files);
+    assert_eq!(languages.len(), 2);
+    assert_eq!(languages[0].language, "Python");
+    assert_eq!(languages[1].files, 2);
+    assert_eq!(languages[1].lines.code, 15);
+    let report = report(&files);
+    assert_eq!(report["files"][2]["language"], "Python");
+    assert_eq!(report["languages"][1]["code"], 15);
+  }
+}
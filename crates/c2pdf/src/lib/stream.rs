@@ -0,0 +1,636 @@
+//! Writes PDFs to disk a page at a time, so memory use doesn't grow with the number of pages
+//!
+//! [`printpdf`] needs every page in memory to save a document, so [`StreamWriter`] writes the PDF itself.
+//! Only the operations which this crate generates are supported, and the whole font is embedded,
+//! as the characters which are used aren't known until the end. Images are stored losslessly,
+//! so streamed PDFs are usually larger than saved ones
+
+use std::{
+  collections::{BTreeMap, HashSet},
+  fmt::Write as _,
+  io::{self, Write},
+  sync::{Condvar, Mutex},
+};
+
+use flate2::{Compression, write::ZlibEncoder};
+use fontdue::Font;
+use log::warn;
+use printpdf::{
+  Color, CurTransMat, LinePoint, Op, PaintMode, PdfPage, RawImage, RawImageData, RawImageFormat,
+  TextItem, XObject, XObjectId,
+};
+
+/// Object number of the catalog
+const CATALOG: usize = 1;
+/// Object number of the page tree
+const PAGES: usize = 2;
+/// Object number of the font
+const FONT: usize = 3;
+/// Name the font is embedded under
+const FONT_NAME: &str = "CodeToPdfFont";
+
+/// Writes a PDF to `out` one page at a time. [`StreamWriter::finish`] must be called once every page has been written
+pub struct StreamWriter<W: Write> {
+  out: W,
+  /// Number of bytes written so far
+  position: u64,
+  /// Byte offset of each object, indexed by object number (0 is unused)
+  offsets: Vec<u64>,
+  /// Object numbers of the pages written so far
+  page_ids: Vec<usize>,
+  font: Font,
+  font_bytes: Vec<u8>,
+  /// Character drawn by each glyph which has been used
+  glyphs: BTreeMap<u16, char>,
+  /// Name of each entry in the outline, and the page (starting at 1) it points to
+  bookmarks: Vec<(String, usize)>,
+  title: String,
+  /// Whether a warning has been logged for an unsupported operation
+  warned_unsupported: bool,
+}
+impl<W: Write> StreamWriter<W> {
+  /// Starts writing a PDF called `title` to `out`, which uses the font parsed from `font_bytes` as `font`
+  pub fn new(mut out: W, font: Font, font_bytes: &[u8], title: &str) -> io::Result<Self> {
+    let header = b"%PDF-1.7\n%\xE2\xE3\xCF\xD3\n";
+    out.write_all(header)?;
+    Ok(Self {
+      out,
+      position: header.len() as u64,
+      offsets: vec![0; FONT + 1],
+      page_ids: vec![],
+      font,
+      font_bytes: font_bytes.to_vec(),
+      glyphs: BTreeMap::new(),
+      bookmarks: vec![],
+      title: title.to_string(),
+      warned_unsupported: false,
+    })
+  }
+  /// Returns the number of pages written so far
+  pub fn page_count(&self) -> usize {
+    self.page_ids.len()
+  }
+  /// Adds an entry called `name` to the outline, which points to the given page (starting at 1)
+  pub fn add_bookmark(&mut self, name: &str, page: usize) {
+    self.bookmarks.push((name.to_string(), page));
+  }
+  /// Writes `page`, along with any of the images in `xobjects` which it uses
+  pub fn write_page(
+    &mut self,
+    page: &PdfPage,
+    xobjects: &BTreeMap<XObjectId, XObject>,
+  ) -> io::Result<()> {
+    let mut images = String::new();
+    let mut written = HashSet::new();
+    for id in page.get_xobject_ids() {
+      let Some(XObject::Image(image)) = xobjects.get(&id) else {
+        continue;
+      };
+      if written.insert(id.0.clone())
+        && let Some(image_id) = self.write_image(image)?
+      {
+        _ = write!(images, "/{} {image_id} 0 R", name(&id.0));
+      }
+    }
+    let content = self.content(&page.ops, xobjects);
+    let content_id = self.write_stream("", &content)?;
+    let page_id = self.next_id();
+    let body = format!(
+      "<</Type/Page/Parent {PAGES} 0 R/MediaBox[0 0 {} {}]/Resources<</Font<</F0 {FONT} 0 R>>/XObject<<{images}>>>>/Contents {content_id} 0 R>>",
+      num(page.media_box.width.0),
+      num(page.media_box.height.0),
+    );
+    self.write_object(page_id, body.as_bytes())?;
+    self.page_ids.push(page_id);
+    Ok(())
+  }
+  /// Writes the font, page tree, outline, and cross-reference table, then returns the underlying writer
+  pub fn finish(mut self) -> io::Result<W> {
+    self.write_font()?;
+    let kids = self
+      .page_ids
+      .iter()
+      .map(|id| format!("{id} 0 R"))
+      .collect::<Vec<_>>()
+      .join(" ");
+    let pages = format!("<</Type/Pages/Kids[{kids}]/Count {}>>", self.page_ids.len());
+    self.write_object(PAGES, pages.as_bytes())?;
+    let outline = match self.write_outline()? {
+      Some(outline) => format!("/Outlines {outline} 0 R/PageMode/UseOutlines"),
+      None => String::new(),
+    };
+    let info = self.next_id();
+    let body = format!(
+      "<</Title{}/Producer(code-to-pdf)>>",
+      text_string(&self.title)
+    );
+    self.write_object(info, body.as_bytes())?;
+    let catalog = format!("<</Type/Catalog/Pages {PAGES} 0 R{outline}>>");
+    self.write_object(CATALOG, catalog.as_bytes())?;
+
+    let xref_position = self.position;
+    let mut xref = format!("xref\n0 {}\n0000000000 65535 f \n", self.offsets.len());
+    for offset in &self.offsets[1..] {
+      _ = writeln!(xref, "{offset:010} 00000 n ");
+    }
+    _ = write!(
+      xref,
+      "trailer\n<</Size {}/Root {CATALOG} 0 R/Info {info} 0 R>>\nstartxref\n{xref_position}\n%%EOF\n",
+      self.offsets.len()
+    );
+    self.write(xref.as_bytes())?;
+    self.out.flush()?;
+    Ok(self.out)
+  }
+
+  /// Reserves the number of a new object
+  fn next_id(&mut self) -> usize {
+    self.offsets.push(0);
+    self.offsets.len() - 1
+  }
+  fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+    self.out.write_all(bytes)?;
+    self.position += bytes.len() as u64;
+    Ok(())
+  }
+  fn write_object(&mut self, id: usize, body: &[u8]) -> io::Result<()> {
+    self.offsets[id] = self.position;
+    self.write(format!("{id} 0 obj\n").as_bytes())?;
+    self.write(body)?;
+    self.write(b"\nendobj\n")
+  }
+  /// Writes a new stream object containing `data` (which is compressed), with the extra entries in `dict`
+  fn write_stream(&mut self, dict: &str, data: &[u8]) -> io::Result<usize> {
+    let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+    encoder.write_all(data)?;
+    let compressed = encoder.finish()?;
+    let id = self.next_id();
+    let mut body = format!(
+      "<<{dict}/Length {}/Filter/FlateDecode>>\nstream\n",
+      compressed.len()
+    )
+    .into_bytes();
+    body.extend(compressed);
+    body.extend(b"\nendstream");
+    self.write_object(id, &body)?;
+    Ok(id)
+  }
+  /// Writes `image` as an image object, returning its object number, or `None` if its format isn't supported
+  fn write_image(&mut self, image: &RawImage) -> io::Result<Option<usize>> {
+    let (channels, colour_channels, reversed) = match image.data_format {
+      RawImageFormat::R8 => (1, 1, false),
+      RawImageFormat::RG8 => (2, 1, false),
+      RawImageFormat::RGB8 => (3, 3, false),
+      RawImageFormat::RGBA8 => (4, 3, false),
+      RawImageFormat::BGR8 => (3, 3, true),
+      RawImageFormat::BGRA8 => (4, 3, true),
+      _ => {
+        warn!("Skipping an image, as its format isn't supported when streaming");
+        return Ok(None);
+      }
+    };
+    let RawImageData::U8(pixels) = &image.pixels else {
+      warn!("Skipping an image, as its format isn't supported when streaming");
+      return Ok(None);
+    };
+    let mut colour = Vec::with_capacity(image.width * image.height * colour_channels);
+    let mut alpha = vec![];
+    for pixel in pixels.chunks_exact(channels) {
+      match (colour_channels, reversed) {
+        (1, _) => colour.push(pixel[0]),
+        (_, true) => colour.extend([pixel[2], pixel[1], pixel[0]]),
+        (_, false) => colour.extend_from_slice(&pixel[..3]),
+      }
+      if channels % 2 == 0 {
+        alpha.push(pixel[channels - 1]);
+      }
+    }
+    let (width, height) = (image.width, image.height);
+    let smask = if alpha.iter().all(|&a| a == 255) {
+      String::new()
+    } else {
+      let dict = format!(
+        "/Type/XObject/Subtype/Image/Width {width}/Height {height}/ColorSpace/DeviceGray/BitsPerComponent 8"
+      );
+      format!("/SMask {} 0 R", self.write_stream(&dict, &alpha)?)
+    };
+    let colour_space = if colour_channels == 1 {
+      "DeviceGray"
+    } else {
+      "DeviceRGB"
+    };
+    let dict = format!(
+      "/Type/XObject/Subtype/Image/Width {width}/Height {height}/ColorSpace/{colour_space}/BitsPerComponent 8{smask}"
+    );
+    self.write_stream(&dict, &colour).map(Some)
+  }
+  /// Translates `ops` into the contents of a page
+  fn content(&mut self, ops: &[Op], xobjects: &BTreeMap<XObjectId, XObject>) -> Vec<u8> {
+    let mut content = String::new();
+    for op in ops {
+      match op {
+        Op::SaveGraphicsState => content.push_str("q\n"),
+        Op::RestoreGraphicsState => content.push_str("Q\n"),
+        Op::StartTextSection => content.push_str("BT\n"),
+        Op::EndTextSection => content.push_str("ET\n"),
+        Op::AddLineBreak => content.push_str("T*\n"),
+        Op::SetLineHeight { lh } => _ = writeln!(content, "{} TL", num(lh.0)),
+        Op::SetFontSize { size, .. } => _ = writeln!(content, "/F0 {} Tf", num(size.0)),
+        Op::SetTextCursor { pos } => _ = writeln!(content, "{} {} Td", num(pos.x.0), num(pos.y.0)),
+        Op::SetTextMatrix { matrix } => {
+          _ = writeln!(content, "{} Tm", matrix_string(matrix.as_array()))
+        }
+        Op::SetTransformationMatrix { matrix } => {
+          _ = writeln!(content, "{} cm", matrix_string(matrix.as_array()))
+        }
+        Op::SetTextRenderingMode { mode } => _ = writeln!(content, "{} Tr", mode.id()),
+        Op::SetCharacterSpacing { multiplier } => _ = writeln!(content, "{} Tc", num(*multiplier)),
+        Op::SetWordSpacing { pt } => _ = writeln!(content, "{} Tw", num(pt.0)),
+        Op::SetOutlineThickness { pt } => _ = writeln!(content, "{} w", num(pt.0)),
+        Op::SetFillColor { col } => _ = writeln!(content, "{}", colour_string(col, false)),
+        Op::SetOutlineColor { col } => _ = writeln!(content, "{}", colour_string(col, true)),
+        Op::WriteText { items, .. } => {
+          content.push('[');
+          for item in items {
+            match item {
+              TextItem::Text(text) => {
+                content.push('<');
+                for ch in text.chars() {
+                  let glyph = self.font.lookup_glyph_index(ch);
+                  if glyph != 0 {
+                    self.glyphs.entry(glyph).or_insert(ch);
+                  }
+                  _ = write!(content, "{glyph:04X}");
+                }
+                content.push('>');
+              }
+              TextItem::Offset(offset) => _ = write!(content, " {} ", num(*offset)),
+            }
+          }
+          content.push_str("] TJ\n");
+        }
+        Op::DrawLine { line } => {
+          path(&mut content, &line.points);
+          content.push_str(if line.is_closed { "h S\n" } else { "S\n" });
+        }
+        Op::DrawPolygon { polygon } => {
+          for ring in &polygon.rings {
+            path(&mut content, &ring.points);
+          }
+          let order = &polygon.winding_order;
+          let paint = match polygon.mode {
+            PaintMode::Clip => format!("{} n", order.get_clip_op()),
+            PaintMode::Fill => order.get_fill_op().to_string(),
+            PaintMode::Stroke => "S".to_string(),
+            PaintMode::FillStroke => order.get_fill_stroke_close_op().to_string(),
+          };
+          _ = writeln!(content, "h {paint}");
+        }
+        Op::UseXobject { id, transform } => {
+          let size = xobjects
+            .get(id)
+            .and_then(|xobject| xobject.get_width_height());
+          let matrix = transform
+            .get_ctms(size)
+            .iter()
+            .fold(CurTransMat::Identity.as_array(), |matrix, transform| {
+              CurTransMat::combine_matrix(matrix, transform.as_array())
+            });
+          _ = writeln!(
+            content,
+            "q {} cm /{} Do Q",
+            matrix_string(matrix),
+            name(&id.0)
+          );
+        }
+        _ => {
+          if !self.warned_unsupported {
+            warn!("Skipping an operation which isn't supported when streaming: {op:?}");
+            self.warned_unsupported = true;
+          }
+        }
+      }
+    }
+    content.into_bytes()
+  }
+  /// Writes the font, with the widths of (and characters drawn by) every glyph which has been used
+  fn write_font(&mut self) -> io::Result<()> {
+    // OpenType fonts with PostScript outlines are embedded differently to TrueType fonts
+    let postscript = self.font_bytes.starts_with(b"OTTO");
+    let font_bytes = std::mem::take(&mut self.font_bytes);
+    let font_file = if postscript {
+      self.write_stream("/Subtype/OpenType", &font_bytes)?
+    } else {
+      self.write_stream(&format!("/Length1 {}", font_bytes.len()), &font_bytes)?
+    };
+    let (ascent, descent) = self
+      .font
+      .horizontal_line_metrics(1000.0)
+      .map_or((800.0, -200.0), |metrics| (metrics.ascent, metrics.descent));
+    let (ascent, descent) = (num(ascent), num(descent));
+    let descriptor = self.next_id();
+    let body = format!(
+      "<</Type/FontDescriptor/FontName/{FONT_NAME}/Flags 32/FontBBox[0 {descent} 1000 {ascent}]/ItalicAngle 0/Ascent {ascent}/Descent {descent}/CapHeight {ascent}/StemV 80/FontFile{} {font_file} 0 R>>",
+      if postscript { "3" } else { "2" }
+    );
+    self.write_object(descriptor, body.as_bytes())?;
+
+    let widths = self
+      .glyphs
+      .keys()
+      .map(|&glyph| {
+        let width = self.font.metrics_indexed(glyph, 1000.0).advance_width;
+        format!("{glyph}[{}]", num(width))
+      })
+      .collect::<String>();
+    let cid_font = self.next_id();
+    let body = format!(
+      "<</Type/Font/Subtype/{}/BaseFont/{FONT_NAME}/CIDSystemInfo<</Registry(Adobe)/Ordering(Identity)/Supplement 0>>/FontDescriptor {descriptor} 0 R{}/W[{widths}]>>",
+      if postscript {
+        "CIDFontType0"
+      } else {
+        "CIDFontType2"
+      },
+      if postscript {
+        ""
+      } else {
+        "/CIDToGIDMap/Identity"
+      },
+    );
+    self.write_object(cid_font, body.as_bytes())?;
+
+    let to_unicode = self.write_stream("", to_unicode(&self.glyphs).as_bytes())?;
+    let body = format!(
+      "<</Type/Font/Subtype/Type0/BaseFont/{FONT_NAME}/Encoding/Identity-H/DescendantFonts[{cid_font} 0 R]/ToUnicode {to_unicode} 0 R>>"
+    );
+    self.write_object(FONT, body.as_bytes())
+  }
+  /// Writes the outline, returning its object number if it has any entries
+  fn write_outline(&mut self) -> io::Result<Option<usize>> {
+    let bookmarks = std::mem::take(&mut self.bookmarks)
+      .into_iter()
+      .filter_map(|(name, page)| Some((name, *self.page_ids.get(page.checked_sub(1)?)?)))
+      .collect::<Vec<_>>();
+    if bookmarks.is_empty() {
+      return Ok(None);
+    }
+    let outline = self.next_id();
+    let ids = bookmarks.iter().map(|_| self.next_id()).collect::<Vec<_>>();
+    for (i, (name, page_id)) in bookmarks.iter().enumerate() {
+      let mut body = format!("<</Title{}/Parent {outline} 0 R", text_string(name));
+      if i > 0 {
+        _ = write!(body, "/Prev {} 0 R", ids[i - 1]);
+      }
+      if let Some(next) = ids.get(i + 1) {
+        _ = write!(body, "/Next {next} 0 R");
+      }
+      _ = write!(body, "/Dest[{page_id} 0 R/Fit]>>");
+      self.write_object(ids[i], body.as_bytes())?;
+    }
+    let body = format!(
+      "<</Type/Outlines/First {} 0 R/Last {} 0 R/Count {}>>",
+      ids[0],
+      ids[ids.len() - 1],
+      ids.len()
+    );
+    self.write_object(outline, body.as_bytes())?;
+    Ok(Some(outline))
+  }
+}
+
+/// Formats a number for a PDF, with at most 3 decimal places
+fn num(n: f32) -> String {
+  let formatted = format!("{n:.3}");
+  let trimmed = formatted.trim_end_matches('0').trim_end_matches('.');
+  match trimmed {
+    "-0" | "" => "0".to_string(),
+    trimmed => trimmed.to_string(),
+  }
+}
+fn matrix_string(matrix: [f32; 6]) -> String {
+  matrix.map(num).join(" ")
+}
+/// Returns the operator which sets the fill (or stroke, if `stroke` is set) colour to `colour`
+fn colour_string(colour: &Color, stroke: bool) -> String {
+  let operator = match colour {
+    Color::Rgb(_) => "rg",
+    Color::Cmyk(_) | Color::SpotColor(_) => "k",
+    Color::Greyscale(_) => "g",
+  };
+  let operator = match stroke {
+    true => operator.to_uppercase(),
+    false => operator.to_string(),
+  };
+  let components = colour.into_vec().into_iter().map(num).collect::<Vec<_>>();
+  format!("{} {operator}", components.join(" "))
+}
+/// Adds a path through `points` to `content`
+fn path(content: &mut String, points: &[LinePoint]) {
+  let Some(first) = points.first() else {
+    return;
+  };
+  _ = writeln!(content, "{} {} m", num(first.p.x.0), num(first.p.y.0));
+  let mut i = 1;
+  while i < points.len() {
+    // Two control points followed by the end point make a curve
+    if points[i].bezier && i + 2 < points.len() && points[i + 1].bezier {
+      let coordinates = points[i..i + 3]
+        .iter()
+        .map(|point| format!("{} {}", num(point.p.x.0), num(point.p.y.0)))
+        .collect::<Vec<_>>();
+      _ = writeln!(content, "{} c", coordinates.join(" "));
+      i += 3;
+    } else {
+      _ = writeln!(
+        content,
+        "{} {} l",
+        num(points[i].p.x.0),
+        num(points[i].p.y.0)
+      );
+      i += 1;
+    }
+  }
+}
+/// Escapes `s` to be used as a PDF name
+fn name(s: &str) -> String {
+  s.bytes()
+    .map(|byte| match byte {
+      b'!'..=b'~' if !b"#%()/<>[]{}".contains(&byte) => (byte as char).to_string(),
+      _ => format!("#{byte:02X}"),
+    })
+    .collect()
+}
+/// Encodes `s` as a PDF text string, in UTF-16
+fn text_string(s: &str) -> String {
+  let mut encoded = String::from("<FEFF");
+  for unit in s.encode_utf16() {
+    _ = write!(encoded, "{unit:04X}");
+  }
+  encoded.push('>');
+  encoded
+}
+/// Generates a CMap mapping each glyph to the character it draws, so text can be copied and extracted
+fn to_unicode(glyphs: &BTreeMap<u16, char>) -> String {
+  let mut cmap = String::from(
+    "/CIDInit /ProcSet findresource begin\n12 dict begin\nbegincmap\n/CIDSystemInfo <</Registry (Adobe) /Ordering (UCS) /Supplement 0>> def\n/CMapName /Adobe-Identity-UCS def\n/CMapType 2 def\n1 begincodespacerange\n<0000> <FFFF>\nendcodespacerange\n",
+  );
+  let glyphs = glyphs.iter().collect::<Vec<_>>();
+  // Each block can contain at most 100 entries
+  for block in glyphs.chunks(100) {
+    _ = writeln!(cmap, "{} beginbfchar", block.len());
+    for (glyph, ch) in block {
+      let mut units = [0; 2];
+      let units = ch
+        .encode_utf16(&mut units)
+        .iter()
+        .map(|unit| format!("{unit:04X}"))
+        .collect::<String>();
+      _ = writeln!(cmap, "<{glyph:04X}> <{units}>");
+    }
+    cmap.push_str("endbfchar\n");
+  }
+  cmap.push_str("endcmap\nCMapName currentdict /CMap defineresource pop\nend\nend\n");
+  cmap
+}
+
+/// Holds items (such as the pages of each file) which are generated out of order, so they can be taken in order.
+///
+/// Producers wait before starting an item which is too far ahead of the next one to be taken,
+/// so at most `capacity` items are being generated or waiting at once
+pub struct ReorderBuffer<T> {
+  state: Mutex<ReorderState<T>>,
+  changed: Condvar,
+  capacity: usize,
+}
+struct ReorderState<T> {
+  /// Index of the next item to be taken
+  next: usize,
+  items: BTreeMap<usize, T>,
+  /// Whether every item has been inserted
+  closed: bool,
+}
+impl<T> ReorderBuffer<T> {
+  /// Initialises an empty [`ReorderBuffer`], which holds at most `capacity` items
+  pub fn new(capacity: usize) -> Self {
+    Self {
+      state: Mutex::new(ReorderState {
+        next: 0,
+        items: BTreeMap::new(),
+        closed: false,
+      }),
+      changed: Condvar::new(),
+      capacity: capacity.max(1),
+    }
+  }
+  /// Waits until the item at `index` can be started
+  pub fn wait_for_space(&self, index: usize) {
+    let mut state = self.state.lock().unwrap();
+    while index >= state.next + self.capacity && !state.closed {
+      state = self.changed.wait(state).unwrap();
+    }
+  }
+  /// Adds the item at `index`. Every index must be inserted (or the buffer closed) for later items to be taken
+  pub fn insert(&self, index: usize, item: T) {
+    self.state.lock().unwrap().items.insert(index, item);
+    self.changed.notify_all();
+  }
+  /// Takes the next item in order, waiting until it's inserted.
+  /// Returns `None` once the buffer has been closed and every item has been taken
+  pub fn take(&self) -> Option<T> {
+    let mut state = self.state.lock().unwrap();
+    loop {
+      let next = state.next;
+      if let Some(item) = state.items.remove(&next) {
+        state.next += 1;
+        self.changed.notify_all();
+        return Some(item);
+      }
+      if state.closed {
+        // Skip over any items which were never inserted
+        state.next = *state.items.keys().next()?;
+        continue;
+      }
+      state = self.changed.wait(state).unwrap();
+    }
+  }
+  /// Marks that no more items will be inserted
+  pub fn close(&self) {
+    self.state.lock().unwrap().closed = true;
+    self.changed.notify_all();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{sync::Arc, thread};
+
+  use printpdf::{Mm, Pt, TextMatrix};
+
+  use super::*;
+  use crate::font_loader::load_font;
+
+  #[test]
+  fn reordering() {
+    let buffer = Arc::new(ReorderBuffer::new(2));
+    let producer = {
+      let buffer = buffer.clone();
+      thread::spawn(move || {
+        for index in [1, 0, 3, 2, 4] {
+          buffer.wait_for_space(index);
+          buffer.insert(index, index);
+        }
+        buffer.close();
+      })
+    };
+    let taken = std::iter::from_fn(|| buffer.take()).collect::<Vec<_>>();
+    producer.join().unwrap();
+    assert_eq!(taken, vec![0, 1, 2, 3, 4]);
+  }
+  #[test]
+  fn writing_pages() {
+    let (font_bytes, _) = load_font(None);
+    let font = Font::from_bytes(font_bytes.as_slice(), fontdue::FontSettings::default()).unwrap();
+    let mut writer = StreamWriter::new(vec![], font, &font_bytes, "Test").unwrap();
+    let ops = vec![
+      Op::StartTextSection,
+      Op::SetTextMatrix {
+        matrix: TextMatrix::Translate(Pt(10.0), Pt(10.0)),
+      },
+      Op::WriteText {
+        items: vec![TextItem::Text("fn main() {}".to_string())],
+        font: printpdf::FontId::new(),
+      },
+      Op::EndTextSection,
+    ];
+    for _ in 0..2 {
+      let page = PdfPage::new(Mm(210.0), Mm(297.0), ops.clone());
+      writer.write_page(&page, &BTreeMap::new()).unwrap();
+    }
+    writer.add_bookmark("main.rs", 1);
+    let pdf = writer.finish().unwrap();
+    assert!(pdf.starts_with(b"%PDF-1.7"));
+    // Every object in the cross-reference table is where it says it is
+    let trailer = String::from_utf8_lossy(&pdf[pdf.len() - 64..]);
+    let xref_position = trailer
+      .lines()
+      .skip_while(|line| *line != "startxref")
+      .nth(1)
+      .and_then(|line| line.parse::<usize>().ok())
+      .unwrap();
+    let xref = String::from_utf8_lossy(&pdf[xref_position..]);
+    assert!(xref.starts_with("xref\n"));
+    let entries = xref
+      .lines()
+      .skip(3)
+      .take_while(|line| line.ends_with(" n "))
+      .collect::<Vec<_>>();
+    assert!(!entries.is_empty());
+    for (id, line) in entries.iter().enumerate() {
+      let offset = line[..10].parse::<usize>().unwrap();
+      assert!(pdf[offset..].starts_with(format!("{} 0 obj", id + 1).as_bytes()));
+    }
+    let text = String::from_utf8_lossy(&pdf);
+    assert!(text.contains("/Count 2"));
+    assert!(text.contains("/Type/Outlines"));
+  }
+}
@@ -1,9 +1,9 @@
 //! Primitives for wrapping text
 
-use std::collections::HashMap;
+use std::{borrow::Cow, collections::HashMap};
 
 use fontdue::{Font, FontSettings};
-use printpdf::Pt;
+use printpdf::{Pt, TextItem};
 
 /// Uses the [`fontdue`] text rasterizer to split text into lines less than the `max_width`
 pub fn split_into_lines_fontdue<F: Fn(usize) -> Pt>(
@@ -12,6 +12,23 @@ pub fn split_into_lines_fontdue<F: Fn(usize) -> Pt>(
   font_size: f32,
   max_width: F,
   cache: &mut std::collections::HashMap<char, f32>,
+) -> Vec<(String, f32)> {
+  split_into_lines_by(
+    txt,
+    |ch| advance_width(font, font_size, ch, cache),
+    max_width,
+  )
+}
+
+/// Lines are split at whitespace within this many points of the maximum width, so lines that get close to it
+/// wrap at a word boundary
+pub const WRAP_SLACK: Pt = Pt(30.0);
+
+/// Splits text into lines less than the `max_width`, using `char_width` to measure each character
+pub fn split_into_lines_by<W: FnMut(char) -> f32, F: Fn(usize) -> Pt>(
+  txt: &str,
+  mut char_width: W,
+  max_width: F,
 ) -> Vec<(String, f32)> {
   let mut lines: Vec<(String, f32)> = vec![];
   let mut line_buf = String::new();
@@ -19,17 +36,10 @@ pub fn split_into_lines_fontdue<F: Fn(usize) -> Pt>(
   // Stores the max line width for the current line (may be different depending on what line we're on)
   let mut max_line_width = max_width(0).0;
   for ch in txt.chars() {
-    let width = match cache.get(&ch) {
-      Some(w) => *w,
-      None => {
-        let width = font.rasterize(ch, font_size).0.advance_width;
-        cache.insert(ch, width);
-        width
-      }
-    };
+    let width = char_width(ch);
     // Move onto new line if width exceeds maximum, or if we're close to the maximum and find a space
     if (current_line_width + width >= max_line_width)
-      || ((max_line_width - (current_line_width + width) < 30.0) && ch.is_whitespace())
+      || ((max_line_width - (current_line_width + width) < WRAP_SLACK.0) && ch.is_whitespace())
     {
       // Push this character so we know that the new line was due to line splitting
       line_buf.push('\n');
@@ -46,12 +56,41 @@ pub fn split_into_lines_fontdue<F: Fn(usize) -> Pt>(
   lines
 }
 
+/// Returns the advance width of `ch`, caching the result
+fn advance_width(font: &Font, font_size: f32, ch: char, cache: &mut HashMap<char, f32>) -> f32 {
+  match cache.get(&ch) {
+    Some(w) => *w,
+    None => {
+      let width = font.rasterize(ch, font_size).0.advance_width;
+      cache.insert(ch, width);
+      width
+    }
+  }
+}
+
+/// Number of grid cells between tab stops
+pub const TAB_WIDTH: usize = 4;
+
+/// How characters are positioned horizontally along a line
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum GridMode {
+  /// Each character takes up its own advance width
+  #[default]
+  Proportional,
+  /// Every character is placed on a grid, with the cell width detected from the font
+  Detect,
+  /// Every character is placed on a grid with a fixed cell width (in points)
+  Fixed(f32),
+}
+
 /// Handles wrapping text into multiple lines
 #[derive(Clone)]
 pub struct TextWrapper {
   rasterize_cache: HashMap<char, f32>,
   font: Font,
   font_size: f32,
+  // Width of each cell when laying characters out on a monospace grid
+  cell_width: Option<f32>,
 }
 
 impl TextWrapper {
@@ -61,6 +100,51 @@ impl TextWrapper {
       rasterize_cache: HashMap::new(),
       font: Font::from_bytes(font_bytes, FontSettings::default()).unwrap(),
       font_size,
+      cell_width: None,
+    }
+  }
+
+  /// Sets how characters are positioned along a line
+  pub fn with_grid(mut self, mode: GridMode) -> Self {
+    self.cell_width = match mode {
+      GridMode::Proportional => None,
+      // Use the widest printable ASCII character so that no glyphs overlap
+      GridMode::Detect => Some(
+        (b'!'..=b'~')
+          .map(|ch| self.advance_width(ch as char))
+          .fold(0.0, f32::max),
+      ),
+      GridMode::Fixed(width) => Some(width),
+    };
+    self
+  }
+
+  /// Returns the width of each grid cell in Point, if characters are being laid out on a grid
+  pub fn cell_width(&self) -> Option<Pt> {
+    self.cell_width.map(Pt)
+  }
+
+  /// Returns `true` if every printable ASCII character in the font has the same advance width
+  pub fn is_monospace(&mut self) -> bool {
+    let first = self.advance_width('!');
+    (b'!'..=b'~').all(|ch| (self.advance_width(ch as char) - first).abs() < 0.01)
+  }
+
+  /// Returns `true` if the font has a glyph for every character in `txt`
+  pub fn has_glyphs(&self, txt: &str) -> bool {
+    txt.chars().all(|ch| self.font.lookup_glyph_index(ch) != 0)
+  }
+
+  /// Returns the advance width of a character in the font, ignoring any grid
+  fn advance_width(&mut self, ch: char) -> f32 {
+    advance_width(&self.font, self.font_size, ch, &mut self.rasterize_cache)
+  }
+
+  /// Returns the width a character takes up on a line (the cell width, if laying out on a grid)
+  fn char_width(&mut self, ch: char) -> f32 {
+    match self.cell_width {
+      Some(width) => width,
+      None => self.advance_width(ch),
     }
   }
 
@@ -70,35 +154,100 @@ impl TextWrapper {
     txt: &str,
     max_width: T,
   ) -> Vec<(String, f32)> {
-    split_into_lines_fontdue(
-      txt,
-      &self.font,
-      self.font_size,
-      max_width,
-      &mut self.rasterize_cache,
-    )
+    split_into_lines_by(txt, |ch| self.char_width(ch), max_width)
   }
 
   /// Returns the width of a given string in Point
   pub fn get_width(&mut self, txt: &str) -> Pt {
-    let mut total_width = 0.0;
+    Pt(txt.chars().map(|ch| self.char_width(ch)).sum())
+  }
+
+  /// Expands each tab in `txt` into spaces up to the next tab stop (every [`TAB_WIDTH`] cells, counting from the
+  /// start of each line) when laying out on a grid, so that tab-indented lines stay on the grid.
+  /// Text is returned unchanged otherwise
+  pub fn expand_tabs<'a>(&self, txt: &'a str) -> Cow<'a, str> {
+    if self.cell_width.is_none() || !txt.contains('\t') {
+      return Cow::Borrowed(txt);
+    }
+    let mut expanded = String::with_capacity(txt.len());
+    let mut column = 0;
     for ch in txt.chars() {
-      let char_width = match self.rasterize_cache.get(&ch) {
-        Some(w) => *w,
-        None => {
-          let width = self.font.rasterize(ch, self.font_size).0.advance_width;
-          self.rasterize_cache.insert(ch, width);
-          width
+      match ch {
+        '\t' => {
+          let spaces = TAB_WIDTH - column % TAB_WIDTH;
+          expanded.extend(std::iter::repeat_n(' ', spaces));
+          column += spaces;
         }
+        '\n' => {
+          expanded.push(ch);
+          column = 0;
+        }
+        _ => {
+          expanded.push(ch);
+          column += 1;
+        }
+      }
+    }
+    Cow::Owned(expanded)
+  }
+
+  /// Converts text into the [`TextItem`]s needed to write it.
+  ///
+  /// When laying out on a grid, characters narrower or wider than the cell are followed by an offset
+  /// so that the next character starts at the beginning of the next cell
+  pub fn text_items(&mut self, txt: &str) -> Vec<TextItem> {
+    let Some(cell_width) = self.cell_width else {
+      return vec![TextItem::Text(txt.to_string())];
+    };
+    let mut items = vec![];
+    let mut run = String::new();
+    // Correction for the previous character, only applied once another visible character follows
+    let mut pending_correction = 0.0;
+    for ch in txt.chars() {
+      if ch.is_control() {
+        run.push(ch);
+        continue;
+      }
+      if pending_correction != 0.0 {
+        items.push(TextItem::Text(std::mem::take(&mut run)));
+        // Offsets are in thousandths of an em, and positive offsets move the cursor to the left
+        items.push(TextItem::Offset(
+          pending_correction * 1000.0 / self.font_size,
+        ));
+      }
+      run.push(ch);
+      let correction = self.advance_width(ch) - cell_width;
+      pending_correction = if correction.abs() > 0.001 {
+        correction
+      } else {
+        0.0
       };
-      total_width += char_width;
     }
-    Pt(total_width)
+    if !run.is_empty() {
+      items.push(TextItem::Text(run));
+    }
+    items
+  }
+  /// Returns the font used to measure text
+  pub fn font(&self) -> &Font {
+    &self.font
   }
   /// Returns the set `font_size`
   pub fn font_size(&self) -> f32 {
     self.font_size
   }
+  /// Changes the font size, scaling the grid cell width (if any) to match
+  pub fn set_font_size(&mut self, font_size: f32) {
+    if font_size == self.font_size {
+      return;
+    }
+    self.cell_width = self
+      .cell_width
+      .map(|width| width * font_size / self.font_size);
+    self.font_size = font_size;
+    // Cached widths are for the previous size
+    self.rasterize_cache.clear();
+  }
 }
 
 #[cfg(test)]
@@ -106,6 +255,7 @@ mod tests {
   use super::*;
 
   const FONT_BYTES: &[u8] = include_bytes!("../../fonts/Helvetica.ttf") as &[u8];
+  const MONOSPACE_FONT_BYTES: &[u8] = include_bytes!("../../fonts/DejaVuSansMono.ttf") as &[u8];
   const TEXT: &str = "Hello World!! This is a vaguely long string to test string splitting!";
   #[test]
   fn splitting_lines() {
@@ -128,4 +278,49 @@ mod tests {
       TEXT.replace(' ', "")
     );
   }
+  #[test]
+  fn grid_layout() {
+    let mut wrapper = TextWrapper::new(FONT_BYTES, 10.0);
+    assert!(!wrapper.is_monospace());
+    let mut wrapper = wrapper.with_grid(GridMode::Fixed(6.0));
+    assert_eq!(wrapper.get_width("abc"), Pt(18.0));
+    // Each line should contain exactly as many characters as fit into the grid
+    let lines = wrapper.split_into_lines("abcdefghij", |_| Pt(31.0));
+    assert_eq!(lines[0].0.trim_end(), "abcde");
+    // Every character in Helvetica is narrower or wider than 6pt, so each needs an offset
+    assert_eq!(wrapper.text_items("ab").len(), 3);
+  }
+  #[test]
+  fn expanding_tabs() {
+    let mut wrapper = TextWrapper::new(FONT_BYTES, 10.0);
+    // Tabs are left alone unless laying out on a grid
+    assert_eq!(wrapper.expand_tabs("\tx"), "\tx");
+    wrapper = wrapper.with_grid(GridMode::Fixed(6.0));
+    assert_eq!(
+      wrapper.expand_tabs("\tif x {\n\t\ty;"),
+      "    if x {\n        y;"
+    );
+    assert_eq!(wrapper.expand_tabs("ab\tc"), "ab  c");
+    // A tab-indented line takes up whole cells, so the text after it stays on the grid
+    let line = wrapper.expand_tabs("\tx = 1;");
+    assert_eq!(wrapper.get_width(&line), Pt(6.0 * 10.0));
+  }
+  #[test]
+  fn monospace_font() {
+    let mut wrapper = TextWrapper::new(MONOSPACE_FONT_BYTES, 10.0);
+    assert!(wrapper.is_monospace());
+    // A monospace font's own advance widths already match the detected grid
+    let mut wrapper = wrapper.with_grid(GridMode::Detect);
+    assert_eq!(wrapper.text_items("fn main() {}").len(), 1);
+  }
+  #[test]
+  fn changing_font_size() {
+    let mut wrapper = TextWrapper::new(FONT_BYTES, 10.0);
+    let width = wrapper.get_width(TEXT);
+    wrapper.set_font_size(5.0);
+    assert!((wrapper.get_width(TEXT).0 - width.0 / 2.0).abs() < 0.5);
+    let mut wrapper = wrapper.with_grid(GridMode::Fixed(6.0));
+    wrapper.set_font_size(10.0);
+    assert_eq!(wrapper.cell_width(), Some(Pt(12.0)));
+  }
 }
@@ -0,0 +1,274 @@
+//! Contains [`Tree`], an overview of every file in the document in the style of `tree`
+
+use std::{
+  collections::{BTreeMap, HashMap},
+  path::{Path, PathBuf},
+};
+
+use printpdf::{FontId, Op, Point, Pt, TextMatrix};
+
+use crate::{
+  code_to_pdf::FileInfo,
+  dimensions::Dimensions,
+  helpers::{format_size, init_page},
+  text_manipulation::TextWrapper,
+};
+
+/// Title at the top of the first page of the tree
+const TITLE: &str = "Contents";
+
+/// Pieces drawn in front of each entry to show the structure of the tree
+struct Branches {
+  /// In front of an entry which has siblings after it
+  middle: &'static str,
+  /// In front of the last entry in a directory
+  last: &'static str,
+  /// In front of the descendants of an entry which has siblings after it
+  through: &'static str,
+}
+const BOX_DRAWING: Branches = Branches {
+  middle: "├── ",
+  last: "└── ",
+  through: "│   ",
+};
+/// Used if the font doesn't have box-drawing characters
+const ASCII: Branches = Branches {
+  middle: "|-- ",
+  last: "`-- ",
+  through: "|   ",
+};
+
+/// A directory or file in the tree
+#[derive(Default)]
+struct Node {
+  name: String,
+  children: Vec<Node>,
+  /// Index of the file from the walker, if this is a file
+  file: Option<usize>,
+}
+
+/// A single line of the tree
+struct Line {
+  /// Whether each ancestor (then the entry itself) is the last entry in its directory
+  last: Vec<bool>,
+  name: String,
+  /// Index of the file from the walker, if this is a file
+  file: Option<usize>,
+}
+
+/// Overview of every file in the document, with their sizes and page numbers
+pub struct Tree {
+  lines: Vec<Line>,
+}
+impl Tree {
+  /// Builds the tree of `files`, keeping them in the order from the walker
+  pub fn new(files: &BTreeMap<usize, FileInfo>) -> Self {
+    let root = common_ancestor(files.values().map(|file| file.path.as_path()));
+    let mut root_node = Node {
+      name: if root.as_os_str().is_empty() {
+        ".".to_string()
+      } else {
+        root.display().to_string()
+      },
+      ..Default::default()
+    };
+    for (index, file) in files {
+      let mut node = &mut root_node;
+      for component in file.path.strip_prefix(&root).unwrap_or(&file.path) {
+        let name = component.to_string_lossy().into_owned();
+        let position = match node.children.iter().position(|child| child.name == name) {
+          Some(position) => position,
+          None => {
+            node.children.push(Node {
+              name,
+              ..Default::default()
+            });
+            node.children.len() - 1
+          }
+        };
+        node = &mut node.children[position];
+      }
+      node.file = Some(*index);
+    }
+    let mut lines = vec![];
+    flatten(&root_node, &mut vec![], &mut lines);
+    Self { lines }
+  }
+  /// Returns the number of pages needed for the tree, with `max_line_count` lines on each page
+  pub fn page_count(&self, max_line_count: u32) -> usize {
+    // The title and a blank line take up the start of the first page
+    (self.lines.len() + 2).div_ceil(max_line_count.max(1) as usize)
+  }
+  /// Writes the tree, returning the contents of each page.
+  ///
+  /// `file_pages` maps the walker index of each file to the page it starts on
+  pub fn render(
+    &self,
+    page_dimensions: &Dimensions,
+    font_id: &FontId,
+    wrapper: &mut TextWrapper,
+    files: &BTreeMap<usize, FileInfo>,
+    file_pages: &HashMap<usize, usize>,
+  ) -> Vec<Vec<Op>> {
+    let font_size = wrapper.font_size();
+    let line_height = Pt(font_size * 1.2);
+    let max_line_count = page_dimensions.max_line_count(font_size).max(1) as usize;
+    let has_box_drawing = [BOX_DRAWING.middle, BOX_DRAWING.last, BOX_DRAWING.through]
+      .iter()
+      .all(|branch| wrapper.has_glyphs(branch));
+    let branches = if has_box_drawing { BOX_DRAWING } else { ASCII };
+    // Every level is indented by the same amount, so that the branches line up with proportional fonts
+    let indent = [branches.middle, branches.last, branches.through]
+      .iter()
+      .map(|branch| wrapper.get_width(branch))
+      .max()
+      .unwrap_or_default();
+    let left = page_dimensions.margin_left.into_pt();
+    let right = (page_dimensions.width - page_dimensions.margin_right).into_pt();
+    // Sizes are right-aligned, leaving room for the page numbers after them
+    let sizes_right = right - wrapper.get_width("00000") - Pt(font_size);
+
+    let mut pages = vec![];
+    let mut ops = vec![];
+    let write = |ops: &mut Vec<Op>, wrapper: &mut TextWrapper, text: &str, x: Pt, y: Pt| {
+      ops.extend_from_slice(&[
+        Op::SetTextMatrix {
+          matrix: TextMatrix::Translate(Pt(0.0), Pt(0.0)),
+        },
+        Op::SetTextCursor {
+          pos: Point { x, y },
+        },
+        Op::WriteText {
+          items: wrapper.text_items(text),
+          font: font_id.clone(),
+        },
+      ]);
+    };
+    // The title and a blank line come before the first line of the tree
+    let rows = [None, None]
+      .into_iter()
+      .chain(self.lines.iter().map(Some))
+      .enumerate();
+    for (row, line) in rows {
+      let row_on_page = row % max_line_count;
+      if row_on_page == 0 {
+        if !ops.is_empty() {
+          ops.push(Op::EndTextSection);
+          pages.push(std::mem::take(&mut ops));
+        }
+        init_page(&mut ops, page_dimensions, font_id.clone(), font_size);
+      }
+      let y = page_dimensions.body_top().into_pt() - line_height * row_on_page as f32;
+      let Some(line) = line else {
+        if row == 0 {
+          write(&mut ops, wrapper, TITLE, left, y);
+        }
+        continue;
+      };
+      // Branches for each ancestor, then the entry itself
+      let depth = line.last.len();
+      for (level, is_last) in line.last.iter().enumerate() {
+        let branch = match (level + 1 == depth, is_last) {
+          (true, true) => branches.last,
+          (true, false) => branches.middle,
+          (false, true) => continue,
+          (false, false) => branches.through,
+        };
+        write(&mut ops, wrapper, branch, left + indent * level as f32, y);
+      }
+      let name = match line.file {
+        Some(_) => line.name.clone(),
+        None => format!("{}/", line.name),
+      };
+      write(&mut ops, wrapper, &name, left + indent * depth as f32, y);
+      let Some(index) = line.file else {
+        continue;
+      };
+      if let Some(file) = files.get(&index) {
+        let size = format_size(file.size);
+        let x = sizes_right - wrapper.get_width(&size);
+        write(&mut ops, wrapper, &size, x, y);
+      }
+      if let Some(page) = file_pages.get(&index) {
+        let page = page.to_string();
+        let x = right - wrapper.get_width(&page);
+        write(&mut ops, wrapper, &page, x, y);
+      }
+    }
+    ops.push(Op::EndTextSection);
+    pages.push(ops);
+    pages
+  }
+}
+
+/// Adds lines for `node` and its descendants to `lines`
+fn flatten(node: &Node, last: &mut Vec<bool>, lines: &mut Vec<Line>) {
+  lines.push(Line {
+    last: last.clone(),
+    name: node.name.clone(),
+    file: node.file,
+  });
+  for (i, child) in node.children.iter().enumerate() {
+    last.push(i + 1 == node.children.len());
+    flatten(child, last, lines);
+    last.pop();
+  }
+}
+
+/// Returns the deepest directory containing all of `paths`
+fn common_ancestor<'a>(mut paths: impl Iterator<Item = &'a Path>) -> PathBuf {
+  let Some(first) = paths.next() else {
+    return PathBuf::new();
+  };
+  let mut ancestor = first.parent().unwrap_or(Path::new("")).to_path_buf();
+  for path in paths {
+    while !path.starts_with(&ancestor) {
+      if !ancestor.pop() {
+        break;
+      }
+    }
+  }
+  ancestor
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn building_tree() {
+    let files = ["src/main.rs", "src/lib/a.rs", "src/lib/b.rs", "README.md"]
+      .into_iter()
+      .enumerate()
+      .map(|(index, path)| {
+        let info = FileInfo {
+          path: Path::new("project").join(path),
+          lines: 1,
+          size: 1,
+          language: None,
+          line_counts: Default::default(),
+        };
+        (index, info)
+      })
+      .collect();
+    let tree = Tree::new(&files);
+    let lines = tree
+      .lines
+      .iter()
+      .map(|line| (line.name.as_str(), line.last.clone()))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      lines,
+      vec![
+        ("project", vec![]),
+        ("src", vec![false]),
+        ("main.rs", vec![false, false]),
+        ("lib", vec![false, true]),
+        ("a.rs", vec![false, true, false]),
+        ("b.rs", vec![false, true, true]),
+        ("README.md", vec![true]),
+      ]
+    );
+    assert_eq!(tree.page_count(5), 2);
+  }
+}